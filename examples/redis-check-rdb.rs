@@ -0,0 +1,208 @@
+//! Checks an RDB file without starting a server, like `redis-check-rdb`:
+//! walks every section, verifies the checksum, and reports what it found
+//! along with the offset of the first corrupt byte.
+//!
+//! Run it with `cargo run --example redis-check-rdb -- dump.rdb`. It is an
+//! example rather than a binary, so `cargo run` still starts the server.
+
+use redis_starter_rust::db::{
+    file::{RedisFile, Section},
+    RdbError,
+};
+use std::{collections::BTreeMap, env, path::Path, process::ExitCode};
+
+/// What was found in a single database.
+#[derive(Debug, Default, PartialEq)]
+struct DbStats {
+    /// Sizes announced by the resize hint, if any.
+    hint: Option<(usize, usize)>,
+    keys: usize,
+    expires: usize,
+    already_expired: usize,
+    types: BTreeMap<&'static str, usize>,
+}
+
+/// Everything the check learnt about a file, up to its first error.
+#[derive(Debug, Default)]
+struct Report {
+    version: u32,
+    aux: Vec<(String, String)>,
+    dbs: BTreeMap<usize, DbStats>,
+    /// Where each section starts, and what it was.
+    log: Vec<(usize, String)>,
+    error: Option<RdbError>,
+}
+
+fn check(path: &Path) -> anyhow::Result<Report> {
+    let file = match RedisFile::open_at(path) {
+        Ok(file) => file,
+        Err(e) => match e.downcast::<RdbError>() {
+            Ok(error) => {
+                return Ok(Report {
+                    error: Some(error),
+                    ..Default::default()
+                })
+            }
+            Err(e) => return Err(e),
+        },
+    };
+
+    let mut report = Report {
+        version: file.version(),
+        ..Default::default()
+    };
+    let mut db = 0;
+    let mut reader = file.into_iter();
+    let mut offset = reader.offset();
+    while let Some(section) = reader.next() {
+        let line = match section {
+            Ok(Section::Aux(aux)) => {
+                let key = String::from_utf8_lossy(aux.key()).into_owned();
+                let value = String::from_utf8_lossy(aux.value()).into_owned();
+                let line = format!("AUX FIELD {key} = '{value}'");
+                report.aux.push((key, value));
+                line
+            }
+            Ok(Section::Database(index)) => {
+                db = index;
+                report.dbs.entry(db).or_default();
+                format!("Selecting DB ID {db}")
+            }
+            Ok(Section::Resize(keys, expires)) => {
+                report.dbs.entry(db).or_default().hint = Some((keys, expires));
+                format!("Resize hint: {keys} keys, {expires} expires")
+            }
+            Ok(Section::Entry(entry)) => {
+                let stats = report.dbs.entry(db).or_default();
+                stats.keys += 1;
+                stats.expires += entry.expires_at().is_some() as usize;
+                stats.already_expired += entry.is_expired() as usize;
+                *stats.types.entry(entry.val().type_name()).or_default() += 1;
+                offset = reader.offset();
+                continue;
+            }
+            Ok(Section::Eof) => "Checksum OK".to_string(),
+            Ok(_) => continue,
+            Err(error) => {
+                report.error = Some(error);
+                break;
+            }
+        };
+        report.log.push((offset, line));
+        offset = reader.offset();
+    }
+    Ok(report)
+}
+
+fn print(path: &Path, report: &Report) {
+    println!("[offset 0] Checking RDB file {}", path.display());
+    if report.version > 0 {
+        println!("[offset 9] RDB version {}", report.version);
+    }
+    for (offset, line) in &report.log {
+        println!("[offset {offset}] {line}");
+    }
+    for (db, stats) in &report.dbs {
+        println!("--- Database {db} ---");
+        if let Some((keys, expires)) = stats.hint {
+            println!("resize hint: {keys} keys, {expires} expires");
+        }
+        println!("keys: {}", stats.keys);
+        println!("expires: {}", stats.expires);
+        println!("already expired: {}", stats.already_expired);
+        for (name, count) in &stats.types {
+            println!("{name}: {count}");
+        }
+    }
+    match &report.error {
+        Some(error) => {
+            println!("--- RDB ERROR DETECTED ---");
+            println!("[offset {}] {}", error.offset, error.error);
+        }
+        None => println!("\\o/ RDB looks OK! \\o/"),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let path = match args.as_slice() {
+        [_, path] => Path::new(path),
+        _ => {
+            eprintln!("Usage: redis-check-rdb <rdb-file-name>");
+            return ExitCode::FAILURE;
+        }
+    };
+    match check(path) {
+        Ok(report) => {
+            print(path, &report);
+            match report.error {
+                Some(_) => ExitCode::FAILURE,
+                None => ExitCode::SUCCESS,
+            }
+        }
+        Err(e) => {
+            eprintln!("{e:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use redis_starter_rust::db::{self, Entry, SaveOptions, Value};
+    use std::time::{Duration, SystemTime};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("redis-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("dump.rdb")
+    }
+
+    #[test]
+    fn test_check() {
+        let path = temp_path("check-rdb");
+        let later = SystemTime::now() + Duration::from_secs(60);
+        let list = Value::List(vec![Bytes::from("a")]);
+        let databases = [
+            vec![
+                Entry::new("k".into(), Value::String("v".into()), Some(later)),
+                Entry::new("l".into(), list, None),
+            ],
+            vec![],
+            vec![Entry::new("n".into(), Value::String("1".into()), None)],
+        ];
+        let aux = [("redis-ver", "7.2.0".to_string())];
+        db::save_at(&path, &aux, &databases, SaveOptions::default()).unwrap();
+
+        let report = check(&path).unwrap();
+        assert!(report.error.is_none());
+        assert_eq!(report.version, 11);
+        assert_eq!(report.aux, [("redis-ver".into(), "7.2.0".into())]);
+        assert_eq!(report.dbs.keys().collect::<Vec<_>>(), [&0, &2]);
+        let stats = &report.dbs[&0];
+        assert_eq!(
+            (stats.hint, stats.keys, stats.expires),
+            (Some((2, 1)), 2, 1)
+        );
+        assert_eq!(stats.types["list"], 1);
+        assert_eq!(stats.types["string"], 1);
+        assert_eq!(report.log[0], (9, "AUX FIELD redis-ver = '7.2.0'".into()));
+        assert_eq!(report.log.last().unwrap().1, "Checksum OK");
+
+        // The first corrupt byte is reported, along with what came before it.
+        let mut rdb = std::fs::read(&path).unwrap();
+        let len = rdb.len();
+        rdb[len - 1] ^= 0xff;
+        std::fs::write(&path, &rdb).unwrap();
+        let report = check(&path).unwrap();
+        assert_eq!(report.error.unwrap().offset, len - 8);
+        assert_eq!(report.dbs[&2].keys, 1);
+
+        std::fs::write(&path, b"REDIS").unwrap();
+        let report = check(&path).unwrap();
+        assert_eq!(report.error.unwrap().offset, 0);
+        assert!(check(&path.with_file_name("missing.rdb")).is_err());
+    }
+}
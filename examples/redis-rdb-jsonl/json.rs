@@ -0,0 +1,323 @@
+//! Just enough JSON to write and read back the lines of an export.
+
+use anyhow::{bail, Context, Result};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    /// Kept as written, so 64 bit integers are not rounded through a float.
+    Number(String),
+    String(String),
+    Array(Vec<Json>),
+    /// Members in the order they were written.
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn object<K: Into<String>>(members: impl IntoIterator<Item = (K, Json)>) -> Self {
+        Self::Object(members.into_iter().map(|(k, v)| (k.into(), v)).collect())
+    }
+
+    pub fn number(n: impl ToString) -> Self {
+        Self::Number(n.to_string())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// The member `key`, which must be there.
+    pub fn field(&self, key: &str) -> Result<&Json> {
+        self.get(key).with_context(|| format!("missing \"{key}\""))
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        match self {
+            Self::String(s) => Ok(s),
+            other => bail!("expected a string, got {other}"),
+        }
+    }
+
+    pub fn as_u64(&self) -> Result<u64> {
+        match self {
+            Self::Number(n) => n.parse().with_context(|| format!("invalid integer {n}")),
+            other => bail!("expected an integer, got {other}"),
+        }
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        match self {
+            Self::Bool(b) => Ok(*b),
+            other => bail!("expected a boolean, got {other}"),
+        }
+    }
+
+    pub fn as_array(&self) -> Result<&[Json]> {
+        match self {
+            Self::Array(items) => Ok(items),
+            other => bail!("expected an array, got {other}"),
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Compact, single line JSON.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => f.write_str(n),
+            Self::String(s) => write_string(f, s),
+            Self::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Json> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let json = parser.value()?;
+    parser.skip_whitespace();
+    match parser.pos == text.len() {
+        true => Ok(json),
+        false => bail!("trailing characters at column {}", parser.pos + 1),
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Result<u8> {
+        let byte = self.peek().context("unexpected end of line")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        self.skip_whitespace();
+        match self.next()? {
+            byte if byte == expected => Ok(()),
+            byte => bail!(
+                "expected '{}' at column {}, got '{}'",
+                expected as char,
+                self.pos,
+                byte as char
+            ),
+        }
+    }
+
+    fn literal(&mut self, word: &str, json: Json) -> Result<Json> {
+        match self.text[self.pos..].starts_with(word.as_bytes()) {
+            true => {
+                self.pos += word.len();
+                Ok(json)
+            }
+            false => bail!("invalid literal at column {}", self.pos + 1),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.skip_whitespace();
+        match self.peek().context("unexpected end of line")? {
+            b'n' => self.literal("null", Json::Null),
+            b't' => self.literal("true", Json::Bool(true)),
+            b'f' => self.literal("false", Json::Bool(false)),
+            b'"' => self.string().map(Json::String),
+            b'[' => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b']' => return Ok(Json::Array(items)),
+                        _ => bail!("expected ',' or ']' at column {}", self.pos),
+                    }
+                }
+            }
+            b'{' => {
+                self.pos += 1;
+                let mut members = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.expect(b':')?;
+                    members.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.next()? {
+                        b',' => continue,
+                        b'}' => return Ok(Json::Object(members)),
+                        _ => bail!("expected ',' or '}}' at column {}", self.pos),
+                    }
+                }
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = self.pos;
+                while matches!(
+                    self.peek(),
+                    Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                ) {
+                    self.pos += 1;
+                }
+                let number = std::str::from_utf8(&self.text[start..self.pos])?;
+                match number.parse::<f64>() {
+                    Ok(_) => Ok(Json::Number(number.to_string())),
+                    Err(_) => bail!("invalid number {number} at column {}", start + 1),
+                }
+            }
+            byte => bail!("unexpected '{}' at column {}", byte as char, self.pos + 1),
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .context("truncated escape")?;
+        let digits = std::str::from_utf8(digits)?;
+        let code = u32::from_str_radix(digits, 16).context("invalid escape")?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            match self.next()? {
+                b'"' => return Ok(String::from_utf8(out)?),
+                b'\\' => {
+                    let c = match self.next()? {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            // Characters outside the BMP come as surrogate pairs.
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.text[self.pos..].starts_with(b"\\u")
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code =
+                                    0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00));
+                            }
+                            char::from_u32(code).context("invalid unicode escape")?
+                        }
+                        byte => bail!("invalid escape '\\{}'", byte as char),
+                    };
+                    let mut buf = [0; 4];
+                    out.extend(c.encode_utf8(&mut buf).as_bytes());
+                }
+                byte => out.push(byte),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let json = Json::object([
+            ("s", Json::String("a \"quoted\"\n\u{1}é".into())),
+            ("n", Json::number(u64::MAX)),
+            ("f", Json::number(-1.5e-7)),
+            (
+                "a",
+                Json::Array(vec![Json::Null, Json::Bool(true), Json::Array(vec![])]),
+            ),
+            ("o", Json::object::<&str>([])),
+        ]);
+        let text = json.to_string();
+        assert!(!text.contains('\n'));
+        assert_eq!(parse(&text).unwrap(), json);
+        assert_eq!(json.field("n").unwrap().as_u64().unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn test_parse() {
+        let json = parse(r#" { "k" : [1, "é😀\/"] } "#).unwrap();
+        let items = json.field("k").unwrap().as_array().unwrap();
+        assert_eq!(items[0], Json::number(1));
+        assert_eq!(items[1].as_str().unwrap(), "é😀/");
+
+        for bad in ["", "{", "[1,]", "{\"a\" 1}", "tru", "\"a", "1 2", "-"] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! Converts RDB files to JSON Lines and back, to diff and inspect snapshots.
+//!
+//! An export starts with a line describing the file, followed by one line
+//! per aux field and one per key, in the order they were stored:
+//!
+//! ```text
+//! {"rdb_version":11,"compression":true,"checksum":true}
+//! {"aux":"redis-ver","value":"7.2.0"}
+//! {"db":0,"key":"k","type":"string","expires_at":1700000000000,"value":"v"}
+//! ```
+//!
+//! Importing an export of a file written by this server gives back the very
+//! same bytes.
+//!
+//! Run it with `cargo run --example redis-rdb-jsonl -- export dump.rdb`.
+
+mod json;
+
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use json::Json;
+use redis_starter_rust::db::{
+    self,
+    file::{RedisFile, Section},
+    stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamEntry, StreamId},
+    Entry, SaveOptions, Value,
+};
+use std::{
+    env, fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Everything an RDB file holds, in the order it holds it.
+#[derive(Debug, Default)]
+struct Snapshot {
+    version: u32,
+    options: SaveOptions,
+    aux: Vec<(String, String)>,
+    databases: Vec<Vec<Entry>>,
+}
+
+impl Snapshot {
+    fn read(path: &Path) -> Result<Self> {
+        let file = RedisFile::open_at(path)?;
+        let mut snapshot = Snapshot {
+            version: file.version(),
+            ..Default::default()
+        };
+        let mut db = 0;
+        for section in file {
+            match section? {
+                Section::Aux(aux) => {
+                    let key = String::from_utf8_lossy(aux.key()).into_owned();
+                    let value = String::from_utf8_lossy(aux.value()).into_owned();
+                    snapshot.aux.push((key, value));
+                }
+                Section::Database(index) => db = index,
+                Section::Entry(entry) => snapshot.push(db, entry),
+                _ => {}
+            }
+        }
+        snapshot.options = snapshot.find_options(&fs::read(path)?);
+        Ok(snapshot)
+    }
+
+    fn push(&mut self, db: usize, entry: Entry) {
+        if self.databases.len() <= db {
+            self.databases.resize_with(db + 1, Vec::new);
+        }
+        self.databases[db].push(entry);
+    }
+
+    fn write_to<W: Write>(&self, writer: W, options: SaveOptions) -> Result<W> {
+        let aux: Vec<_> = self
+            .aux
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.clone()))
+            .collect();
+        db::write_to(writer, &aux, &self.databases, options)
+    }
+
+    /// The options which write `original` back, as this server would have
+    /// saved it with them. Files written elsewhere can't be reproduced, so
+    /// they get the defaults.
+    fn find_options(&self, original: &[u8]) -> SaveOptions {
+        let all = [(true, true), (true, false), (false, true), (false, false)];
+        let found = all.into_iter().find_map(|(compression, checksum)| {
+            let options = SaveOptions {
+                compression,
+                checksum,
+            };
+            let written = self.write_to(Vec::new(), options).ok()?;
+            (written == original).then_some(options)
+        });
+        found.unwrap_or_else(|| {
+            eprintln!("Warning: this file was not written by this server, an import won't match it byte for byte");
+            SaveOptions::default()
+        })
+    }
+}
+
+fn export(path: &Path, out: impl Write) -> Result<()> {
+    let snapshot = Snapshot::read(path)?;
+    let mut out = BufWriter::new(out);
+    let header = Json::object([
+        ("rdb_version", Json::number(snapshot.version)),
+        ("compression", Json::Bool(snapshot.options.compression)),
+        ("checksum", Json::Bool(snapshot.options.checksum)),
+    ]);
+    writeln!(out, "{header}")?;
+    for (key, value) in &snapshot.aux {
+        let aux = Json::object([
+            ("aux", Json::String(key.clone())),
+            ("value", Json::String(value.clone())),
+        ]);
+        writeln!(out, "{aux}")?;
+    }
+    for (db, entries) in snapshot.databases.iter().enumerate() {
+        for entry in entries {
+            let mut line = vec![
+                ("db", Json::number(db)),
+                ("key", bytes_json(entry.key())),
+                ("type", Json::String(entry.val().type_name().into())),
+            ];
+            if let Some(at) = entry.expires_at() {
+                let ms = at
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                line.push(("expires_at", Json::number(ms)));
+            }
+            line.push(("value", value_json(entry.val())));
+            writeln!(out, "{}", Json::object(line))?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn import(input: impl BufRead, path: &Path) -> Result<()> {
+    let mut lines = input.lines().enumerate();
+    let mut snapshot = match lines.next() {
+        Some((_, line)) => {
+            let header = json::parse(&line?).context("Invalid header on line 1")?;
+            Snapshot {
+                version: header.field("rdb_version")?.as_u64()? as u32,
+                options: SaveOptions {
+                    compression: header.field("compression")?.as_bool()?,
+                    checksum: header.field("checksum")?.as_bool()?,
+                },
+                ..Default::default()
+            }
+        }
+        None => bail!("Empty export, it should start with a header line"),
+    };
+    for (i, line) in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let read_line = |snapshot: &mut Snapshot| -> Result<()> {
+            let json = json::parse(&line)?;
+            if let Some(aux) = json.get("aux") {
+                let value = json.field("value")?.as_str()?;
+                snapshot.aux.push((aux.as_str()?.into(), value.into()));
+                return Ok(());
+            }
+            let db = json.field("db")?.as_u64()? as usize;
+            let key = json_bytes(json.field("key")?)?;
+            let expires_at = match json.get("expires_at") {
+                Some(ms) => Some(UNIX_EPOCH + Duration::from_millis(ms.as_u64()?)),
+                None => None,
+            };
+            let kind = json.field("type")?.as_str()?;
+            let value = json_value(kind, json.field("value")?)?;
+            snapshot.push(db, Entry::new(key, value, expires_at));
+            Ok(())
+        };
+        read_line(&mut snapshot).with_context(|| format!("Invalid entry on line {}", i + 1))?;
+    }
+    if snapshot.version != 11 {
+        eprintln!(
+            "Warning: the export came from an RDB version {} file, it is written as version 11",
+            snapshot.version
+        );
+    }
+    let aux: Vec<_> = snapshot
+        .aux
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.clone()))
+        .collect();
+    db::save_at(path, &aux, &snapshot.databases, snapshot.options)
+}
+
+/// Strings are kept as they are when they are valid UTF-8, binary ones are
+/// written in hex.
+fn bytes_json(bytes: &[u8]) -> Json {
+    match std::str::from_utf8(bytes) {
+        Ok(s) => Json::String(s.into()),
+        Err(_) => {
+            let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+            Json::object([("hex", Json::String(hex))])
+        }
+    }
+}
+
+fn json_bytes(json: &Json) -> Result<Bytes> {
+    if let Some(hex) = json.get("hex") {
+        let hex = hex.as_str()?;
+        if hex.len() % 2 != 0 {
+            bail!("odd length hex string");
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or("-"), 16))
+            .collect::<Result<Vec<_>, _>>()
+            .context("invalid hex string")?;
+        return Ok(bytes.into());
+    }
+    Ok(Bytes::from(json.as_str()?.to_string()))
+}
+
+fn list_json(items: &[Bytes]) -> Json {
+    Json::Array(items.iter().map(|b| bytes_json(b)).collect())
+}
+
+fn json_list(json: &Json) -> Result<Vec<Bytes>> {
+    json.as_array()?.iter().map(json_bytes).collect()
+}
+
+fn pairs_json<T>(pairs: &[(Bytes, T)], value: impl Fn(&T) -> Json) -> Json {
+    let pair = |(k, v): &(Bytes, T)| Json::Array(vec![bytes_json(k), value(v)]);
+    Json::Array(pairs.iter().map(pair).collect())
+}
+
+fn json_pairs<T>(json: &Json, value: impl Fn(&Json) -> Result<T>) -> Result<Vec<(Bytes, T)>> {
+    let pair = |pair: &Json| match pair.as_array()? {
+        [k, v] => Ok((json_bytes(k)?, value(v)?)),
+        _ => bail!("expected a pair, got {pair}"),
+    };
+    json.as_array()?.iter().map(pair).collect()
+}
+
+/// Scores are numbers, except for infinities and NaN which JSON lacks.
+fn score_json(score: &f64) -> Json {
+    match score.is_finite() {
+        true => Json::number(score),
+        false => Json::String(score.to_string()),
+    }
+}
+
+fn json_score(json: &Json) -> Result<f64> {
+    let text = match json {
+        Json::Number(n) => n.as_str(),
+        other => other.as_str()?,
+    };
+    text.parse()
+        .with_context(|| format!("invalid score {text}"))
+}
+
+fn id_json(id: &StreamId) -> Json {
+    Json::String(format!("{}-{}", id.ms, id.seq))
+}
+
+fn json_id(json: &Json) -> Result<StreamId> {
+    let id = json.as_str()?;
+    let parsed = id
+        .split_once('-')
+        .and_then(|(ms, seq)| Some((ms.parse().ok()?, seq.parse().ok()?)));
+    match parsed {
+        Some((ms, seq)) => Ok(StreamId { ms, seq }),
+        None => bail!("invalid stream ID {id}"),
+    }
+}
+
+fn stream_json(stream: &Stream) -> Json {
+    let entries = stream.entries.iter().map(|entry| {
+        Json::object([
+            ("id", id_json(&entry.id)),
+            ("fields", pairs_json(&entry.fields, |v| bytes_json(v))),
+        ])
+    });
+    let groups = stream.groups.iter().map(|group| {
+        let pending = group.pending.iter().map(|pending| {
+            Json::object([
+                ("id", id_json(&pending.id)),
+                ("delivery_time", Json::number(pending.delivery_time)),
+                ("delivery_count", Json::number(pending.delivery_count)),
+            ])
+        });
+        let consumers = group.consumers.iter().map(|consumer| {
+            Json::object([
+                ("name", bytes_json(&consumer.name)),
+                ("seen_time", Json::number(consumer.seen_time)),
+                ("active_time", Json::number(consumer.active_time)),
+                (
+                    "pending",
+                    Json::Array(consumer.pending.iter().map(id_json).collect()),
+                ),
+            ])
+        });
+        Json::object([
+            ("name", bytes_json(&group.name)),
+            ("last_id", id_json(&group.last_id)),
+            ("entries_read", Json::number(group.entries_read)),
+            ("pending", Json::Array(pending.collect())),
+            ("consumers", Json::Array(consumers.collect())),
+        ])
+    });
+    Json::object([
+        ("entries", Json::Array(entries.collect())),
+        ("length", Json::number(stream.length)),
+        ("last_id", id_json(&stream.last_id)),
+        ("first_id", id_json(&stream.first_id)),
+        ("max_deleted_id", id_json(&stream.max_deleted_id)),
+        ("entries_added", Json::number(stream.entries_added)),
+        ("groups", Json::Array(groups.collect())),
+    ])
+}
+
+fn json_stream(json: &Json) -> Result<Stream> {
+    let entries = json.field("entries")?.as_array()?.iter().map(|entry| {
+        Ok(StreamEntry {
+            id: json_id(entry.field("id")?)?,
+            fields: json_pairs(entry.field("fields")?, json_bytes)?,
+        })
+    });
+    let groups = json.field("groups")?.as_array()?.iter().map(|group| {
+        let pending = group.field("pending")?.as_array()?.iter().map(|pending| {
+            Ok(PendingEntry {
+                id: json_id(pending.field("id")?)?,
+                delivery_time: pending.field("delivery_time")?.as_u64()?,
+                delivery_count: pending.field("delivery_count")?.as_u64()?,
+            })
+        });
+        let consumers = group
+            .field("consumers")?
+            .as_array()?
+            .iter()
+            .map(|consumer| {
+                let pending = consumer.field("pending")?.as_array()?.iter();
+                Ok(Consumer {
+                    name: json_bytes(consumer.field("name")?)?,
+                    seen_time: consumer.field("seen_time")?.as_u64()?,
+                    active_time: consumer.field("active_time")?.as_u64()?,
+                    pending: pending.map(json_id).collect::<Result<_>>()?,
+                })
+            });
+        Ok(ConsumerGroup {
+            name: json_bytes(group.field("name")?)?,
+            last_id: json_id(group.field("last_id")?)?,
+            entries_read: group.field("entries_read")?.as_u64()?,
+            pending: pending.collect::<Result<_>>()?,
+            consumers: consumers.collect::<Result<_>>()?,
+        })
+    });
+    Ok(Stream {
+        entries: entries.collect::<Result<_>>()?,
+        length: json.field("length")?.as_u64()?,
+        last_id: json_id(json.field("last_id")?)?,
+        first_id: json_id(json.field("first_id")?)?,
+        max_deleted_id: json_id(json.field("max_deleted_id")?)?,
+        entries_added: json.field("entries_added")?.as_u64()?,
+        groups: groups.collect::<Result<_>>()?,
+    })
+}
+
+fn value_json(value: &Value) -> Json {
+    match value {
+        Value::String(s) => bytes_json(s),
+        Value::List(items) | Value::Set(items) => list_json(items),
+        Value::SortedSet(members) => pairs_json(members, score_json),
+        Value::Hash(fields) => pairs_json(fields, |v| bytes_json(v)),
+        Value::Stream(stream) => stream_json(stream),
+    }
+}
+
+fn json_value(kind: &str, json: &Json) -> Result<Value> {
+    let value = match kind {
+        "string" => Value::String(json_bytes(json)?),
+        "list" => Value::List(json_list(json)?),
+        "set" => Value::Set(json_list(json)?),
+        "zset" => Value::SortedSet(json_pairs(json, json_score)?),
+        "hash" => Value::Hash(json_pairs(json, json_bytes)?),
+        "stream" => Value::Stream(json_stream(json)?),
+        other => bail!("unknown type {other}"),
+    };
+    Ok(value)
+}
+
+const USAGE: &str = "Usage: redis-rdb-jsonl export <rdb-file> [jsonl-file]
+       redis-rdb-jsonl import <jsonl-file> <rdb-file>";
+
+fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["export", rdb] => export(Path::new(rdb), io::stdout().lock()),
+        ["export", rdb, jsonl] => export(Path::new(rdb), fs::File::create(jsonl)?),
+        ["import", "-", rdb] => import(io::stdin().lock(), Path::new(rdb)),
+        ["import", jsonl, rdb] => {
+            let file = fs::File::open(jsonl).with_context(|| format!("Unable to open {jsonl}"))?;
+            import(BufReader::new(file), Path::new(rdb))
+        }
+        _ => bail!(USAGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, time::SystemTime};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("redis-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn stream() -> Stream {
+        let id = |ms, seq| StreamId { ms, seq };
+        let entry = |ms, fields: &[(&str, &str)]| StreamEntry {
+            id: id(ms, 0),
+            fields: fields
+                .iter()
+                .map(|(f, v)| (Bytes::from(f.to_string()), Bytes::from(v.to_string())))
+                .collect(),
+        };
+        Stream {
+            entries: vec![entry(1, &[("a", "1")]), entry(2, &[("b", "2"), ("c", "3")])],
+            length: 2,
+            last_id: id(2, 0),
+            first_id: id(1, 0),
+            max_deleted_id: id(0, 0),
+            entries_added: 2,
+            groups: vec![ConsumerGroup {
+                name: "g".into(),
+                last_id: id(1, 0),
+                entries_read: u64::MAX,
+                pending: vec![PendingEntry {
+                    id: id(1, 0),
+                    delivery_time: 1_700_000_000_000,
+                    delivery_count: 1,
+                }],
+                consumers: vec![Consumer {
+                    name: "c".into(),
+                    seen_time: 1_700_000_000_000,
+                    active_time: 1_700_000_000_001,
+                    pending: vec![id(1, 0)],
+                }],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = temp_dir("rdb-jsonl");
+        let later = SystemTime::now() + Duration::from_secs(3600);
+        let past = UNIX_EPOCH + Duration::from_millis(1);
+        let scores = vec![
+            ("a".into(), 1.5),
+            ("b".into(), f64::INFINITY),
+            ("c".into(), -0.1),
+        ];
+        let databases = [
+            vec![
+                Entry::new("s".into(), Value::String("v".into()), Some(later)),
+                Entry::new("n".into(), Value::String("12345".into()), None),
+                Entry::new("long".into(), Value::String("ab".repeat(100).into()), None),
+                Entry::new(
+                    Bytes::from_static(b"\xff\x00"),
+                    Value::String("\"quoted\"\n".into()),
+                    None,
+                ),
+                Entry::new("gone".into(), Value::String("x".into()), Some(past)),
+            ],
+            vec![],
+            vec![
+                Entry::new("l".into(), Value::List(vec!["1".into(), "b".into()]), None),
+                Entry::new("set".into(), Value::Set(vec!["x".into()]), None),
+                Entry::new("z".into(), Value::SortedSet(scores), None),
+                Entry::new(
+                    "h".into(),
+                    Value::Hash(vec![("f".into(), "v".into())]),
+                    None,
+                ),
+                Entry::new("x".into(), Value::Stream(stream()), None),
+            ],
+        ];
+        let aux = [
+            ("redis-ver", "7.2.0".to_string()),
+            ("redis-bits", "64".to_string()),
+        ];
+
+        for (compression, checksum) in [(true, true), (false, false)] {
+            let options = SaveOptions {
+                compression,
+                checksum,
+            };
+            let original = dir.join("original.rdb");
+            db::save_at(&original, &aux, &databases, options).unwrap();
+            let mut jsonl = Vec::new();
+            export(&original, &mut jsonl).unwrap();
+            let text = String::from_utf8(jsonl.clone()).unwrap();
+            assert!(text.starts_with(&format!(
+                "{{\"rdb_version\":11,\"compression\":{compression},\"checksum\":{checksum}}}\n"
+            )));
+            assert!(text.contains("{\"aux\":\"redis-bits\",\"value\":\"64\"}\n"));
+            assert!(text.contains("\"key\":{\"hex\":\"ff00\"}"));
+            assert!(text.contains("\"value\":[[\"a\",1.5],[\"b\",\"inf\"],[\"c\",-0.1]]"));
+
+            let imported = dir.join("imported.rdb");
+            import(jsonl.as_slice(), &imported).unwrap();
+            assert_eq!(fs::read(&imported).unwrap(), fs::read(&original).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let dir = temp_dir("rdb-jsonl-errors");
+        let path = dir.join("dump.rdb");
+        assert!(import(&b""[..], &path).is_err());
+        let header = "{\"rdb_version\":11,\"compression\":true,\"checksum\":true}\n";
+        let bad = format!("{header}{{\"db\":0,\"key\":\"k\",\"type\":\"blob\",\"value\":\"v\"}}\n");
+        let error = import(bad.as_bytes(), &path).unwrap_err();
+        assert_eq!(
+            format!("{error:#}"),
+            "Invalid entry on line 2: unknown type blob"
+        );
+        let good =
+            format!("{header}{{\"db\":3,\"key\":\"k\",\"type\":\"string\",\"value\":\"v\"}}\n");
+        import(good.as_bytes(), &path).unwrap();
+        let mut out = Vec::new();
+        export(&path, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), good);
+    }
+}
@@ -1,50 +1,298 @@
-use anyhow::{Context, Result};
+pub mod table;
+
+use crate::proto::ErrorKind;
+use bytes::Bytes;
 use std::time;
+use table::Flag;
+use thiserror::Error;
+
+#[derive(Error, PartialEq, Debug, Clone)]
+pub enum CommandError {
+    #[error("unknown command '{0}', with args beginning with: {1}")]
+    UnknownCommand(String, String),
+    #[error("unknown subcommand '{0}'. Try {1} HELP.")]
+    UnknownSubcommand(String, String),
+    #[error("wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+    #[error("syntax error")]
+    Syntax,
+    #[error("value is not an integer or out of range")]
+    NotInteger,
+    #[error("invalid expire time in '{0}' command")]
+    InvalidExpire(String),
+    #[error("Operation against a key holding the wrong kind of value")]
+    WrongType,
+    #[error("unsupported protocol version")]
+    NoProto,
+    #[error("invalid username-password pair or user is disabled.")]
+    WrongPass,
+    #[error("command not allowed when used memory > 'maxmemory'.")]
+    Oom,
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CommandError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::WrongType => ErrorKind::WrongType,
+            Self::NoProto => ErrorKind::NoProto,
+            Self::WrongPass => ErrorKind::WrongPass,
+            Self::Oom => ErrorKind::Oom,
+            _ => ErrorKind::Err,
+        }
+    }
+
+    /// Mirrors Redis' unknown command reply, quoting arguments until the
+    /// message reaches 128 characters.
+    pub fn unknown_command(name: &str, args: &[Bytes]) -> Self {
+        let mut quoted = String::new();
+        for arg in args {
+            if quoted.len() >= 128 {
+                break;
+            }
+            let arg = String::from_utf8_lossy(arg);
+            let arg: String = arg.chars().take(128 - quoted.len()).collect();
+            quoted.push_str(&format!("'{arg}' "));
+        }
+        let name: String = name.chars().take(128).collect();
+        Self::UnknownCommand(name, quoted)
+    }
+}
 
 #[derive(PartialEq, Debug)]
 pub enum ConfigKey {
     Dir,
     DbFilename,
+    ClientQueryBufferLimit,
+    Save,
+    RdbCompression,
+    RdbChecksum,
+    AppendOnly,
+    AppendFsync,
+    AutoAofRewritePercentage,
+    AutoAofRewriteMinSize,
+    Databases,
+    MaxMemory,
+    MaxMemoryPolicy,
+    MaxMemorySamples,
 }
 
 impl TryFrom<&str> for ConfigKey {
-    type Error = anyhow::Error;
+    type Error = CommandError;
 
-    fn try_from(value: &str) -> Result<Self> {
-        match value {
+    fn try_from(value: &str) -> Result<Self, CommandError> {
+        match value.to_lowercase().as_str() {
             "dir" => Ok(Self::Dir),
             "dbfilename" => Ok(Self::DbFilename),
-            u => Err(anyhow::anyhow!("Unknown cmd: {u}")),
+            "client-query-buffer-limit" => Ok(Self::ClientQueryBufferLimit),
+            "save" => Ok(Self::Save),
+            "rdbcompression" => Ok(Self::RdbCompression),
+            "rdbchecksum" => Ok(Self::RdbChecksum),
+            "appendonly" => Ok(Self::AppendOnly),
+            "appendfsync" => Ok(Self::AppendFsync),
+            "auto-aof-rewrite-percentage" => Ok(Self::AutoAofRewritePercentage),
+            "auto-aof-rewrite-min-size" => Ok(Self::AutoAofRewriteMinSize),
+            "databases" => Ok(Self::Databases),
+            "maxmemory" => Ok(Self::MaxMemory),
+            "maxmemory-policy" => Ok(Self::MaxMemoryPolicy),
+            "maxmemory-samples" => Ok(Self::MaxMemorySamples),
+            _ => Err(CommandError::Other(format!(
+                "Unknown option or number of arguments for CONFIG GET - '{value}'"
+            ))),
         }
     }
 }
 
 #[derive(PartialEq, Debug)]
 pub enum ConfigCmd {
-    Get(ConfigKey),
+    Get(Vec<ConfigKey>),
+    Set(Vec<(ConfigKey, String)>),
 }
 
 impl TryFrom<&[&str]> for ConfigCmd {
-    type Error = anyhow::Error;
-
-    fn try_from(value: &[&str]) -> Result<Self> {
-        let mut iter = value.iter();
-        let cmd = iter.next().context("command")?;
-        let param = iter.next().context("param")?;
-        match *cmd {
-            "GET" | "get" => Ok(Self::Get(ConfigKey::try_from(*param)?)),
-            u => Err(anyhow::anyhow!("Unknown cmd: {u}")),
+    type Error = CommandError;
+
+    fn try_from(value: &[&str]) -> Result<Self, CommandError> {
+        let cmd = value
+            .first()
+            .ok_or_else(|| CommandError::WrongArity("config".to_string()))?;
+        match cmd.to_uppercase().as_str() {
+            "GET" => match value {
+                [_, params @ ..] if !params.is_empty() => {
+                    let keys = params.iter().map(|p| ConfigKey::try_from(*p));
+                    Ok(Self::Get(keys.collect::<Result<_, _>>()?))
+                }
+                _ => Err(CommandError::WrongArity("config|get".to_string())),
+            },
+            "SET" => match value {
+                [_, params @ ..] if !params.is_empty() && params.len() % 2 == 0 => {
+                    let pairs = params.chunks(2).map(|pair| {
+                        let key = ConfigKey::try_from(pair[0]).map_err(|_| {
+                            CommandError::Other(format!(
+                                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                                pair[0]
+                            ))
+                        })?;
+                        Ok((key, pair[1].to_string()))
+                    });
+                    Ok(Self::Set(pairs.collect::<Result<_, _>>()?))
+                }
+                _ => Err(CommandError::WrongArity("config|set".to_string())),
+            },
+            _ => Err(CommandError::UnknownSubcommand(
+                cmd.to_string(),
+                "CONFIG".to_string(),
+            )),
         }
     }
 }
 
+#[derive(PartialEq, Debug)]
+pub enum CommandCmd {
+    List,
+    Count,
+    Info(Vec<Bytes>),
+    Docs(Vec<Bytes>),
+    GetKeys(Vec<Bytes>),
+}
+
+/// When the key written by `SET` expires.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Expiry {
+    /// After a while, counted from when the command was received.
+    In(time::Duration),
+    /// At a unix time.
+    At(time::SystemTime),
+    /// Whenever the previous value was going to.
+    Keep,
+}
+
+/// Which state of the key `SET` requires to write it.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Condition {
+    #[default]
+    Always,
+    IfMissing,
+    IfExists,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct SetOptions {
+    /// Without one, the key never expires.
+    pub expiry: Option<Expiry>,
+    pub condition: Condition,
+    /// Reply with the previous value.
+    pub get: bool,
+}
+
+/// When the key given to `EXPIRE` and its variants expires, in milliseconds.
+/// Either may already be in the past, which deletes the key.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Timeout {
+    /// After a while, counted from when the command was received.
+    In(i64),
+    /// At a unix time.
+    At(i64),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub struct ExpireOptions {
+    /// Whether the key must have a deadline already (`XX`) or not (`NX`).
+    pub condition: Condition,
+    /// How the new deadline must compare to the current one (`GT` or `LT`).
+    /// Keys without one compare as never expiring.
+    pub compare: Option<std::cmp::Ordering>,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum ObjectCmd {
+    Encoding(Bytes),
+    IdleTime(Bytes),
+    Freq(Bytes),
+}
+
+#[derive(PartialEq, Debug, Default)]
+pub struct Hello {
+    pub protover: Option<i64>,
+    pub auth: Option<(Bytes, Bytes)>,
+    pub setname: Option<Bytes>,
+}
+
 #[derive(PartialEq, Debug)]
 pub enum Command {
-    Ping,
-    Echo(String),
-    Get(String),
-    Set(String, String, Option<time::Duration>),
+    /// Replies `PONG`, or the message when there is one.
+    Ping(Option<Bytes>),
+    Echo(Bytes),
+    Get(Bytes),
+    Set(Bytes, Bytes, SetOptions),
     Config(ConfigCmd),
-    Keys(String),
-    Unknown(String, String),
+    Keys(Bytes),
+    Hello(Hello),
+    Introspect(CommandCmd),
+    Save,
+    BgSave,
+    LastSave,
+    Info(Vec<Bytes>),
+    BgRewriteAof,
+    Select(i64),
+    SwapDb(i64, i64),
+    Move(Bytes, i64),
+    Del(Vec<Bytes>),
+    /// Whether the keys are freed in the background.
+    FlushDb(bool),
+    FlushAll(bool),
+    DbSize,
+    Type(Bytes),
+    Object(ObjectCmd),
+    Expire(Bytes, Timeout, ExpireOptions),
+    /// Whether the reply is in milliseconds.
+    Ttl(Bytes, bool),
+    ExpireTime(Bytes, bool),
+    Persist(Bytes),
+}
+
+impl Command {
+    /// Name of the command in the table. Commands which only differ in the
+    /// unit of their arguments, like `EXPIRE` and `PEXPIRE`, share one.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ping(_) => "ping",
+            Self::Echo(_) => "echo",
+            Self::Get(_) => "get",
+            Self::Set(..) => "set",
+            Self::Config(_) => "config",
+            Self::Keys(_) => "keys",
+            Self::Hello(_) => "hello",
+            Self::Introspect(_) => "command",
+            Self::Save => "save",
+            Self::BgSave => "bgsave",
+            Self::LastSave => "lastsave",
+            Self::Info(_) => "info",
+            Self::BgRewriteAof => "bgrewriteaof",
+            Self::Select(_) => "select",
+            Self::SwapDb(..) => "swapdb",
+            Self::Move(..) => "move",
+            Self::Del(_) => "del",
+            Self::FlushDb(_) => "flushdb",
+            Self::FlushAll(_) => "flushall",
+            Self::DbSize => "dbsize",
+            Self::Type(_) => "type",
+            Self::Object(_) => "object",
+            Self::Expire(_, Timeout::In(_), _) => "pexpire",
+            Self::Expire(_, Timeout::At(_), _) => "pexpireat",
+            Self::Ttl(_, false) => "ttl",
+            Self::Ttl(_, true) => "pttl",
+            Self::ExpireTime(_, false) => "expiretime",
+            Self::ExpireTime(_, true) => "pexpiretime",
+            Self::Persist(_) => "persist",
+        }
+    }
+
+    /// Whether the command may need more memory, so it is refused once
+    /// `maxmemory` is reached, as flagged `denyoom` in the table.
+    pub fn is_denyoom(&self) -> bool {
+        let spec = table::lookup(self.name());
+        spec.map_or(false, |spec| spec.has_flag(Flag::DenyOom))
+    }
 }
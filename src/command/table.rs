@@ -0,0 +1,674 @@
+use super::CommandError;
+use bytes::Bytes;
+use std::fmt;
+
+#[allow(dead_code)]
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Flag {
+    Write,
+    Readonly,
+    DenyOom,
+    Admin,
+    NoScript,
+    Loading,
+    Stale,
+    Fast,
+    Blocking,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Write => "write",
+            Self::Readonly => "readonly",
+            Self::DenyOom => "denyoom",
+            Self::Admin => "admin",
+            Self::NoScript => "noscript",
+            Self::Loading => "loading",
+            Self::Stale => "stale",
+            Self::Fast => "fast",
+            Self::Blocking => "blocking",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Key positions of a command, counted from the command name at index 0.
+/// A negative `last` counts from the end, `-1` being the last argument.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Keys {
+    pub first: i64,
+    pub last: i64,
+    pub step: i64,
+}
+
+const NO_KEYS: Keys = Keys {
+    first: 0,
+    last: 0,
+    step: 0,
+};
+
+const ONE_KEY: Keys = Keys {
+    first: 1,
+    last: 1,
+    step: 1,
+};
+
+/// Metadata of a supported command, the way `COMMAND INFO` and `COMMAND DOCS`
+/// report it. A positive arity is exact, a negative one is a minimum; both
+/// count the command name itself.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    pub arity: i64,
+    pub flags: &'static [Flag],
+    pub keys: Keys,
+    pub categories: &'static [&'static str],
+    pub group: &'static str,
+    pub since: &'static str,
+    pub summary: &'static str,
+    pub subcommands: &'static [Spec],
+}
+
+impl Spec {
+    pub fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity >= 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+
+    pub fn subcommand(&self, name: &str) -> Option<&'static Spec> {
+        let name = format!("{}|{}", self.name, name.to_lowercase());
+        self.subcommands.iter().find(|s| s.name == name)
+    }
+
+    pub fn is_container(&self) -> bool {
+        !self.subcommands.is_empty()
+    }
+
+    pub fn has_flag(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /// Positions of the keys within `argc` arguments, command name included.
+    pub fn key_positions(&self, argc: usize) -> Vec<usize> {
+        let Keys { first, last, step } = self.keys;
+        if first == 0 {
+            return vec![];
+        }
+        let last = if last < 0 { argc as i64 + last } else { last };
+        (first..=last.min(argc as i64 - 1))
+            .step_by(step.max(1) as usize)
+            .map(|i| i as usize)
+            .collect()
+    }
+}
+
+const CONFIG_SUBCOMMANDS: &[Spec] = &[
+    Spec {
+        name: "config|get",
+        arity: -3,
+        flags: &[Flag::Admin, Flag::NoScript, Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@admin", "@slow", "@dangerous"],
+        group: "server",
+        since: "2.0.0",
+        summary: "Returns the effective values of configuration parameters.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "config|set",
+        arity: -4,
+        flags: &[Flag::Admin, Flag::NoScript, Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@admin", "@slow", "@dangerous"],
+        group: "server",
+        since: "2.0.0",
+        summary: "Sets configuration parameters in-flight.",
+        subcommands: &[],
+    },
+];
+
+const OBJECT_SUBCOMMANDS: &[Spec] = &[
+    Spec {
+        name: "object|encoding",
+        arity: 3,
+        flags: &[Flag::Readonly],
+        keys: Keys {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+        categories: &["@keyspace", "@read", "@slow"],
+        group: "generic",
+        since: "2.2.3",
+        summary: "Returns the internal encoding of a Redis object.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "object|freq",
+        arity: 3,
+        flags: &[Flag::Readonly],
+        keys: Keys {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+        categories: &["@keyspace", "@read", "@slow"],
+        group: "generic",
+        since: "4.0.0",
+        summary: "Returns the logarithmic access frequency counter of a Redis object.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "object|idletime",
+        arity: 3,
+        flags: &[Flag::Readonly],
+        keys: Keys {
+            first: 2,
+            last: 2,
+            step: 1,
+        },
+        categories: &["@keyspace", "@read", "@slow"],
+        group: "generic",
+        since: "2.2.3",
+        summary: "Returns the time since the last access to a Redis object.",
+        subcommands: &[],
+    },
+];
+
+const COMMAND_SUBCOMMANDS: &[Spec] = &[
+    Spec {
+        name: "command|count",
+        arity: 2,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@connection"],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns a count of commands.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "command|info",
+        arity: -2,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@connection"],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns information about one, multiple or all commands.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "command|docs",
+        arity: -2,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@connection"],
+        group: "server",
+        since: "7.0.0",
+        summary: "Returns documentary information about one, multiple or all commands.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "command|getkeys",
+        arity: -3,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@connection"],
+        group: "server",
+        since: "2.8.13",
+        summary: "Extracts the key names from an arbitrary command.",
+        subcommands: &[],
+    },
+];
+
+const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "ping",
+        arity: -1,
+        flags: &[Flag::Fast, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@fast", "@connection"],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the server's liveliness response.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "echo",
+        arity: 2,
+        flags: &[Flag::Fast, Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@fast", "@connection"],
+        group: "connection",
+        since: "1.0.0",
+        summary: "Returns the given string.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "hello",
+        arity: -1,
+        flags: &[Flag::Fast, Flag::NoScript, Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@fast", "@connection"],
+        group: "connection",
+        since: "6.0.0",
+        summary: "Handshakes with the Redis server.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "get",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@read", "@string", "@fast"],
+        group: "string",
+        since: "1.0.0",
+        summary: "Returns the string value of a key.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "set",
+        arity: -3,
+        flags: &[Flag::Write, Flag::DenyOom],
+        keys: ONE_KEY,
+        categories: &["@write", "@string", "@slow"],
+        group: "string",
+        since: "1.0.0",
+        summary: "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "del",
+        arity: -2,
+        flags: &[Flag::Write],
+        keys: Keys {
+            first: 1,
+            last: -1,
+            step: 1,
+        },
+        categories: &["@keyspace", "@write", "@slow"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Deletes one or more keys.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "keys",
+        arity: 2,
+        flags: &[Flag::Readonly],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@read", "@slow", "@dangerous"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns all key names that match a pattern.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "save",
+        arity: 1,
+        flags: &[Flag::Admin, Flag::NoScript],
+        keys: NO_KEYS,
+        categories: &["@admin", "@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Synchronously saves the database(s) to disk.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "bgsave",
+        arity: -1,
+        flags: &[Flag::Admin, Flag::NoScript],
+        keys: NO_KEYS,
+        categories: &["@admin", "@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously saves the database(s) to disk.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "bgrewriteaof",
+        arity: 1,
+        flags: &[Flag::Admin, Flag::NoScript],
+        keys: NO_KEYS,
+        categories: &["@admin", "@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Asynchronously rewrites the append-only file to disk.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "lastsave",
+        arity: 1,
+        flags: &[Flag::Loading, Flag::Stale, Flag::Fast],
+        keys: NO_KEYS,
+        categories: &["@admin", "@fast", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the Unix timestamp of the last successful save to disk.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "info",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns information and statistics about the server.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "select",
+        arity: 2,
+        flags: &[Flag::Loading, Flag::Stale, Flag::Fast],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@fast"],
+        group: "connection",
+        since: "2.0.0",
+        summary: "Changes the selected database.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "swapdb",
+        arity: 3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@write", "@fast", "@dangerous"],
+        group: "server",
+        since: "4.0.0",
+        summary: "Swaps two Redis databases.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "move",
+        arity: 3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Moves a key to another database.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "flushdb",
+        arity: -1,
+        flags: &[Flag::Write],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@write", "@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Remove all keys from the current database.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "flushall",
+        arity: -1,
+        flags: &[Flag::Write],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@write", "@slow", "@dangerous"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Removes all keys from all databases.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "dbsize",
+        arity: 1,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: NO_KEYS,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "server",
+        since: "1.0.0",
+        summary: "Returns the number of keys in the database.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "type",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Determines the type of value stored at a key.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "expire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Sets the expiration time of a key in seconds.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "pexpire",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key in milliseconds.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "expireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "1.2.0",
+        summary: "Sets the expiration time of a key to a Unix timestamp.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "pexpireat",
+        arity: -3,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "2.6.0",
+        summary: "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "ttl",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "generic",
+        since: "1.0.0",
+        summary: "Returns the expiration time in seconds of a key.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "pttl",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "generic",
+        since: "2.6.0",
+        summary: "Returns the expiration time in milliseconds of a key.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "expiretime",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix timestamp.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "pexpiretime",
+        arity: 2,
+        flags: &[Flag::Readonly, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@read", "@fast"],
+        group: "generic",
+        since: "7.0.0",
+        summary: "Returns the expiration time of a key as a Unix milliseconds timestamp.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "persist",
+        arity: 2,
+        flags: &[Flag::Write, Flag::Fast],
+        keys: ONE_KEY,
+        categories: &["@keyspace", "@write", "@fast"],
+        group: "generic",
+        since: "2.2.0",
+        summary: "Removes the expiration time of a key.",
+        subcommands: &[],
+    },
+    Spec {
+        name: "object",
+        arity: -2,
+        flags: &[],
+        keys: NO_KEYS,
+        categories: &["@slow"],
+        group: "generic",
+        since: "2.2.3",
+        summary: "A container for object introspection commands.",
+        subcommands: OBJECT_SUBCOMMANDS,
+    },
+    Spec {
+        name: "config",
+        arity: -2,
+        flags: &[],
+        keys: NO_KEYS,
+        categories: &["@slow"],
+        group: "server",
+        since: "2.0.0",
+        summary: "A container for server configuration commands.",
+        subcommands: CONFIG_SUBCOMMANDS,
+    },
+    Spec {
+        name: "command",
+        arity: -1,
+        flags: &[Flag::Loading, Flag::Stale],
+        keys: NO_KEYS,
+        categories: &["@slow", "@connection"],
+        group: "server",
+        since: "2.8.13",
+        summary: "Returns detailed information about all commands.",
+        subcommands: COMMAND_SUBCOMMANDS,
+    },
+];
+
+pub fn all() -> &'static [Spec] {
+    COMMANDS
+}
+
+pub fn lookup(name: &str) -> Option<&'static Spec> {
+    let name = name.to_lowercase();
+    COMMANDS.iter().find(|s| s.name == name)
+}
+
+/// Finds the spec a full command line runs, descending into subcommands,
+/// and checks the amount of arguments it was given.
+pub fn resolve(args: &[Bytes]) -> Result<&'static Spec, CommandError> {
+    let name = args
+        .first()
+        .map(|a| String::from_utf8_lossy(a))
+        .unwrap_or_default();
+    let spec = lookup(&name).ok_or_else(|| CommandError::unknown_command(&name, &args[1..]))?;
+    let spec = match args.get(1) {
+        Some(sub) if spec.is_container() => {
+            let sub = String::from_utf8_lossy(sub);
+            spec.subcommand(&sub).ok_or_else(|| {
+                CommandError::UnknownSubcommand(sub.into_owned(), name.to_uppercase())
+            })?
+        }
+        _ => spec,
+    };
+    if !spec.accepts(args.len()) {
+        return Err(CommandError::WrongArity(spec.name.to_string()));
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let set = lookup("SeT").unwrap();
+        assert!(set.has_flag(Flag::Write));
+        assert!(set.accepts(3) && set.accepts(5) && !set.accepts(2));
+        assert!(lookup("get").unwrap().accepts(2));
+        assert!(!lookup("get").unwrap().accepts(3));
+        assert!(lookup("nope").is_none());
+
+        let config = lookup("config").unwrap();
+        assert_eq!(config.subcommand("GET").unwrap().name, "config|get");
+        assert_eq!(config.subcommand("set").unwrap().name, "config|set");
+        assert!(config.subcommand("rewrite").is_none());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let args = |a: &[&str]| {
+            a.iter()
+                .map(|s| Bytes::from(s.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(resolve(&args(&["GET", "k"])).unwrap().name, "get");
+        assert_eq!(
+            resolve(&args(&["config", "get", "dir"])).unwrap().name,
+            "config|get"
+        );
+        assert_eq!(resolve(&args(&["command"])).unwrap().name, "command");
+        assert_eq!(
+            resolve(&args(&["get"])).unwrap_err(),
+            CommandError::WrongArity("get".into())
+        );
+        assert_eq!(
+            resolve(&args(&["config", "get"])).unwrap_err(),
+            CommandError::WrongArity("config|get".into())
+        );
+        assert_eq!(
+            resolve(&args(&["config", "nope"])).unwrap_err(),
+            CommandError::UnknownSubcommand("nope".into(), "CONFIG".into())
+        );
+        assert_eq!(
+            resolve(&args(&["foo", "a"])).unwrap_err(),
+            CommandError::UnknownCommand("foo".into(), "'a' ".into())
+        );
+    }
+
+    #[test]
+    fn test_key_positions() {
+        assert_eq!(lookup("set").unwrap().key_positions(5), [1]);
+        assert!(lookup("ping").unwrap().key_positions(1).is_empty());
+        let spec = Spec {
+            keys: Keys {
+                first: 1,
+                last: -1,
+                step: 2,
+            },
+            ..*lookup("set").unwrap()
+        };
+        assert_eq!(spec.key_positions(6), [1, 3, 5]);
+    }
+}
@@ -1,10 +1,131 @@
+use crate::db::SaveOptions;
 use std::path;
 use std::path::PathBuf;
 
-#[derive(Debug)]
+/// Snapshot the dataset once `changes` writes happened within `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// When the append only file is flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum AppendFsync {
+    Always,
+    #[default]
+    EverySec,
+    No,
+}
+
+impl TryFrom<&str> for AppendFsync {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value.to_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            _ => Err(()),
+        }
+    }
+}
+
+impl AppendFsync {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        }
+    }
+}
+
+/// Which keys are evicted once `maxmemory` is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MaxMemoryPolicy {
+    VolatileLru,
+    AllKeysLru,
+    VolatileLfu,
+    AllKeysLfu,
+    VolatileRandom,
+    AllKeysRandom,
+    VolatileTtl,
+    /// Writes are refused instead.
+    #[default]
+    NoEviction,
+}
+
+impl TryFrom<&str> for MaxMemoryPolicy {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, ()> {
+        match value.to_lowercase().as_str() {
+            "volatile-lru" => Ok(Self::VolatileLru),
+            "allkeys-lru" => Ok(Self::AllKeysLru),
+            "volatile-lfu" => Ok(Self::VolatileLfu),
+            "allkeys-lfu" => Ok(Self::AllKeysLfu),
+            "volatile-random" => Ok(Self::VolatileRandom),
+            "allkeys-random" => Ok(Self::AllKeysRandom),
+            "volatile-ttl" => Ok(Self::VolatileTtl),
+            "noeviction" => Ok(Self::NoEviction),
+            _ => Err(()),
+        }
+    }
+}
+
+impl MaxMemoryPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileRandom => "volatile-random",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileTtl => "volatile-ttl",
+            Self::NoEviction => "noeviction",
+        }
+    }
+
+    /// Whether only keys with a deadline may be evicted.
+    pub fn is_volatile(&self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+
+    pub fn is_lru(&self) -> bool {
+        matches!(self, Self::VolatileLru | Self::AllKeysLru)
+    }
+
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, Self::VolatileLfu | Self::AllKeysLfu)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Config {
     pub dir: path::PathBuf,
     pub db_filename: String,
+    pub client_query_buffer_limit: usize,
+    pub databases: usize,
+    pub save: Vec<SavePoint>,
+    pub rdb_compression: bool,
+    pub rdb_checksum: bool,
+    pub append_only: bool,
+    pub append_filename: String,
+    pub append_dirname: String,
+    pub append_fsync: AppendFsync,
+    pub aof_load_truncated: bool,
+    pub auto_aof_rewrite_percentage: u64,
+    pub auto_aof_rewrite_min_size: usize,
+    /// Bytes the keyspace may use, without a limit when 0.
+    pub maxmemory: usize,
+    pub maxmemory_policy: MaxMemoryPolicy,
+    /// Keys looked at to pick each one to evict.
+    pub maxmemory_samples: usize,
 }
 
 impl Config {
@@ -13,6 +134,23 @@ impl Config {
         path.push(self.db_filename.clone());
         path
     }
+
+    /// The single file AOF written by older versions, loaded when the
+    /// AOF directory has no manifest yet.
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.append_filename)
+    }
+
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.append_dirname)
+    }
+
+    pub fn save_options(&self) -> SaveOptions {
+        SaveOptions {
+            compression: self.rdb_compression,
+            checksum: self.rdb_checksum,
+        }
+    }
 }
 
 impl Default for Config {
@@ -20,6 +158,21 @@ impl Default for Config {
         Config {
             dir: ".".into(),
             db_filename: "store.rdb".into(),
+            client_query_buffer_limit: 1024 * 1024 * 1024,
+            databases: 16,
+            save: save_points("3600 1 300 100 60 10000").unwrap_or_default(),
+            rdb_compression: true,
+            rdb_checksum: true,
+            append_only: false,
+            append_filename: "appendonly.aof".into(),
+            append_dirname: "appendonlydir".into(),
+            append_fsync: AppendFsync::default(),
+            aof_load_truncated: true,
+            auto_aof_rewrite_percentage: 100,
+            auto_aof_rewrite_min_size: 64 * 1024 * 1024,
+            maxmemory: 0,
+            maxmemory_policy: MaxMemoryPolicy::default(),
+            maxmemory_samples: 5,
         }
     }
 }
@@ -34,6 +187,60 @@ impl From<&[String]> for Config {
                 match cmd.as_str() {
                     "--dir" => cfg.dir = PathBuf::from(val),
                     "--dbfilename" => cfg.db_filename = val.to_string(),
+                    "--client-query-buffer-limit" => match memory(val) {
+                        Some(limit) => cfg.client_query_buffer_limit = limit,
+                        None => println!("Invalid memory amount: {val}"),
+                    },
+                    "--databases" => match val.parse() {
+                        Ok(databases) if databases > 0 => cfg.databases = databases,
+                        _ => println!("Invalid databases: {val}"),
+                    },
+                    "--save" => match save_points(val) {
+                        Some(points) => cfg.save = points,
+                        None => println!("Invalid save parameters: {val}"),
+                    },
+                    "--rdbcompression" => match yes_no(val) {
+                        Some(on) => cfg.rdb_compression = on,
+                        None => println!("Invalid rdbcompression: {val}"),
+                    },
+                    "--rdbchecksum" => match yes_no(val) {
+                        Some(on) => cfg.rdb_checksum = on,
+                        None => println!("Invalid rdbchecksum: {val}"),
+                    },
+                    "--appendonly" => match yes_no(val) {
+                        Some(on) => cfg.append_only = on,
+                        None => println!("Invalid appendonly: {val}"),
+                    },
+                    "--appendfilename" => cfg.append_filename = val.to_string(),
+                    "--appenddirname" => cfg.append_dirname = val.to_string(),
+                    "--appendfsync" => match AppendFsync::try_from(val.as_str()) {
+                        Ok(fsync) => cfg.append_fsync = fsync,
+                        Err(_) => println!("Invalid appendfsync: {val}"),
+                    },
+                    "--aof-load-truncated" => match yes_no(val) {
+                        Some(on) => cfg.aof_load_truncated = on,
+                        None => println!("Invalid aof-load-truncated: {val}"),
+                    },
+                    "--auto-aof-rewrite-percentage" => match val.parse() {
+                        Ok(percentage) => cfg.auto_aof_rewrite_percentage = percentage,
+                        Err(_) => println!("Invalid auto-aof-rewrite-percentage: {val}"),
+                    },
+                    "--auto-aof-rewrite-min-size" => match memory(val) {
+                        Some(size) => cfg.auto_aof_rewrite_min_size = size,
+                        None => println!("Invalid memory amount: {val}"),
+                    },
+                    "--maxmemory" => match memory(val) {
+                        Some(limit) => cfg.maxmemory = limit,
+                        None => println!("Invalid memory amount: {val}"),
+                    },
+                    "--maxmemory-policy" => match MaxMemoryPolicy::try_from(val.as_str()) {
+                        Ok(policy) => cfg.maxmemory_policy = policy,
+                        Err(_) => println!("Invalid maxmemory-policy: {val}"),
+                    },
+                    "--maxmemory-samples" => match val.parse() {
+                        Ok(samples) if samples > 0 => cfg.maxmemory_samples = samples,
+                        _ => println!("Invalid maxmemory-samples: {val}"),
+                    },
                     cmd => println!("Unknown command: {cmd}"),
                 }
             }
@@ -42,3 +249,137 @@ impl From<&[String]> for Config {
         cfg
     }
 }
+
+/// Parses a memory amount the way redis.conf does: `1024`, `64k`, `512mb`, `1gb`...
+pub fn memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let unit = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    amount.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+pub fn yes_no(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+pub fn yes_no_name(on: bool) -> &'static str {
+    if on {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Parses save points as `seconds changes` pairs, like `900 1 300 10`.
+/// An empty value disables automatic snapshots.
+pub fn save_points(value: &str) -> Option<Vec<SavePoint>> {
+    let parts: Vec<_> = value.split_whitespace().collect();
+    if parts.len() % 2 != 0 {
+        return None;
+    }
+    parts
+        .chunks(2)
+        .map(|pair| {
+            let seconds = pair[0].parse().ok().filter(|s| *s > 0)?;
+            let changes = pair[1].parse().ok()?;
+            Some(SavePoint { seconds, changes })
+        })
+        .collect()
+}
+
+/// Formats save points back the way `CONFIG GET save` shows them.
+pub fn save_params(points: &[SavePoint]) -> String {
+    let pairs = points
+        .iter()
+        .map(|p| format!("{} {}", p.seconds, p.changes));
+    pairs.collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        assert_eq!(memory("1024"), Some(1024));
+        assert_eq!(memory("64kb"), Some(64 * 1024));
+        assert_eq!(memory("1GB"), Some(1024 * 1024 * 1024));
+        assert_eq!(memory("2m"), Some(2_000_000));
+        assert_eq!(memory("mb"), None);
+        assert_eq!(memory("10xb"), None);
+    }
+
+    #[test]
+    fn test_from_args() {
+        let args = ["--client-query-buffer-limit", "1mb", "--dir", "/tmp"].map(String::from);
+        let cfg = Config::from(args.as_slice());
+        assert_eq!(cfg.client_query_buffer_limit, 1024 * 1024);
+        assert_eq!(cfg.dir, PathBuf::from("/tmp"));
+        assert_eq!(cfg.save, Config::default().save);
+
+        let args = ["--save", ""].map(String::from);
+        assert!(Config::from(args.as_slice()).save.is_empty());
+
+        let args = ["--appendonly", "yes", "--appendfsync", "always"].map(String::from);
+        let cfg = Config::from(args.as_slice());
+        assert!(cfg.append_only);
+        assert_eq!(cfg.append_fsync, AppendFsync::Always);
+        assert!(cfg.rdb_compression);
+
+        let args = ["--rdbcompression", "no"].map(String::from);
+        assert!(!Config::from(args.as_slice()).rdb_compression);
+
+        let args = ["--databases", "4"].map(String::from);
+        assert_eq!(Config::from(args.as_slice()).databases, 4);
+        let args = ["--databases", "0"].map(String::from);
+        assert_eq!(Config::from(args.as_slice()).databases, 16);
+
+        let args = ["--maxmemory", "100mb", "--maxmemory-policy", "allkeys-LFU"].map(String::from);
+        let cfg = Config::from(args.as_slice());
+        assert_eq!(cfg.maxmemory, 100 * 1024 * 1024);
+        assert_eq!(cfg.maxmemory_policy, MaxMemoryPolicy::AllKeysLfu);
+        assert!(cfg.maxmemory_policy.is_lfu() && !cfg.maxmemory_policy.is_volatile());
+        let args = ["--maxmemory-policy", "lru"].map(String::from);
+        let cfg = Config::from(args.as_slice());
+        assert_eq!(cfg.maxmemory_policy, MaxMemoryPolicy::NoEviction);
+    }
+
+    #[test]
+    fn test_save_points() {
+        let points = save_points("900 1  300 10").unwrap();
+        assert_eq!(
+            points,
+            [
+                SavePoint {
+                    seconds: 900,
+                    changes: 1
+                },
+                SavePoint {
+                    seconds: 300,
+                    changes: 10
+                }
+            ]
+        );
+        assert_eq!(save_params(&points), "900 1 300 10");
+        assert_eq!(save_points(""), Some(vec![]));
+        assert_eq!(save_points("900"), None);
+        assert_eq!(save_points("0 1"), None);
+        assert_eq!(save_points("900 -1"), None);
+    }
+}
@@ -1,48 +1,133 @@
 mod codec;
-mod file;
+mod crc64;
+mod error;
+pub mod file;
+mod lzf;
 
-use anyhow::Result;
-use codec::Value;
-use file::{RedisFile, Section};
-use std::path::Path;
+use anyhow::{Context, Result};
+use file::{RedisFile, RedisFileWriter, Section};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufRead, BufWriter, Write},
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+pub use codec::{stream, Value};
+pub use error::RdbError;
+pub use file::{Entry, Usage};
+
+/// How snapshots are written.
+#[derive(Debug, Clone, Copy)]
+pub struct SaveOptions {
+    /// Compress large strings with LZF.
+    pub compression: bool,
+    /// End the file with its CRC-64, rather than zero.
+    pub checksum: bool,
+}
+
+impl Default for SaveOptions {
+    fn default() -> Self {
+        Self {
+            compression: true,
+            checksum: true,
+        }
+    }
+}
 
 pub trait Database {
-    fn all_keys(self) -> Vec<String>;
-    fn find(self, key: &str) -> Option<String>;
-}
-
-pub fn open_at(path: &Path) -> Result<impl Database> {
-    let file = RedisFile::build_at(path)?;
-    Ok(file)
-}
-
-impl RedisFile {
-    fn all_entries(self) -> impl Iterator<Item = (String, Value)> {
-        self.into_iter()
-            .flat_map(|s| match s {
-                Section::Entry(e) => Some(e),
-                _ => None,
-            })
-            .filter_map(|e| {
-                if e.is_expired() {
-                    None
-                } else {
-                    Some((e.key().clone(), e.val().clone()))
-                }
-            })
+    /// Every entry in the snapshot which has not expired yet, grouped by
+    /// database index. Fails on the first corrupt section, or when the
+    /// checksum does not match.
+    fn databases(self) -> Result<Vec<(usize, Vec<Entry>)>, RdbError>;
+}
+
+/// Opens the snapshot at `path`, failing when there is none.
+pub fn open_at(path: &Path, verify_checksum: bool) -> Result<impl Database> {
+    let file = RedisFile::open_at(path)?;
+    Ok(file.verify_checksum(verify_checksum))
+}
+
+/// Reads a snapshot from `reader`, like a socket or a buffer in memory.
+pub fn read_from(reader: impl BufRead, verify_checksum: bool) -> Result<impl Database> {
+    let file = RedisFile::read_from(reader)?;
+    Ok(file.verify_checksum(verify_checksum))
+}
+
+/// Writes a snapshot of `databases`, the entries of each database by index,
+/// to `path`. The file is written aside and renamed over the old one, so
+/// readers never see a partial snapshot. Every save gets its own temp file,
+/// so a snapshot and an AOF rewrite can run side by side.
+pub fn save_at(
+    path: &Path,
+    aux: &[(&str, String)],
+    databases: &[Vec<Entry>],
+    options: SaveOptions,
+) -> Result<()> {
+    let dir = path.parent().context("dir")?;
+    fs::create_dir_all(dir)?;
+    let temp = dir.join(format!(
+        "temp-{}-{}.rdb",
+        std::process::id(),
+        SAVES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let written = File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let file = write_to(BufWriter::new(file), aux, databases, options)?.into_inner()?;
+            file.sync_all()?;
+            Ok(())
+        });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
     }
+    written?;
+    fs::rename(&temp, path)?;
+    Ok(())
 }
 
-impl Database for RedisFile {
-    fn all_keys(self) -> Vec<String> {
-        self.all_entries().map(|(k, _)| k).collect()
+/// Saves started by this process, to name their temp files apart.
+static SAVES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a snapshot of `databases` to `writer`. The same input and options
+/// always give the same bytes.
+pub fn write_to<W: Write>(
+    writer: W,
+    aux: &[(&str, String)],
+    databases: &[Vec<Entry>],
+    options: SaveOptions,
+) -> Result<W> {
+    let mut writer = RedisFileWriter::new(writer, options)?;
+    for (key, value) in aux {
+        writer.aux(key.as_bytes(), value.as_bytes())?;
     }
+    for (db, entries) in databases.iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+        let expires = entries.iter().filter(|e| e.expires_at().is_some());
+        writer.select_db(db)?;
+        writer.resize_db(entries.len(), expires.count())?;
+        for entry in entries {
+            writer.entry(entry)?;
+        }
+    }
+    writer.finish()
+}
 
-    fn find(self, key: &str) -> Option<String> {
-        self.all_entries()
-            .find(|(k, _)| k == key)
-            .map(|(_, v)| match v {
-                Value::String(s) => s.clone(),
-            })
+impl<R: BufRead> Database for RedisFile<R> {
+    fn databases(self) -> Result<Vec<(usize, Vec<Entry>)>, RdbError> {
+        let mut databases = BTreeMap::<usize, Vec<Entry>>::new();
+        let mut db = 0;
+        for section in self {
+            match section? {
+                Section::Database(index) => db = index,
+                Section::Entry(e) if !e.is_expired() => databases.entry(db).or_default().push(e),
+                _ => {}
+            }
+        }
+        Ok(databases.into_iter().collect())
     }
 }
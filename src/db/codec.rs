@@ -1,6 +1,18 @@
+mod intset;
+mod listpack;
+pub mod stream;
+mod ziplist;
+mod zipmap;
+
+use super::error::{DecodeError, DecodeResult};
+use super::lzf;
 use anyhow::Result;
-use bytes::BytesMut;
-use std::{io::Read, time::Duration};
+use bytes::Bytes;
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+use stream::Stream;
 
 #[allow(dead_code)]
 #[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Copy)]
@@ -11,38 +23,292 @@ pub enum Kind {
     Set = 2,
     SortedSet = 3,
     Hash = 4,
+    SortedSet2 = 5,
+    Module2 = 7,
     ZipMap = 9,
     ZipList = 10,
     IntSet = 11,
     SortedSetZipList = 12,
     HashMapZipList = 13,
     QuickList = 14,
+    StreamListpacks = 15,
+    HashListpack = 16,
+    SortedSetListpack = 17,
+    QuickList2 = 18,
+    StreamListpacks2 = 19,
+    SetListpack = 20,
+    StreamListpacks3 = 21,
 }
 
 impl TryFrom<u8> for Kind {
-    type Error = anyhow::Error;
+    type Error = DecodeError;
 
-    fn try_from(value: u8) -> Result<Self> {
+    fn try_from(value: u8) -> DecodeResult<Self> {
         match value {
             0 => Ok(Self::String),
             1 => Ok(Self::List),
             2 => Ok(Self::Set),
             3 => Ok(Self::SortedSet),
             4 => Ok(Self::Hash),
+            5 => Ok(Self::SortedSet2),
+            7 => Ok(Self::Module2),
             9 => Ok(Self::ZipMap),
             10 => Ok(Self::ZipList),
             11 => Ok(Self::IntSet),
             12 => Ok(Self::SortedSetZipList),
             13 => Ok(Self::HashMapZipList),
             14 => Ok(Self::QuickList),
-            e => Err(anyhow::anyhow!("Unknown kind: {e}")),
+            15 => Ok(Self::StreamListpacks),
+            16 => Ok(Self::HashListpack),
+            17 => Ok(Self::SortedSetListpack),
+            18 => Ok(Self::QuickList2),
+            19 => Ok(Self::StreamListpacks2),
+            20 => Ok(Self::SetListpack),
+            21 => Ok(Self::StreamListpacks3),
+            e => Err(DecodeError::UnknownOpcode(e)),
         }
     }
 }
 
-#[derive(Debug, Clone)]
+/// A decoded value, whichever encoding it was stored with. Integers kept
+/// by the compact encodings are turned into their decimal form.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
-    String(String),
+    String(Bytes),
+    List(Vec<Bytes>),
+    Set(Vec<Bytes>),
+    SortedSet(Vec<(Bytes, f64)>),
+    Hash(Vec<(Bytes, Bytes)>),
+    Stream(Stream),
+}
+
+/// Container of a quicklist node holding a single large element.
+const QUICKLIST_NODE_PLAIN: usize = 1;
+/// Container of a quicklist node holding a listpack.
+const QUICKLIST_NODE_PACKED: usize = 2;
+
+impl Value {
+    /// The kind the value is written as, the most portable of those that
+    /// can hold it.
+    pub fn kind(&self) -> Kind {
+        match self {
+            Self::String(_) => Kind::String,
+            Self::List(_) => Kind::List,
+            Self::Set(_) => Kind::Set,
+            Self::SortedSet(_) => Kind::SortedSet2,
+            Self::Hash(_) => Kind::Hash,
+            Self::Stream(_) => Kind::StreamListpacks3,
+        }
+    }
+
+    /// Name of the type, as `TYPE` reports it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Hash(_) => "hash",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    pub fn read(kind: Kind, reader: &mut impl Read) -> DecodeResult<Self> {
+        let value = match kind {
+            Kind::String => Self::String(string::read(reader)?),
+            Kind::List => Self::List(strings(reader)?),
+            Kind::Set => Self::Set(strings(reader)?),
+            Kind::SortedSet | Kind::SortedSet2 => {
+                let len = length::read_len(reader)?;
+                let members = (0..len).map(|_| {
+                    let member = string::read(reader)?;
+                    let score = match kind {
+                        Kind::SortedSet => double::read_text(reader)?,
+                        _ => double::read_binary(reader)?,
+                    };
+                    Ok((member, score))
+                });
+                Self::SortedSet(members.collect::<DecodeResult<_>>()?)
+            }
+            Kind::Hash => pairs(strings_n(reader, 2)?).map(Self::Hash)?,
+            Kind::ZipMap => Self::Hash(zipmap::entries(&string::read(reader)?)?),
+            Kind::ZipList => Self::List(ziplist::entries(&string::read(reader)?)?),
+            Kind::IntSet => Self::Set(intset::entries(&string::read(reader)?)?),
+            Kind::SortedSetZipList => scores(ziplist::entries(&string::read(reader)?)?)?,
+            Kind::HashMapZipList => {
+                pairs(ziplist::entries(&string::read(reader)?)?).map(Self::Hash)?
+            }
+            Kind::QuickList => {
+                let nodes = length::read_len(reader)?;
+                let mut list = Vec::new();
+                for _ in 0..nodes {
+                    list.extend(ziplist::entries(&string::read(reader)?)?);
+                }
+                Self::List(list)
+            }
+            Kind::HashListpack => {
+                pairs(listpack::entries(&string::read(reader)?)?).map(Self::Hash)?
+            }
+            Kind::SortedSetListpack => scores(listpack::entries(&string::read(reader)?)?)?,
+            Kind::QuickList2 => {
+                let nodes = length::read_len(reader)?;
+                let mut list = Vec::new();
+                for _ in 0..nodes {
+                    let container = length::read_len(reader)?;
+                    let node = string::read(reader)?;
+                    match container {
+                        QUICKLIST_NODE_PLAIN => list.push(node),
+                        QUICKLIST_NODE_PACKED => list.extend(listpack::entries(&node)?),
+                        c => {
+                            let msg = format!("quicklist container {c}");
+                            return Err(DecodeError::UnsupportedEncoding(msg));
+                        }
+                    }
+                }
+                Self::List(list)
+            }
+            Kind::SetListpack => Self::Set(listpack::entries(&string::read(reader)?)?),
+            Kind::StreamListpacks | Kind::StreamListpacks2 | Kind::StreamListpacks3 => {
+                Self::Stream(stream::read(kind, reader)?)
+            }
+            k => return Err(DecodeError::UnsupportedEncoding(format!("{k:?} values"))),
+        };
+        Ok(value)
+    }
+
+    pub fn write_kind(&self, writer: &mut impl Write) -> Result<()> {
+        writer.write_all(&[self.kind() as u8])?;
+        Ok(())
+    }
+
+    /// Writes the value, with its strings LZF compressed when `compress`.
+    pub fn write(&self, writer: &mut impl Write, compress: bool) -> Result<()> {
+        let string = |writer: &mut _, s: &[u8]| match compress {
+            true => string::write_compressed(writer, s),
+            false => string::write(writer, s),
+        };
+        match self {
+            Self::String(s) => string(writer, s),
+            Self::List(items) | Self::Set(items) => {
+                length::write(writer, items.len())?;
+                items.iter().try_for_each(|i| string(writer, i))
+            }
+            Self::SortedSet(members) => {
+                length::write(writer, members.len())?;
+                members.iter().try_for_each(|(member, score)| {
+                    string(writer, member)?;
+                    double::write_binary(writer, *score)
+                })
+            }
+            Self::Hash(pairs) => {
+                length::write(writer, pairs.len())?;
+                pairs.iter().try_for_each(|(field, value)| {
+                    string(writer, field)?;
+                    string(writer, value)
+                })
+            }
+            Self::Stream(stream) => stream::write(writer, stream, compress),
+        }
+    }
+}
+
+fn strings(reader: &mut impl Read) -> DecodeResult<Vec<Bytes>> {
+    strings_n(reader, 1)
+}
+
+/// Reads a length followed by `per_item` times as many strings.
+fn strings_n(reader: &mut impl Read, per_item: usize) -> DecodeResult<Vec<Bytes>> {
+    let len = length::read_len(reader)?;
+    let count = len.checked_mul(per_item);
+    let count = count.ok_or_else(|| DecodeError::BadLength(format!("{len} items")))?;
+    (0..count).map(|_| string::read(reader)).collect()
+}
+
+fn pairs(items: Vec<Bytes>) -> DecodeResult<Vec<(Bytes, Bytes)>> {
+    if items.len() % 2 != 0 {
+        let msg = format!("{} elements for pairs", items.len());
+        return Err(DecodeError::BadLength(msg));
+    }
+    let mut items = items.into_iter();
+    let mut pairs = Vec::new();
+    while let (Some(field), Some(value)) = (items.next(), items.next()) {
+        pairs.push((field, value));
+    }
+    Ok(pairs)
+}
+
+fn scores(items: Vec<Bytes>) -> DecodeResult<Value> {
+    let pairs = pairs(items)?.into_iter().map(|(member, score)| {
+        let score = std::str::from_utf8(&score)
+            .ok()
+            .and_then(|s| s.parse().ok());
+        let score = score.ok_or_else(|| DecodeError::Corrupt("sorted set score".into()))?;
+        Ok((member, score))
+    });
+    Ok(Value::SortedSet(pairs.collect::<DecodeResult<_>>()?))
+}
+
+/// Reads exactly `len` bytes, without trusting `len` enough to allocate
+/// them upfront.
+fn read_bytes(reader: &mut impl Read, len: usize) -> DecodeResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    Read::take(&mut *reader, len as u64).read_to_end(&mut buf)?;
+    match buf.len() == len {
+        true => Ok(buf),
+        false => Err(DecodeError::Truncated),
+    }
+}
+
+/// The decimal form of `s`, if it holds one exactly.
+fn as_int(s: &[u8]) -> Option<i64> {
+    if s.is_empty() || s.len() > 20 {
+        return None;
+    }
+    let int: i64 = std::str::from_utf8(s).ok()?.parse().ok()?;
+    (int.to_string().as_bytes() == s).then_some(int)
+}
+
+/// Bounds checked reads over the blob of a compact encoding.
+struct Blob<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Blob<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn take(&mut self, len: usize) -> DecodeResult<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|e| *e <= self.buf.len());
+        let end = end.ok_or_else(|| self.truncated())?;
+        let bytes = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> DecodeResult<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> DecodeResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn peek(&self) -> DecodeResult<u8> {
+        let byte = self.buf.get(self.pos);
+        byte.copied().ok_or_else(|| self.truncated())
+    }
+
+    fn truncated(&self) -> DecodeError {
+        DecodeError::Corrupt(format!("blob, truncated at {}", self.pos))
+    }
 }
 
 pub mod length {
@@ -52,9 +318,10 @@ pub mod length {
     const LENGTH_READY: u8 = 0u8;
     const LENGTH_READ_MORE: u8 = 0b01000000u8;
     const LENGTH_NEXT_4: u8 = 0b10000000u8;
-    const LENGTH_FORMAT: u8 = 0b11000000u8;
+    const LENGTH_NEXT_8: u8 = 0b10000001u8;
+    pub(super) const LENGTH_FORMAT: u8 = 0b11000000u8;
+    pub(super) const FORMAT_LZF: u8 = 3;
 
-    #[allow(dead_code)]
     #[derive(Debug)]
     pub enum Length {
         Read(usize),
@@ -62,101 +329,337 @@ pub mod length {
         Compressed,
     }
 
-    impl Into<usize> for Length {
-        fn into(self) -> usize {
-            match self {
-                Self::Read(s) => s,
-                Self::Value(v) => v as usize,
-                Length::Compressed => panic!("to be implemented"),
-            }
-        }
-    }
-
-    pub fn read(reader: &mut impl Read) -> Result<Length> {
+    pub fn read(reader: &mut impl Read) -> DecodeResult<Length> {
         let mut buf = [0; 1];
         reader.read_exact(&mut buf)?;
         read_mask(buf[0], reader)
     }
 
-    fn read_mask(mask: u8, reader: &mut impl Read) -> Result<Length> {
-        let mask = match mask & LENGTH_BITMASK {
-            LENGTH_READY => Length::Read(mask as usize),
-            LENGTH_READ_MORE => {
-                let mut buf2 = [0; 1];
+    /// Reads a plain length, failing on the special string formats.
+    pub fn read_len(reader: &mut impl Read) -> DecodeResult<usize> {
+        match read(reader)? {
+            Length::Read(len) => Ok(len),
+            other => Err(DecodeError::BadLength(format!("{other:?} is not a length"))),
+        }
+    }
+
+    fn read_mask(mask: u8, reader: &mut impl Read) -> DecodeResult<Length> {
+        let mask = match mask {
+            LENGTH_NEXT_8 => {
+                let mut buf2 = [0; 8];
                 reader.read_exact(&mut buf2)?;
-                let val: usize = ((mask & !LENGTH_BITMASK) as usize) << 8;
-                let val = val | (buf2[0] as usize);
-                Length::Read(val)
+                let val = u64::from_be_bytes(buf2);
+                let val = usize::try_from(val);
+                Length::Read(val.map_err(|_| DecodeError::BadLength(format!("{mask:#04x}")))?)
             }
             LENGTH_NEXT_4 => {
                 let mut buf2 = [0; 4];
                 reader.read_exact(&mut buf2)?;
-                let val = u32::from_le_bytes(buf2) as usize;
+                let val = u32::from_be_bytes(buf2) as usize;
                 Length::Read(val)
             }
-            LENGTH_FORMAT => {
-                let len = mask & !LENGTH_BITMASK;
-                Length::Value(len)
-            }
-            _ => panic!("Unreachable"),
+            _ => match mask & LENGTH_BITMASK {
+                LENGTH_READY => Length::Read(mask as usize),
+                LENGTH_READ_MORE => {
+                    let mut buf2 = [0; 1];
+                    reader.read_exact(&mut buf2)?;
+                    let val: usize = ((mask & !LENGTH_BITMASK) as usize) << 8;
+                    let val = val | (buf2[0] as usize);
+                    Length::Read(val)
+                }
+                LENGTH_FORMAT => match mask & !LENGTH_BITMASK {
+                    FORMAT_LZF => Length::Compressed,
+                    len => Length::Value(len),
+                },
+                _ => {
+                    let msg = format!("unknown length prefix {mask:#04x}");
+                    return Err(DecodeError::BadLength(msg));
+                }
+            },
         };
         Ok(mask)
     }
+
+    pub fn write(writer: &mut impl Write, len: usize) -> Result<()> {
+        match len {
+            len if len < 1 << 6 => writer.write_all(&[len as u8])?,
+            len if len < 1 << 14 => {
+                writer.write_all(&[LENGTH_READ_MORE | (len >> 8) as u8, len as u8])?
+            }
+            len if len <= u32::MAX as usize => {
+                writer.write_all(&[LENGTH_NEXT_4])?;
+                writer.write_all(&(len as u32).to_be_bytes())?
+            }
+            len => {
+                writer.write_all(&[LENGTH_NEXT_8])?;
+                writer.write_all(&(len as u64).to_be_bytes())?
+            }
+        }
+        Ok(())
+    }
 }
 
 pub mod string {
     use super::length::Length;
     use super::*;
 
-    pub fn read(reader: &mut impl Read) -> Result<String> {
+    pub fn read(reader: &mut impl Read) -> DecodeResult<Bytes> {
         let kind = length::read(reader)?;
         let str = match kind {
-            Length::Read(len) => {
-                let mut buf = BytesMut::zeroed(len);
-                reader.read_exact(&mut buf)?;
-                std::str::from_utf8(&buf)?.to_string()
-            }
+            Length::Read(len) => read_bytes(reader, len)?.into(),
             Length::Value(len) => {
-                let val: u32 = match len {
+                let val: i64 = match len {
                     0 => {
                         let mut buf: [u8; 1] = [0; 1];
                         reader.read_exact(&mut buf)?;
-                        u8::from_le_bytes(buf) as u32
+                        i8::from_le_bytes(buf) as i64
                     }
                     1 => {
                         let mut buf: [u8; 2] = [0; 2];
                         reader.read_exact(&mut buf)?;
-                        u16::from_le_bytes(buf) as u32
+                        i16::from_le_bytes(buf) as i64
                     }
                     2 => {
                         let mut buf: [u8; 4] = [0; 4];
                         reader.read_exact(&mut buf)?;
-                        u32::from_le_bytes(buf)
+                        i32::from_le_bytes(buf) as i64
+                    }
+                    e => {
+                        let msg = format!("string format {e}");
+                        return Err(DecodeError::UnsupportedEncoding(msg));
                     }
-                    _ => panic!("Unsupported"),
                 };
-                val.to_string()
+                val.to_string().into()
+            }
+            Length::Compressed => {
+                let compressed = length::read_len(reader)?;
+                let len = length::read_len(reader)?;
+                let buf = read_bytes(reader, compressed)?;
+                lzf::decompress(&buf, len)?.into()
             }
-            Length::Compressed => panic!("Not implemented"),
         };
         Ok(str)
     }
+
+    /// Writes `s` like `write`, compressing it with LZF when it is large
+    /// enough and shrinks by more than the lengths it takes to store.
+    pub fn write_compressed(writer: &mut impl Write, s: &[u8]) -> Result<()> {
+        if s.len() <= 20 || as_int(s).is_some() {
+            return write(writer, s);
+        }
+        let compressed = lzf::compress(s);
+        if compressed.len() > s.len() - 4 {
+            return write(writer, s);
+        }
+        writer.write_all(&[length::LENGTH_FORMAT | length::FORMAT_LZF])?;
+        length::write(writer, compressed.len())?;
+        length::write(writer, s.len())?;
+        writer.write_all(&compressed)?;
+        Ok(())
+    }
+
+    /// Writes `s`, using the compact integer encodings when it holds the
+    /// canonical form of a 32 bit integer, as Redis does.
+    pub fn write(writer: &mut impl Write, s: &[u8]) -> Result<()> {
+        if let Some(int) = as_int(s).and_then(|i| i32::try_from(i).ok()) {
+            let format = length::LENGTH_FORMAT;
+            if let Ok(int) = i8::try_from(int) {
+                writer.write_all(&[format])?;
+                writer.write_all(&int.to_le_bytes())?;
+            } else if let Ok(int) = i16::try_from(int) {
+                writer.write_all(&[format | 1])?;
+                writer.write_all(&int.to_le_bytes())?;
+            } else {
+                writer.write_all(&[format | 2])?;
+                writer.write_all(&int.to_le_bytes())?;
+            }
+            return Ok(());
+        }
+        length::write(writer, s.len())?;
+        writer.write_all(s)?;
+        Ok(())
+    }
 }
 
 pub mod time {
     use super::*;
 
-    pub fn read_ms(reader: &mut impl Read) -> Result<Duration> {
+    pub fn read_ms(reader: &mut impl Read) -> DecodeResult<Duration> {
         let mut buf = [0u8; 8];
         reader.read_exact(&mut buf)?;
         let ts = u64::from_le_bytes(buf);
         Ok(Duration::from_millis(ts))
     }
 
-    pub fn read_sec(reader: &mut impl Read) -> Result<Duration> {
+    pub fn write_ms(writer: &mut impl Write, ts: Duration) -> Result<()> {
+        writer.write_all(&(ts.as_millis() as u64).to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn read_sec(reader: &mut impl Read) -> DecodeResult<Duration> {
         let mut buf = [0u8; 4];
         reader.read_exact(&mut buf)?;
         let ts = u32::from_le_bytes(buf);
         Ok(Duration::from_secs(ts as u64))
     }
 }
+
+pub mod double {
+    use super::*;
+
+    /// Reads a score of the first sorted set encoding, stored as text.
+    pub fn read_text(reader: &mut impl Read) -> DecodeResult<f64> {
+        let mut len = [0u8; 1];
+        reader.read_exact(&mut len)?;
+        let score = match len[0] {
+            253 => f64::NAN,
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            len => {
+                let buf = read_bytes(reader, len as usize)?;
+                let score = std::str::from_utf8(&buf).ok().and_then(|s| s.parse().ok());
+                score.ok_or_else(|| DecodeError::Corrupt("sorted set score".into()))?
+            }
+        };
+        Ok(score)
+    }
+
+    pub fn read_binary(reader: &mut impl Read) -> DecodeResult<f64> {
+        let mut buf = [0u8; 8];
+        reader.read_exact(&mut buf)?;
+        Ok(f64::from_le_bytes(buf))
+    }
+
+    pub fn write_binary(writer: &mut impl Write, score: f64) -> Result<()> {
+        writer.write_all(&score.to_le_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_length_round_trip() {
+        for len in [
+            0,
+            63,
+            64,
+            16383,
+            16384,
+            u32::MAX as usize,
+            u32::MAX as usize + 1,
+        ] {
+            let mut buf = Vec::new();
+            length::write(&mut buf, len).unwrap();
+            let read = length::read_len(&mut buf.as_slice()).unwrap();
+            assert_eq!(read, len);
+        }
+    }
+
+    #[test]
+    fn test_string_round_trip() {
+        let cases: [&[u8]; 7] = [b"", b"hello", b"-1", b"300", b"-70000", b"007", b"\xff\x00"];
+        for s in cases {
+            let mut buf = Vec::new();
+            string::write(&mut buf, s).unwrap();
+            assert_eq!(string::read(&mut buf.as_slice()).unwrap(), s);
+        }
+        let mut buf = Vec::new();
+        string::write(&mut buf, b"-2").unwrap();
+        assert_eq!(buf, [0xC0, 0xFE]);
+
+        let long = "redis ".repeat(100);
+        let mut buf = Vec::new();
+        string::write_compressed(&mut buf, long.as_bytes()).unwrap();
+        assert_eq!(buf[0], 0xC3);
+        assert!(buf.len() < long.len());
+        assert_eq!(string::read(&mut buf.as_slice()).unwrap(), long.as_bytes());
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        use stream::{Consumer, ConsumerGroup, PendingEntry, StreamEntry, StreamId};
+
+        let id = |ms, seq| StreamId { ms, seq };
+        let entry = |ms, seq, fields: &[(&str, &str)]| StreamEntry {
+            id: id(ms, seq),
+            fields: fields
+                .iter()
+                .map(|(f, v)| (Bytes::from(f.to_string()), Bytes::from(v.to_string())))
+                .collect(),
+        };
+        let stream = Stream {
+            entries: vec![
+                entry(1, 5, &[("a", "1"), ("b", "x")]),
+                entry(2, 0, &[("a", "2"), ("b", "y")]),
+                entry(3, 0, &[("c", "-3")]),
+            ],
+            length: 3,
+            last_id: id(3, 0),
+            first_id: id(1, 5),
+            max_deleted_id: id(0, 0),
+            entries_added: 4,
+            groups: vec![ConsumerGroup {
+                name: "g".into(),
+                last_id: id(2, 0),
+                entries_read: 2,
+                pending: vec![PendingEntry {
+                    id: id(2, 0),
+                    delivery_time: 1_700_000_000_000,
+                    delivery_count: 1,
+                }],
+                consumers: vec![Consumer {
+                    name: "c".into(),
+                    seen_time: 1_700_000_000_000,
+                    active_time: 1_700_000_000_001,
+                    pending: vec![id(2, 0)],
+                }],
+            }],
+        };
+        let values = [
+            Value::String("s".into()),
+            Value::List(vec!["a".into(), "1".into()]),
+            Value::Set(vec!["x".into()]),
+            Value::SortedSet(vec![("m".into(), 1.5), ("n".into(), f64::INFINITY)]),
+            Value::Hash(vec![("f".into(), "v".into())]),
+            Value::Stream(stream),
+            Value::Stream(Stream::default()),
+        ];
+        for value in values {
+            for compress in [false, true] {
+                let mut buf = Vec::new();
+                value.write(&mut buf, compress).unwrap();
+                let read = Value::read(value.kind(), &mut buf.as_slice()).unwrap();
+                assert_eq!(read, value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_compact() {
+        // A listpack hash and a quicklist with a plain and a packed node.
+        let hash = listpack::write(&["f", "v", "n", "1"]);
+        let mut buf = Vec::new();
+        string::write(&mut buf, &hash).unwrap();
+        let read = Value::read(Kind::HashListpack, &mut buf.as_slice()).unwrap();
+        let pairs = vec![("f".into(), "v".into()), ("n".into(), "1".into())];
+        assert_eq!(read, Value::Hash(pairs));
+
+        let mut buf = vec![2, 1];
+        string::write(&mut buf, b"plain").unwrap();
+        buf.push(2);
+        string::write(&mut buf, &listpack::write(&["a", "7"])).unwrap();
+        let read = Value::read(Kind::QuickList2, &mut buf.as_slice()).unwrap();
+        assert_eq!(
+            read,
+            Value::List(vec!["plain".into(), "a".into(), "7".into()])
+        );
+
+        let mut buf = vec![1, 1, b'm', 3];
+        buf.extend(b"2.5");
+        let read = Value::read(Kind::SortedSet, &mut buf.as_slice()).unwrap();
+        assert_eq!(read, Value::SortedSet(vec![("m".into(), 2.5)]));
+    }
+}
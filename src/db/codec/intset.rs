@@ -0,0 +1,38 @@
+//! Intsets, the encoding of small sets made only of integers.
+
+use super::{Blob, DecodeError, DecodeResult};
+use bytes::Bytes;
+
+/// Every member of an intset, in their decimal form.
+pub fn entries(blob: &[u8]) -> DecodeResult<Vec<Bytes>> {
+    let mut blob = Blob::new(blob);
+    let encoding = u32::from_le_bytes(blob.array()?);
+    let len = u32::from_le_bytes(blob.array()?);
+    (0..len)
+        .map(|_| {
+            let int = match encoding {
+                2 => i16::from_le_bytes(blob.array()?) as i64,
+                4 => i32::from_le_bytes(blob.array()?) as i64,
+                8 => i64::from_le_bytes(blob.array()?),
+                e => {
+                    return Err(DecodeError::UnsupportedEncoding(format!(
+                        "Unknown intset encoding: {e}"
+                    )))
+                }
+            };
+            Ok(int.to_string().into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let blob = b"\x04\x00\x00\x00\x02\x00\x00\x00\xff\xff\xff\xff\x00\x00\x01\x00";
+        assert_eq!(entries(blob).unwrap(), ["-1", "65536"]);
+        assert!(entries(b"\x03\x00\x00\x00\x01\x00\x00\x00\x00").is_err());
+    }
+}
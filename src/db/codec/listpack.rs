@@ -0,0 +1,171 @@
+//! Listpacks, the compact encoding of small lists, sets, hashes, sorted sets
+//! and stream nodes since Redis 7.
+
+use super::{Blob, DecodeError, DecodeResult};
+use bytes::Bytes;
+
+const LP_END: u8 = 0xFF;
+const HEADER_SIZE: usize = 6;
+const NUMELE_UNKNOWN: usize = u16::MAX as usize;
+
+/// Every element of a listpack, integers in their decimal form.
+pub fn entries(blob: &[u8]) -> DecodeResult<Vec<Bytes>> {
+    let mut blob = Blob::new(blob);
+    blob.take(HEADER_SIZE)?;
+
+    let mut entries = Vec::new();
+    while blob.peek()? != LP_END {
+        let start = blob.position();
+        let entry = element(&mut blob)?;
+        blob.take(backlen_size(blob.position() - start))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn element(blob: &mut Blob) -> DecodeResult<Bytes> {
+    let encoding = blob.u8()?;
+    let int = match encoding {
+        0x00..=0x7F => encoding as i64,
+        0x80..=0xBF => {
+            return Ok(Bytes::copy_from_slice(
+                blob.take((encoding & 0x3f) as usize)?,
+            ))
+        }
+        0xC0..=0xDF => {
+            let int = ((encoding & 0x1f) as i64) << 8 | blob.u8()? as i64;
+            if int >= 1 << 12 {
+                int - (1 << 13)
+            } else {
+                int
+            }
+        }
+        0xE0..=0xEF => {
+            let len = ((encoding & 0x0f) as usize) << 8 | blob.u8()? as usize;
+            return Ok(Bytes::copy_from_slice(blob.take(len)?));
+        }
+        0xF0 => {
+            let len = u32::from_le_bytes(blob.array()?) as usize;
+            return Ok(Bytes::copy_from_slice(blob.take(len)?));
+        }
+        0xF1 => i16::from_le_bytes(blob.array()?) as i64,
+        0xF2 => {
+            let [a, b, c] = blob.array()?;
+            (i32::from_le_bytes([0, a, b, c]) >> 8) as i64
+        }
+        0xF3 => i32::from_le_bytes(blob.array()?) as i64,
+        0xF4 => i64::from_le_bytes(blob.array()?),
+        e => {
+            return Err(DecodeError::UnsupportedEncoding(format!(
+                "Unknown listpack encoding: {e:#04x}"
+            )))
+        }
+    };
+    Ok(int.to_string().into())
+}
+
+/// Bytes taken by the back length of an element `len` bytes long.
+fn backlen_size(len: usize) -> usize {
+    match len {
+        0..=127 => 1,
+        128..=16382 => 2,
+        16383..=2097150 => 3,
+        2097151..=268435454 => 4,
+        _ => 5,
+    }
+}
+
+/// Encodes `elements` as a listpack, storing those holding canonical
+/// integers in the smallest integer encoding, like Redis does.
+pub fn write<T: AsRef<[u8]>>(elements: &[T]) -> Vec<u8> {
+    let mut buf = vec![0; HEADER_SIZE];
+    for element in elements {
+        let start = buf.len();
+        encode(&mut buf, element.as_ref());
+        let len = buf.len() - start;
+        let size = backlen_size(len);
+        for i in (0..size).rev() {
+            let byte = (len >> (7 * i)) as u8 & 0x7f;
+            buf.push(if i == size - 1 { byte } else { byte | 0x80 });
+        }
+    }
+    buf.push(LP_END);
+
+    let total = buf.len() as u32;
+    let count = elements.len().min(NUMELE_UNKNOWN) as u16;
+    buf[..4].copy_from_slice(&total.to_le_bytes());
+    buf[4..6].copy_from_slice(&count.to_le_bytes());
+    buf
+}
+
+fn encode(buf: &mut Vec<u8>, element: &[u8]) {
+    match super::as_int(element) {
+        Some(int @ 0..=127) => buf.push(int as u8),
+        Some(int @ -4096..=4095) => {
+            let int = int as u16 & 0x1fff;
+            buf.extend([0xC0 | (int >> 8) as u8, int as u8]);
+        }
+        Some(int) if i16::try_from(int).is_ok() => {
+            buf.push(0xF1);
+            buf.extend((int as i16).to_le_bytes());
+        }
+        Some(int @ -8388608..=8388607) => {
+            buf.push(0xF2);
+            buf.extend(&(int as i32).to_le_bytes()[..3]);
+        }
+        Some(int) if i32::try_from(int).is_ok() => {
+            buf.push(0xF3);
+            buf.extend((int as i32).to_le_bytes());
+        }
+        Some(int) => {
+            buf.push(0xF4);
+            buf.extend(int.to_le_bytes());
+        }
+        None => {
+            let len = element.len();
+            match len {
+                0..=63 => buf.push(0x80 | len as u8),
+                64..=4095 => buf.extend([0xE0 | (len >> 8) as u8, len as u8]),
+                _ => {
+                    buf.push(0xF0);
+                    buf.extend((len as u32).to_le_bytes());
+                }
+            }
+            buf.extend(element);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let long = "x".repeat(5000);
+        let elements = [
+            "a",
+            "0",
+            "127",
+            "128",
+            "-4096",
+            "30000",
+            "-8388608",
+            "2147483647",
+            "-9223372036854775808",
+            "007",
+            &long,
+        ];
+        let blob = write(&elements);
+        assert_eq!(entries(&blob).unwrap(), elements);
+        assert_eq!(&blob[4..6], &[elements.len() as u8, 0]);
+        assert_eq!(*blob.last().unwrap(), LP_END);
+    }
+
+    #[test]
+    fn test_entries() {
+        // `lpNew` followed by pushing "hi", 12 and -1, as Redis encodes them.
+        let blob = b"\x10\x00\x00\x00\x03\x00\x82hi\x03\x0c\x01\xdf\xff\x02\xff";
+        assert_eq!(entries(blob).unwrap(), ["hi", "12", "-1"]);
+    }
+}
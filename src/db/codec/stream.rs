@@ -0,0 +1,304 @@
+//! Streams, stored as a radix tree of listpack nodes followed by their
+//! metadata and consumer groups.
+
+use super::{length, listpack, string, time, DecodeError, DecodeResult, Kind};
+use anyhow::Result;
+use bytes::Bytes;
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+const FLAG_DELETED: i64 = 1;
+const FLAG_SAMEFIELDS: i64 = 2;
+/// `entries_read` of a consumer group whose position is unknown.
+const INVALID_ENTRIES_READ: u64 = u64::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    fn read_raw(reader: &mut impl Read) -> DecodeResult<Self> {
+        let mut buf = [0u8; 16];
+        reader.read_exact(&mut buf)?;
+        Self::from_raw(&buf)
+    }
+
+    fn from_raw(raw: &[u8]) -> DecodeResult<Self> {
+        if raw.len() != 16 {
+            let msg = format!("stream ID of {} bytes", raw.len());
+            return Err(DecodeError::BadLength(msg));
+        }
+        let mut ms = [0u8; 8];
+        let mut seq = [0u8; 8];
+        ms.copy_from_slice(&raw[..8]);
+        seq.copy_from_slice(&raw[8..]);
+        Ok(Self {
+            ms: u64::from_be_bytes(ms),
+            seq: u64::from_be_bytes(seq),
+        })
+    }
+
+    fn raw(&self) -> [u8; 16] {
+        let mut raw = [0u8; 16];
+        raw[..8].copy_from_slice(&self.ms.to_be_bytes());
+        raw[8..].copy_from_slice(&self.seq.to_be_bytes());
+        raw
+    }
+
+    fn read(reader: &mut impl Read) -> DecodeResult<Self> {
+        let ms = read_len(reader)?;
+        let seq = read_len(reader)?;
+        Ok(Self { ms, seq })
+    }
+
+    fn write(&self, writer: &mut impl Write) -> Result<()> {
+        length::write(writer, self.ms as usize)?;
+        length::write(writer, self.seq as usize)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(Bytes, Bytes)>,
+}
+
+/// A message delivered to a group but not acknowledged yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingEntry {
+    pub id: StreamId,
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Consumer {
+    pub name: Bytes,
+    pub seen_time: u64,
+    pub active_time: u64,
+    pub pending: Vec<StreamId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerGroup {
+    pub name: Bytes,
+    pub last_id: StreamId,
+    pub entries_read: u64,
+    pub pending: Vec<PendingEntry>,
+    pub consumers: Vec<Consumer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Stream {
+    pub entries: Vec<StreamEntry>,
+    pub length: u64,
+    pub last_id: StreamId,
+    pub first_id: StreamId,
+    pub max_deleted_id: StreamId,
+    pub entries_added: u64,
+    pub groups: Vec<ConsumerGroup>,
+}
+
+fn read_len(reader: &mut impl Read) -> DecodeResult<u64> {
+    Ok(length::read_len(reader)? as u64)
+}
+
+fn read_ms(reader: &mut impl Read) -> DecodeResult<u64> {
+    Ok(time::read_ms(reader)?.as_millis() as u64)
+}
+
+fn write_ms(writer: &mut impl Write, ms: u64) -> Result<()> {
+    time::write_ms(writer, Duration::from_millis(ms))
+}
+
+/// Reads a stream in any of the layouts of `kind`, which added metadata
+/// to streams and consumers over time.
+pub fn read(kind: Kind, reader: &mut impl Read) -> DecodeResult<Stream> {
+    let mut stream = Stream::default();
+    for _ in 0..read_len(reader)? {
+        let master = StreamId::from_raw(&string::read(reader)?)?;
+        let node = listpack::entries(&string::read(reader)?)?;
+        read_node(master, &node, &mut stream.entries)?;
+    }
+
+    stream.length = read_len(reader)?;
+    stream.last_id = StreamId::read(reader)?;
+    if kind >= Kind::StreamListpacks2 {
+        stream.first_id = StreamId::read(reader)?;
+        stream.max_deleted_id = StreamId::read(reader)?;
+        stream.entries_added = read_len(reader)?;
+    } else {
+        stream.first_id = stream.entries.first().map(|e| e.id).unwrap_or_default();
+        stream.entries_added = stream.length;
+    }
+
+    for _ in 0..read_len(reader)? {
+        let name = string::read(reader)?;
+        let last_id = StreamId::read(reader)?;
+        let entries_read = match kind >= Kind::StreamListpacks2 {
+            true => read_len(reader)?,
+            false => INVALID_ENTRIES_READ,
+        };
+        let pending = (0..read_len(reader)?)
+            .map(|_| {
+                Ok(PendingEntry {
+                    id: StreamId::read_raw(reader)?,
+                    delivery_time: read_ms(reader)?,
+                    delivery_count: read_len(reader)?,
+                })
+            })
+            .collect::<DecodeResult<_>>()?;
+        let consumers = (0..read_len(reader)?)
+            .map(|_| {
+                let name = string::read(reader)?;
+                let seen_time = read_ms(reader)?;
+                let active_time = match kind >= Kind::StreamListpacks3 {
+                    true => read_ms(reader)?,
+                    false => seen_time,
+                };
+                let pending = (0..read_len(reader)?)
+                    .map(|_| StreamId::read_raw(reader))
+                    .collect::<DecodeResult<_>>()?;
+                Ok(Consumer {
+                    name,
+                    seen_time,
+                    active_time,
+                    pending,
+                })
+            })
+            .collect::<DecodeResult<_>>()?;
+        stream.groups.push(ConsumerGroup {
+            name,
+            last_id,
+            entries_read,
+            pending,
+            consumers,
+        });
+    }
+    Ok(stream)
+}
+
+/// Decodes the entries of a node. Its first element is a master entry with
+/// the fields most entries share, which later entries may refer to.
+fn read_node(master: StreamId, node: &[Bytes], entries: &mut Vec<StreamEntry>) -> DecodeResult<()> {
+    let corrupt = |what: &str| DecodeError::Corrupt(format!("stream node, {what}"));
+    let mut items = node.iter();
+    let mut next = || items.next().ok_or_else(|| corrupt("truncated"));
+    let int = |item: &Bytes| -> DecodeResult<i64> {
+        let int = std::str::from_utf8(item).ok().and_then(|s| s.parse().ok());
+        int.ok_or_else(|| corrupt("invalid integer"))
+    };
+
+    let count = int(next()?)?;
+    let deleted = int(next()?)?;
+    let master_fields = (0..int(next()?)?)
+        .map(|_| next().cloned())
+        .collect::<DecodeResult<Vec<_>>>()?;
+    next()?;
+
+    for _ in 0..count.saturating_add(deleted) {
+        let flags = int(next()?)?;
+        let id = StreamId {
+            ms: master.ms.wrapping_add(int(next()?)? as u64),
+            seq: master.seq.wrapping_add(int(next()?)? as u64),
+        };
+        let fields = if flags & FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Ok((field.clone(), next()?.clone())))
+                .collect::<DecodeResult<Vec<_>>>()?
+        } else {
+            (0..int(next()?)?)
+                .map(|_| Ok((next()?.clone(), next()?.clone())))
+                .collect::<DecodeResult<Vec<_>>>()?
+        };
+        next()?;
+        if flags & FLAG_DELETED == 0 {
+            entries.push(StreamEntry { id, fields });
+        }
+    }
+    Ok(())
+}
+
+/// Writes `stream` in the latest layout, with all its entries in one node.
+pub fn write(writer: &mut impl Write, stream: &Stream, compress: bool) -> Result<()> {
+    match stream.entries.first() {
+        Some(first) => {
+            length::write(writer, 1)?;
+            string::write(writer, &first.id.raw())?;
+            let node = write_node(first, &stream.entries);
+            match compress {
+                true => string::write_compressed(writer, &node)?,
+                false => string::write(writer, &node)?,
+            }
+        }
+        None => length::write(writer, 0)?,
+    }
+
+    length::write(writer, stream.length as usize)?;
+    stream.last_id.write(writer)?;
+    stream.first_id.write(writer)?;
+    stream.max_deleted_id.write(writer)?;
+    length::write(writer, stream.entries_added as usize)?;
+
+    length::write(writer, stream.groups.len())?;
+    for group in &stream.groups {
+        string::write(writer, &group.name)?;
+        group.last_id.write(writer)?;
+        length::write(writer, group.entries_read as usize)?;
+        length::write(writer, group.pending.len())?;
+        for pending in &group.pending {
+            writer.write_all(&pending.id.raw())?;
+            write_ms(writer, pending.delivery_time)?;
+            length::write(writer, pending.delivery_count as usize)?;
+        }
+        length::write(writer, group.consumers.len())?;
+        for consumer in &group.consumers {
+            string::write(writer, &consumer.name)?;
+            write_ms(writer, consumer.seen_time)?;
+            write_ms(writer, consumer.active_time)?;
+            length::write(writer, consumer.pending.len())?;
+            for id in &consumer.pending {
+                writer.write_all(&id.raw())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_node(master: &StreamEntry, entries: &[StreamEntry]) -> Vec<u8> {
+    let int = |i: i64| Bytes::from(i.to_string());
+    let mut items = vec![int(entries.len() as i64), int(0)];
+    items.push(int(master.fields.len() as i64));
+    items.extend(master.fields.iter().map(|(field, _)| field.clone()));
+    items.push(int(0));
+
+    for entry in entries {
+        let same_fields = entry.fields.len() == master.fields.len()
+            && entry
+                .fields
+                .iter()
+                .zip(&master.fields)
+                .all(|(a, b)| a.0 == b.0);
+        let flags = if same_fields { FLAG_SAMEFIELDS } else { 0 };
+        items.push(int(flags));
+        items.push(int(entry.id.ms.wrapping_sub(master.id.ms) as i64));
+        items.push(int(entry.id.seq.wrapping_sub(master.id.seq) as i64));
+        let fields = entry.fields.len() as i64;
+        if same_fields {
+            items.extend(entry.fields.iter().map(|(_, value)| value.clone()));
+            items.push(int(fields + 3));
+        } else {
+            items.push(int(fields));
+            for (field, value) in &entry.fields {
+                items.extend([field.clone(), value.clone()]);
+            }
+            items.push(int(fields * 2 + 4));
+        }
+    }
+    listpack::write(&items)
+}
@@ -0,0 +1,79 @@
+//! Ziplists, the compact encoding Redis used for small lists, hashes and
+//! sorted sets before listpacks replaced it.
+
+use super::{Blob, DecodeError, DecodeResult};
+use bytes::Bytes;
+
+const ZIP_END: u8 = 0xFF;
+const ZIP_BIG_PREVLEN: u8 = 0xFE;
+const HEADER_SIZE: usize = 10;
+
+/// Every entry of a ziplist, integers in their decimal form.
+pub fn entries(blob: &[u8]) -> DecodeResult<Vec<Bytes>> {
+    let mut blob = Blob::new(blob);
+    blob.take(HEADER_SIZE)?;
+
+    let mut entries = Vec::new();
+    loop {
+        match blob.u8()? {
+            ZIP_END => break,
+            ZIP_BIG_PREVLEN => {
+                blob.take(4)?;
+            }
+            _ => {}
+        }
+        let encoding = blob.u8()?;
+        let entry = match encoding >> 6 {
+            0b00 => blob.take((encoding & 0x3f) as usize)?.to_vec(),
+            0b01 => {
+                let len = ((encoding & 0x3f) as usize) << 8 | blob.u8()? as usize;
+                blob.take(len)?.to_vec()
+            }
+            0b10 => {
+                let len = u32::from_be_bytes(blob.array()?) as usize;
+                blob.take(len)?.to_vec()
+            }
+            _ => integer(encoding, &mut blob)?.to_string().into_bytes(),
+        };
+        entries.push(entry.into());
+    }
+    Ok(entries)
+}
+
+fn integer(encoding: u8, blob: &mut Blob) -> DecodeResult<i64> {
+    let int = match encoding {
+        0xC0 => i16::from_le_bytes(blob.array()?) as i64,
+        0xD0 => i32::from_le_bytes(blob.array()?) as i64,
+        0xE0 => i64::from_le_bytes(blob.array()?),
+        0xF0 => {
+            let [a, b, c] = blob.array()?;
+            (i32::from_le_bytes([0, a, b, c]) >> 8) as i64
+        }
+        0xFE => i8::from_le_bytes(blob.array()?) as i64,
+        0xF1..=0xFD => (encoding & 0x0f) as i64 - 1,
+        e => {
+            return Err(DecodeError::UnsupportedEncoding(format!(
+                "Unknown ziplist encoding: {e:#04x}"
+            )))
+        }
+    };
+    Ok(int)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let mut blob = vec![0; HEADER_SIZE];
+        blob.extend(b"\x00\x05hello");
+        blob.extend(b"\x07\xf3");
+        blob.extend(b"\x02\xfe\x9c");
+        blob.extend(b"\x03\xc0\x00\x80");
+        blob.extend(b"\x04\xf0\xff\xff\x7f");
+        blob.push(ZIP_END);
+        let entries = entries(&blob).unwrap();
+        assert_eq!(entries, ["hello", "2", "-100", "-32768", "8388607"]);
+    }
+}
@@ -0,0 +1,50 @@
+//! Zipmaps, the encoding of small hashes before Redis 2.6.
+
+use super::{Blob, DecodeError, DecodeResult};
+use bytes::Bytes;
+
+const ZIPMAP_BIGLEN: u8 = 254;
+const ZIPMAP_END: u8 = 255;
+
+/// Every field and value of a zipmap.
+pub fn entries(blob: &[u8]) -> DecodeResult<Vec<(Bytes, Bytes)>> {
+    let mut blob = Blob::new(blob);
+    blob.u8()?;
+
+    let mut entries = Vec::new();
+    while let Some(field_len) = len(&mut blob)? {
+        let field = Bytes::copy_from_slice(blob.take(field_len)?);
+        let value_len = len(&mut blob)?;
+        let value_len =
+            value_len.ok_or_else(|| DecodeError::Corrupt("zipmap, field without value".into()))?;
+        let free = blob.u8()? as usize;
+        let value = Bytes::copy_from_slice(blob.take(value_len)?);
+        blob.take(free)?;
+        entries.push((field, value));
+    }
+    Ok(entries)
+}
+
+fn len(blob: &mut Blob) -> DecodeResult<Option<usize>> {
+    let len = match blob.u8()? {
+        ZIPMAP_END => return Ok(None),
+        ZIPMAP_BIGLEN => u32::from_le_bytes(blob.array()?) as usize,
+        len => len as usize,
+    };
+    Ok(Some(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries() {
+        let blob = b"\x02\x03foo\x03\x01bar\x00\x01a\x01\x00b\xff";
+        let entries = entries(blob).unwrap();
+        assert_eq!(
+            entries,
+            [("foo".into(), "bar".into()), ("a".into(), "b".into())]
+        );
+    }
+}
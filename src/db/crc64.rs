@@ -0,0 +1,44 @@
+//! CRC-64 with the Jones polynomial, as Redis uses to checksum RDB files:
+//! reflected input and output, `0xad93d23594c935a9`, no initial or final xor.
+
+const POLY: u64 = 0x95ac_9329_ac4b_c9b5; // 0xad93d23594c935a9 bit-reversed.
+
+const TABLE: [u64; 256] = table();
+
+const fn table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn update(crc: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(crc, |crc, b| {
+        TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(update(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+        let split = update(update(0, b"1234"), b"56789");
+        assert_eq!(split, 0xe9c6_d914_c4b8_d9ca);
+    }
+}
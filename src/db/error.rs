@@ -0,0 +1,42 @@
+use std::io;
+use thiserror::Error;
+
+/// Why part of a snapshot could not be decoded.
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("unexpected end of file")]
+    Truncated,
+    #[error("unknown opcode {0:#04x}")]
+    UnknownOpcode(u8),
+    #[error("unsupported opcode {0:#04x}, {1}")]
+    UnsupportedOpcode(u8, &'static str),
+    #[error("unsupported encoding: {0}")]
+    UnsupportedEncoding(String),
+    #[error("bad length: {0}")]
+    BadLength(String),
+    #[error("corrupt {0}")]
+    Corrupt(String),
+    #[error("checksum mismatch, stored {stored:#018x} but computed {computed:#018x}")]
+    Checksum { stored: u64, computed: u64 },
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(value: io::Error) -> Self {
+        match value.kind() {
+            io::ErrorKind::UnexpectedEof => Self::Truncated,
+            _ => Self::Io(value),
+        }
+    }
+}
+
+pub type DecodeResult<T> = Result<T, DecodeError>;
+
+/// A decoding error, with the offset in the file where it was found.
+#[derive(Debug, Error)]
+#[error("Bad RDB file at offset {offset}: {error}")]
+pub struct RdbError {
+    pub offset: usize,
+    pub error: DecodeError,
+}
@@ -1,9 +1,11 @@
 use crate::db::codec::{self, length, Kind, Value};
+use crate::db::error::{DecodeError, DecodeResult, RdbError};
+use crate::db::{crc64, SaveOptions};
 use anyhow::{Context, Result};
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use std::io::{BufRead, BufReader};
 use std::{
-    fs::{self, File},
+    fs::File,
     io::{Read, Write},
     path::Path,
     time::{Duration, SystemTime, UNIX_EPOCH},
@@ -13,28 +15,39 @@ const REDIS_RDB: &[u8] = b"REDIS";
 const REDIS_VER: &str = "0011";
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
 pub enum OpCode {
-    EOF,
-    SelectDB,
-    ExpireTime,
-    ExpireTimeMs,
-    ResizeDB,
-    Aux,
+    Eof = 0xFF,
+    SelectDB = 0xFE,
+    ExpireTime = 0xFD,
+    ExpireTimeMs = 0xFC,
+    ResizeDB = 0xFB,
+    Aux = 0xFA,
+    Freq = 0xF9,
+    Idle = 0xF8,
+    ModuleAux = 0xF7,
+    FunctionPreGa = 0xF6,
+    Function2 = 0xF5,
 }
 
 impl TryFrom<u8> for OpCode {
-    type Error = anyhow::Error;
+    type Error = DecodeError;
 
-    fn try_from(value: u8) -> Result<Self> {
+    fn try_from(value: u8) -> DecodeResult<Self> {
         match value {
-            0xFF => Ok(Self::EOF),
+            0xFF => Ok(Self::Eof),
             0xFE => Ok(Self::SelectDB),
             0xFD => Ok(Self::ExpireTime),
             0xFC => Ok(Self::ExpireTimeMs),
             0xFB => Ok(Self::ResizeDB),
             0xFA => Ok(Self::Aux),
-            _ => Err(anyhow::anyhow!("Not an OpCode")),
+            0xF9 => Ok(Self::Freq),
+            0xF8 => Ok(Self::Idle),
+            0xF7 => Ok(Self::ModuleAux),
+            0xF6 => Ok(Self::FunctionPreGa),
+            0xF5 => Ok(Self::Function2),
+            e => Err(DecodeError::UnknownOpcode(e)),
         }
     }
 }
@@ -48,21 +61,48 @@ pub enum AuxKey {
     UsedMem,
 }
 
+#[allow(dead_code)]
 #[derive(Debug)]
-pub struct Aux(String, String);
+pub struct Aux(Bytes, Bytes);
 
 impl Aux {
-    fn read(reader: &mut impl Read) -> Result<Aux> {
+    pub fn key(&self) -> &Bytes {
+        &self.0
+    }
+
+    pub fn value(&self) -> &Bytes {
+        &self.1
+    }
+
+    fn read(reader: &mut impl Read) -> DecodeResult<Aux> {
         let key = codec::string::read(reader)?;
         let value = codec::string::read(reader)?;
         Ok(Aux(key, value))
     }
 }
 
+/// How a key was used, saved before it under the LRU and LFU policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    /// Seconds since the key was last accessed.
+    Idle(u64),
+    /// Logarithmic access counter.
+    Freq(u8),
+}
+
 #[derive(Debug)]
-pub struct Entry(Option<Duration>, String, Value);
+pub struct Entry(Option<Duration>, Bytes, Value, Option<Usage>);
 
 impl Entry {
+    pub fn new(key: Bytes, value: Value, expires_at: Option<SystemTime>) -> Self {
+        let ts = expires_at.map(|t| t.duration_since(UNIX_EPOCH).unwrap_or_default());
+        Self(ts, key, value, None)
+    }
+
+    pub fn with_usage(self, usage: Option<Usage>) -> Self {
+        Self(self.0, self.1, self.2, usage)
+    }
+
     pub fn is_expired(&self) -> bool {
         if let Some(ts) = self.0 {
             let date = UNIX_EPOCH.checked_add(ts);
@@ -72,13 +112,25 @@ impl Entry {
         }
     }
 
-    pub fn key(&self) -> &String {
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.0.and_then(|ts| UNIX_EPOCH.checked_add(ts))
+    }
+
+    pub fn key(&self) -> &Bytes {
         &self.1
     }
 
     pub fn val(&self) -> &Value {
         &self.2
     }
+
+    pub fn usage(&self) -> Option<Usage> {
+        self.3
+    }
+
+    pub fn into_parts(self) -> (Bytes, Value) {
+        (self.1, self.2)
+    }
 }
 
 #[allow(dead_code)]
@@ -90,110 +142,515 @@ pub enum Section {
     Database(usize),
     Resize(usize, usize),
     Entry(Entry),
+    /// Source of a function library, which is not loaded.
+    Function(Bytes),
+    Eof,
 }
 
 impl Section {
-    fn read(reader: &mut impl BufRead) -> Result<Self> {
+    fn read(reader: &mut impl BufRead) -> DecodeResult<Self> {
         let buf = reader.fill_buf()?;
         if buf.is_empty() {
-            return Err(anyhow::anyhow!("Buffer is empty: EOF?"));
+            return Err(DecodeError::Truncated);
         }
 
         // TODO: Use a `let...else` once Codecrafters updates this toolchain :'(.
-        let code = OpCode::try_from(buf[0]);
-        if code.is_err() {
-            return Self::key_value(None, reader);
-        }
-        let code = code.unwrap();
+        let code = match OpCode::try_from(buf[0]) {
+            Ok(code) => code,
+            Err(_) => return Self::key_value(None, None, reader),
+        };
 
         reader.consume(1);
         match code {
             OpCode::Aux => Aux::read(reader).map(Section::Aux),
-            OpCode::SelectDB => length::read(reader)
-                .map(|len| len.into())
-                .map(Section::Database),
+            OpCode::SelectDB => length::read_len(reader).map(Section::Database),
             OpCode::ResizeDB => {
-                let db_size = length::read(reader)?;
-                let exp_size = length::read(reader)?;
-                Ok(Section::Resize(db_size.into(), exp_size.into()))
+                let db_size = length::read_len(reader)?;
+                let exp_size = length::read_len(reader)?;
+                Ok(Section::Resize(db_size, exp_size))
             }
             OpCode::ExpireTimeMs => {
                 let exp = codec::time::read_ms(reader)?;
-                Self::key_value(Some(exp), reader)
+                Self::key_value(Some(exp), None, reader)
             }
             OpCode::ExpireTime => {
                 let exp = codec::time::read_sec(reader)?;
-                Self::key_value(Some(exp), reader)
+                Self::key_value(Some(exp), None, reader)
+            }
+            OpCode::Idle | OpCode::Freq => {
+                let usage = Self::usage(code, reader)?;
+                Self::key_value(None, Some(usage), reader)
             }
-            other => Err(anyhow::anyhow!("Code not supported: {other:?}")),
+            OpCode::Function2 => codec::string::read(reader).map(Section::Function),
+            OpCode::FunctionPreGa => Err(DecodeError::UnsupportedOpcode(
+                code as u8,
+                "pre-release function format",
+            )),
+            OpCode::ModuleAux => Err(DecodeError::UnsupportedOpcode(
+                code as u8,
+                "module aux data",
+            )),
+            OpCode::Eof => Ok(Section::Eof),
         }
     }
 
-    fn key_value(ts: Option<Duration>, reader: &mut impl BufRead) -> Result<Self> {
-        let mut kind = [0u8; 1];
-        reader.read(&mut kind)?;
-        let kind = Kind::try_from(kind[0])?;
-        let key = codec::string::read(reader)?;
-        let val = match kind {
-            Kind::String => codec::string::read(reader).map(Value::String),
-            k => Err(anyhow::anyhow!("Kind not supported: {k:?}")),
-        };
+    /// Reads a key, after its expire time, and the LRU or LFU info
+    /// which may come between the two.
+    fn key_value(
+        ts: Option<Duration>,
+        usage: Option<Usage>,
+        reader: &mut impl BufRead,
+    ) -> DecodeResult<Self> {
+        let mut usage = usage;
+        loop {
+            // Peeked, so an unknown type is reported at its own offset.
+            let kind = reader.fill_buf()?.first().copied();
+            let kind = kind.ok_or(DecodeError::Truncated)?;
+            match OpCode::try_from(kind) {
+                Ok(code @ (OpCode::Idle | OpCode::Freq)) => {
+                    reader.consume(1);
+                    usage = Some(Self::usage(code, reader)?);
+                }
+                _ => {
+                    let kind = Kind::try_from(kind)?;
+                    reader.consume(1);
+                    let key = codec::string::read(reader)?;
+                    let val = Value::read(kind, reader)?;
+                    return Ok(Section::Entry(Entry(ts, key, val, usage)));
+                }
+            }
+        }
+    }
 
-        val.map(|v| Entry(ts, key, v)).map(Section::Entry)
+    fn usage(code: OpCode, reader: &mut impl BufRead) -> DecodeResult<Usage> {
+        match code {
+            OpCode::Idle => length::read_len(reader).map(|idle| Usage::Idle(idle as u64)),
+            _ => {
+                let mut freq = [0u8; 1];
+                reader.read_exact(&mut freq)?;
+                Ok(Usage::Freq(freq[0]))
+            }
+        }
     }
 }
 
+/// Version which added the CRC-64 trailer after the EOF opcode.
+const CHECKSUM_VER: u32 = 5;
+
+/// A snapshot read from any source: a file, a socket or a buffer in memory.
+#[allow(dead_code)]
 #[derive(Debug)]
-pub struct RedisFile(File, u32);
+pub struct RedisFile<R>(R, u32, bool);
 
-impl RedisFile {
-    pub fn build_at(p: &Path) -> Result<RedisFile> {
-        let dir = p.parent().context("dir")?;
-        fs::create_dir_all(dir)?;
-        match File::open(p) {
-            Ok(file) => Self::sanity_check(file),
-            Err(_) => Self::create_at(p),
-        }
+impl RedisFile<BufReader<File>> {
+    /// Opens the snapshot at `p`, failing if there is none.
+    pub fn open_at(p: &Path) -> Result<Self> {
+        let file = File::open(p).with_context(|| format!("Unable to open {}", p.display()))?;
+        Self::read_from(BufReader::new(file))
     }
+}
 
-    fn sanity_check(f: File) -> Result<RedisFile> {
-        let mut file = f;
+impl<R: BufRead> RedisFile<R> {
+    /// Starts reading a snapshot from `reader`, checking its header.
+    pub fn read_from(reader: R) -> Result<Self> {
+        let mut reader = reader;
         let mut buffer = BytesMut::zeroed(REDIS_RDB.len());
 
-        file.read_exact(&mut buffer)?;
-        anyhow::ensure!(buffer.starts_with(REDIS_RDB));
+        let header = |error| RdbError { offset: 0, error };
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|e| header(e.into()))?;
+        if !buffer.starts_with(REDIS_RDB) {
+            let error = DecodeError::UnsupportedEncoding("not an RDB file".into());
+            return Err(header(error).into());
+        }
 
         buffer.resize(4, 0);
-        file.read_exact(&mut buffer)?;
+        reader
+            .read_exact(&mut buffer)
+            .map_err(|e| header(e.into()))?;
 
-        let ver = std::str::from_utf8(&buffer)?;
-        Ok(RedisFile(file, ver.parse()?))
+        let ver = std::str::from_utf8(&buffer)
+            .ok()
+            .and_then(|v| v.parse().ok());
+        let ver = ver.ok_or_else(|| header(DecodeError::Corrupt("version".into())))?;
+        Ok(RedisFile(reader, ver, true))
     }
 
-    fn create_at(path: &Path) -> Result<RedisFile> {
-        let mut file = File::create(path)?;
-        file.write(REDIS_RDB)?;
-        file.write(REDIS_VER.as_bytes())?;
-        Ok(RedisFile(file, REDIS_VER.parse()?))
+    pub fn version(&self) -> u32 {
+        self.1
+    }
+
+    /// Whether the checksum in the trailer is verified. A zero checksum,
+    /// written when checksums are disabled, is never verified.
+    pub fn verify_checksum(self, verify: bool) -> Self {
+        RedisFile(self.0, self.1, verify)
     }
 }
 
-impl IntoIterator for RedisFile {
-    type Item = Section;
-    type IntoIter = RedisFileReader;
+impl<R: BufRead> IntoIterator for RedisFile<R> {
+    type Item = Result<Section, RdbError>;
+    type IntoIter = RedisFileReader<R>;
 
     fn into_iter(self) -> Self::IntoIter {
-        RedisFileReader(BufReader::new(self.0))
+        let version = format!("{:04}", self.1);
+        RedisFileReader {
+            reader: Checksummed {
+                inner: self.0,
+                crc: crc64::update(crc64::update(0, REDIS_RDB), version.as_bytes()),
+                offset: REDIS_RDB.len() + version.len(),
+            },
+            version: self.1,
+            verify: self.2,
+            done: false,
+        }
+    }
+}
+
+/// Reads through `inner`, keeping the CRC-64 and the offset of every byte
+/// consumed so far.
+struct Checksummed<R> {
+    inner: R,
+    crc: u64,
+    offset: usize,
+}
+
+impl<R: BufRead> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.crc = crc64::update(self.crc, &buf[..read]);
+        self.offset += read;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for Checksummed<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.crc = crc64::update(self.crc, &buf[..amt]);
+        }
+        self.offset += amt;
+        self.inner.consume(amt);
     }
 }
 
-pub struct RedisFileReader(BufReader<File>);
+/// Reads the sections of an RDB file up to its EOF opcode, which is only
+/// returned once the checksum after it is verified. Stops after an error,
+/// naming the offset it was found at.
+pub struct RedisFileReader<R> {
+    reader: Checksummed<R>,
+    version: u32,
+    verify: bool,
+    done: bool,
+}
 
-impl Iterator for RedisFileReader {
-    type Item = Section;
+impl<R: BufRead> RedisFileReader<R> {
+    /// Offset of the next section in the file.
+    pub fn offset(&self) -> usize {
+        self.reader.offset
+    }
+
+    /// Reads the CRC-64 which follows the EOF opcode, and compares it with
+    /// the one of everything before it.
+    fn check_trailer(&mut self) -> DecodeResult<()> {
+        if self.version < CHECKSUM_VER {
+            return Ok(());
+        }
+        let computed = self.reader.crc;
+        let mut trailer = [0u8; 8];
+        self.reader.read_exact(&mut trailer)?;
+        let stored = u64::from_le_bytes(trailer);
+        match !self.verify || stored == 0 || stored == computed {
+            true => Ok(()),
+            false => Err(DecodeError::Checksum { stored, computed }),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for RedisFileReader<R> {
+    type Item = Result<Section, RdbError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let reader = &mut self.0;
-        Section::read(reader).ok()
+        if self.done {
+            return None;
+        }
+        let section = match Section::read(&mut self.reader) {
+            Ok(Section::Eof) => {
+                self.done = true;
+                // Mismatches are reported where the checksum starts.
+                let offset = self.reader.offset;
+                self.check_trailer()
+                    .map(|_| Section::Eof)
+                    .map_err(|error| RdbError { offset, error })
+            }
+            Ok(section) => Ok(section),
+            Err(error) => {
+                self.done = true;
+                let offset = self.reader.offset;
+                Err(RdbError { offset, error })
+            }
+        };
+        Some(section)
+    }
+}
+
+/// Writes an RDB file section by section, keeping the running CRC-64 of
+/// everything written so `finish` can append it after the EOF opcode.
+pub struct RedisFileWriter<W: Write> {
+    inner: W,
+    crc: u64,
+    options: SaveOptions,
+}
+
+impl<W: Write> Write for RedisFileWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.crc = crc64::update(self.crc, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> RedisFileWriter<W> {
+    pub fn new(inner: W, options: SaveOptions) -> Result<Self> {
+        let mut writer = Self {
+            inner,
+            crc: 0,
+            options,
+        };
+        writer.write_all(REDIS_RDB)?;
+        writer.write_all(REDIS_VER.as_bytes())?;
+        Ok(writer)
+    }
+
+    fn opcode(&mut self, code: OpCode) -> Result<()> {
+        self.write_all(&[code as u8])?;
+        Ok(())
+    }
+
+    pub fn aux(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.opcode(OpCode::Aux)?;
+        codec::string::write(self, key)?;
+        codec::string::write(self, value)
+    }
+
+    pub fn select_db(&mut self, db: usize) -> Result<()> {
+        self.opcode(OpCode::SelectDB)?;
+        length::write(self, db)
+    }
+
+    pub fn resize_db(&mut self, db_size: usize, exp_size: usize) -> Result<()> {
+        self.opcode(OpCode::ResizeDB)?;
+        length::write(self, db_size)?;
+        length::write(self, exp_size)
+    }
+
+    pub fn entry(&mut self, entry: &Entry) -> Result<()> {
+        if let Some(ts) = entry.0 {
+            self.opcode(OpCode::ExpireTimeMs)?;
+            codec::time::write_ms(self, ts)?;
+        }
+        match entry.3 {
+            Some(Usage::Idle(idle)) => {
+                self.opcode(OpCode::Idle)?;
+                length::write(self, idle as usize)?;
+            }
+            Some(Usage::Freq(freq)) => {
+                self.opcode(OpCode::Freq)?;
+                self.write_all(&[freq])?;
+            }
+            None => {}
+        }
+        entry.2.write_kind(self)?;
+        let compress = self.options.compression;
+        match compress {
+            true => codec::string::write_compressed(self, &entry.1)?,
+            false => codec::string::write(self, &entry.1)?,
+        }
+        entry.2.write(self, compress)
+    }
+
+    /// Ends the file and returns the underlying writer. The checksum is
+    /// left as zero when disabled, which readers take as not to verify.
+    pub fn finish(mut self) -> Result<W> {
+        self.opcode(OpCode::Eof)?;
+        let crc = if self.options.checksum { self.crc } else { 0 };
+        let crc = crc.to_le_bytes();
+        self.inner.write_all(&crc)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_write_read() {
+        let expires_at = UNIX_EPOCH + Duration::from_millis(4_102_444_800_000);
+        let entries = [
+            Entry::new("a".into(), Value::String("1".into()), None)
+                .with_usage(Some(Usage::Idle(300))),
+            Entry::new("b".into(), Value::String("two".into()), Some(expires_at))
+                .with_usage(Some(Usage::Freq(9))),
+            Entry::new("c".into(), Value::String("x".repeat(100).into()), None),
+        ];
+        let mut writer = RedisFileWriter::new(Vec::new(), SaveOptions::default()).unwrap();
+        writer.aux(b"redis-ver", b"7.2.0").unwrap();
+        writer.select_db(0).unwrap();
+        writer.resize_db(3, 1).unwrap();
+        entries.iter().for_each(|e| writer.entry(e).unwrap());
+        let bytes = writer.finish().unwrap();
+
+        assert!(bytes.len() < 100);
+        let (body, crc) = bytes.split_at(bytes.len() - 8);
+        assert_eq!(crc, crc64::update(0, body).to_le_bytes());
+
+        let mut reader = &bytes[REDIS_RDB.len() + REDIS_VER.len()..];
+        let mut sections = std::iter::from_fn(|| Section::read(&mut reader).ok());
+        assert!(matches!(sections.next(), Some(Section::Aux(_))));
+        assert!(matches!(sections.next(), Some(Section::Database(0))));
+        assert!(matches!(sections.next(), Some(Section::Resize(3, 1))));
+        for expected in &entries {
+            match sections.next() {
+                Some(Section::Entry(e)) => {
+                    assert_eq!(e.key(), expected.key());
+                    assert_eq!(e.val(), expected.val());
+                    assert_eq!(e.expires_at(), expected.expires_at());
+                    assert_eq!(e.usage(), expected.usage());
+                }
+                other => panic!("Expected an entry, got {other:?}"),
+            }
+        }
+        assert!(matches!(sections.next(), Some(Section::Eof)));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let dir = std::env::temp_dir().join(format!("redis-{}-crc", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.rdb");
+        let read = |verify| {
+            let file = RedisFile::open_at(&path).unwrap().verify_checksum(verify);
+            file.into_iter().collect::<Result<Vec<_>, _>>()
+        };
+
+        let options = SaveOptions::default();
+        let mut writer = RedisFileWriter::new(Vec::new(), options).unwrap();
+        writer
+            .entry(&Entry::new("k".into(), Value::String("v".into()), None))
+            .unwrap();
+        let mut bytes = writer.finish().unwrap();
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(read(true).unwrap().len(), 2);
+
+        // A flipped bit in the value is only noticed through the checksum.
+        let offset = bytes.len() - 8;
+        bytes[offset - 2] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        let error = read(true).unwrap_err();
+        assert_eq!(error.offset, offset);
+        assert!(matches!(error.error, DecodeError::Checksum { .. }));
+        assert!(read(false).is_ok());
+
+        let options = SaveOptions {
+            checksum: false,
+            ..options
+        };
+        let writer = RedisFileWriter::new(Vec::new(), options).unwrap();
+        let bytes = writer.finish().unwrap();
+        assert!(bytes.ends_with(&[0xFF, 0, 0, 0, 0, 0, 0, 0, 0]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let read = |bytes: &[u8]| {
+            let file = RedisFile::read_from(bytes).unwrap();
+            file.into_iter().collect::<Result<Vec<_>, _>>().unwrap_err()
+        };
+
+        let error = read(b"REDIS0011\xfe\x00\x00\x03foo\x05ba");
+        assert_eq!(error.offset, 19);
+        assert!(matches!(error.error, DecodeError::Truncated));
+
+        let error = read(b"REDIS0011\xfe\x00\x00\x03foo\x03bar\x30\x01k");
+        assert_eq!(error.offset, 20);
+        assert!(matches!(error.error, DecodeError::UnknownOpcode(0x30)));
+
+        let error = read(b"REDIS0011\x00\x03foo\xc5\x00");
+        assert!(matches!(error.error, DecodeError::UnsupportedEncoding(_)));
+
+        let error = read(b"REDIS0011\xfe\x82\x00");
+        assert!(matches!(error.error, DecodeError::BadLength(_)));
+
+        let error = read(b"REDIS0011\x00\xc3\x02\x05\x01a\xff");
+        assert!(matches!(error.error, DecodeError::Corrupt(_)));
+
+        let error = read(b"REDIS0011\xf7\x81\x02");
+        assert!(matches!(
+            error.error,
+            DecodeError::UnsupportedOpcode(0xF7, _)
+        ));
+        assert_eq!(error.offset, 10);
+
+        let error = read(b"REDIS0011\xf6\x03foo");
+        assert!(matches!(
+            error.error,
+            DecodeError::UnsupportedOpcode(0xF6, _)
+        ));
+
+        let error = RedisFile::read_from(&b"REDIS00"[..]).unwrap_err();
+        let error = error.downcast::<RdbError>().unwrap();
+        assert!(matches!(error.error, DecodeError::Truncated));
+        let missing = Path::new("/nonexistent/dump.rdb");
+        assert!(RedisFile::open_at(missing).is_err());
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_usage_and_functions() {
+        let bytes = [
+            &b"REDIS0011"[..],
+            b"\xf5\x03lib",
+            // Idle for 10 seconds, "a" => "1".
+            b"\xf8\x0a\x00\x01a\x011",
+            // Expired at the epoch, with a frequency of 7, "b" => "2".
+            b"\xfc\x00\x00\x00\x00\x00\x00\x00\x00\xf9\x07\x00\x01b\x012",
+            b"\xff\x00\x00\x00\x00\x00\x00\x00\x00",
+        ]
+        .concat();
+        let file = RedisFile::read_from(&bytes[..])
+            .unwrap()
+            .verify_checksum(false);
+        let mut sections = file.into_iter().map(Result::unwrap);
+
+        assert!(matches!(sections.next(), Some(Section::Function(lib)) if lib == "lib"));
+        match sections.next() {
+            Some(Section::Entry(e)) => {
+                assert_eq!(e.key(), "a");
+                assert_eq!(e.usage(), Some(Usage::Idle(10)));
+                assert_eq!(e.expires_at(), None);
+            }
+            other => panic!("Expected an entry, got {other:?}"),
+        }
+        match sections.next() {
+            Some(Section::Entry(e)) => {
+                assert_eq!(e.key(), "b");
+                assert_eq!(e.usage(), Some(Usage::Freq(7)));
+                assert_eq!(e.expires_at(), Some(UNIX_EPOCH));
+            }
+            other => panic!("Expected an entry, got {other:?}"),
+        }
+        assert!(matches!(sections.next(), Some(Section::Eof)));
     }
 }
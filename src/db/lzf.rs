@@ -0,0 +1,142 @@
+//! LZF, the compression Redis applies to large strings in RDB files.
+
+use super::error::{DecodeError, DecodeResult};
+
+const HASH_LOG: u32 = 14;
+const MAX_LITERAL: usize = 1 << 5;
+const MAX_OFFSET: usize = 1 << 13;
+const MAX_MATCH: usize = (1 << 8) + (1 << 3);
+
+/// Expands `input` back into the `len` bytes it was made from.
+pub fn decompress(input: &[u8], len: usize) -> DecodeResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut input = input.iter().copied();
+    let mut next = || {
+        let byte = input.next();
+        byte.ok_or_else(|| DecodeError::Corrupt("LZF data, truncated".into()))
+    };
+
+    while out.len() < len {
+        let ctrl = next()? as usize;
+        if ctrl < MAX_LITERAL {
+            for _ in 0..=ctrl {
+                out.push(next()?);
+            }
+            continue;
+        }
+
+        let mut run = ctrl >> 5;
+        if run == 7 {
+            run += next()? as usize;
+        }
+        let offset = ((ctrl & 0x1f) << 8 | next()? as usize) + 1;
+        let start = out.len().checked_sub(offset);
+        let start = start.ok_or_else(|| {
+            DecodeError::Corrupt(format!("LZF data, back reference out of bounds: {offset}"))
+        })?;
+        // Copied one byte at a time, as a reference may overlap its output.
+        for i in start..start + run + 2 {
+            out.push(out[i]);
+        }
+    }
+    match out.len() == len {
+        true => Ok(out),
+        false => Err(DecodeError::BadLength(format!(
+            "LZF data longer than {len} bytes"
+        ))),
+    }
+}
+
+/// Compresses `input`, finding earlier occurrences of each three bytes
+/// through a hash table like liblzf does.
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut table = vec![0usize; 1 << HASH_LOG];
+    let mut literal = 0;
+    let mut i = 0;
+
+    while i + 2 < input.len() {
+        let head = &input[i..i + 3];
+        let hash = u32::from_le_bytes([head[0], head[1], head[2], 0]).wrapping_mul(2654435761);
+        let slot = &mut table[(hash >> (32 - HASH_LOG)) as usize];
+        let candidate = std::mem::replace(slot, i + 1);
+
+        let reference = candidate
+            .checked_sub(1)
+            .filter(|r| i - r <= MAX_OFFSET && &input[*r..*r + 3] == head);
+        let reference = match reference {
+            Some(reference) => reference,
+            None => {
+                literal += 1;
+                i += 1;
+                if literal == MAX_LITERAL {
+                    flush(&mut out, &input[i - literal..i]);
+                    literal = 0;
+                }
+                continue;
+            }
+        };
+
+        flush(&mut out, &input[i - literal..i]);
+        literal = 0;
+        let max = MAX_MATCH.min(input.len() - i);
+        let mut len = 3;
+        while len < max && input[reference + len] == input[i + len] {
+            len += 1;
+        }
+        let offset = i - reference - 1;
+        let run = len - 2;
+        if run < 7 {
+            out.push((run << 5 | offset >> 8) as u8);
+        } else {
+            out.push((7 << 5 | offset >> 8) as u8);
+            out.push((run - 7) as u8);
+        }
+        out.push(offset as u8);
+        i += len;
+    }
+
+    // The last bytes are too short to start a match.
+    let literal = i - literal..input.len();
+    for chunk in input[literal].chunks(MAX_LITERAL) {
+        flush(&mut out, chunk);
+    }
+    out
+}
+
+fn flush(out: &mut Vec<u8>, literal: &[u8]) {
+    if !literal.is_empty() {
+        out.push((literal.len() - 1) as u8);
+        out.extend(literal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let inputs = [
+            b"".to_vec(),
+            b"ab".to_vec(),
+            b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".to_vec(),
+            "hello world, hello redis. ".repeat(50).into_bytes(),
+            (0..5000u32).map(|i| (i * 7 % 251) as u8).collect(),
+        ];
+        for input in inputs {
+            let compressed = compress(&input);
+            assert_eq!(decompress(&compressed, input.len()).unwrap(), input);
+        }
+        assert!(compress(&[b'x'; 1000]).len() < 20);
+    }
+
+    #[test]
+    fn test_decompress() {
+        // "abcabcabc" as liblzf compresses it: a literal run and a reference.
+        let data = [2, b'a', b'b', b'c', 0x80, 2];
+        assert_eq!(decompress(&data, 9).unwrap(), b"abcabcabc");
+        assert!(decompress(&data, 10).is_err());
+        assert!(decompress(&[0x20, 5], 3).is_err());
+    }
+}
@@ -0,0 +1,3 @@
+//! The parts of the server shared with its tools, like `redis-check-rdb`.
+
+pub mod db;
@@ -1,16 +1,17 @@
 mod command;
 mod config;
-mod db;
 mod proto;
 mod redis;
 mod response;
 mod scanner;
 mod server;
+mod session;
 
 use crate::config::Config;
 use crate::redis::Redis;
 use anyhow::Result;
 use command::Command;
+use redis_starter_rust::db;
 use server::Server;
 use std::{env, sync::Arc};
 use tokio::net::TcpListener;
@@ -27,6 +28,9 @@ async fn main() -> Result<()> {
     let server = Arc::new(Server::new(redis));
     println!("Listening at 6379...");
 
+    let cron = Arc::clone(&server);
+    tokio::spawn(async move { cron.cron().await });
+
     loop {
         let (stream, origin) = listener.accept().await?;
         let server = Arc::clone(&server);
@@ -3,35 +3,546 @@
 // For Integers, the first byte of the reply is ":"
 // For Bulk Strings, the first byte of the reply is "$"
 // For Arrays, the first byte of the reply is "*"
+//
+// RESP3 adds a few more types on top of those:
+// For Nulls, the first byte of the reply is "_"
+// For Doubles, the first byte of the reply is ","
+// For Booleans, the first byte of the reply is "#"
+// For Big Numbers, the first byte of the reply is "("
+// For Verbatim Strings, the first byte of the reply is "="
+// For Blob Errors, the first byte of the reply is "!"
+// For Maps, the first byte of the reply is "%"
+// For Sets, the first byte of the reply is "~"
+// For Attributes, the first byte of the reply is "|"
+// For Pushes, the first byte of the reply is ">"
+
+use bytes::Bytes;
 
 pub const CRLF: &str = "\r\n";
 
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl Protocol {
+    pub fn version(&self) -> i64 {
+        match self {
+            Self::Resp2 => 2,
+            Self::Resp3 => 3,
+        }
+    }
+}
+
+impl TryFrom<i64> for Protocol {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i64) -> anyhow::Result<Self> {
+        match value {
+            2 => Ok(Self::Resp2),
+            3 => Ok(Self::Resp3),
+            v => Err(anyhow::anyhow!("Unsupported protocol version: {v}")),
+        }
+    }
+}
+
+/// Standard prefixes clients use to tell error replies apart.
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+    Err,
+    WrongType,
+    NoProto,
+    WrongPass,
+    Oom,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prefix = match self {
+            Self::Err => "ERR",
+            Self::WrongType => "WRONGTYPE",
+            Self::NoProto => "NOPROTO",
+            Self::WrongPass => "WRONGPASS",
+            Self::Oom => "OOM",
+        };
+        f.write_str(prefix)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    Array(Vec<Frame>),
+    NullArray,
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    Verbatim(String, Bytes),
+    Attribute(Vec<(Frame, Frame)>, Box<Frame>),
+    Push(Vec<Frame>),
+}
+
 pub mod encode {
     use super::*;
 
-    pub fn array(items: &[&str]) -> String {
-        let msg = format!("*{}{CRLF}", items.len());
-        items
-            .iter()
-            .fold(msg, |acc, m| acc + &format!("${}{CRLF}{m}{CRLF}", m.len()))
+    /// Encodes `frame` for a client speaking `protocol`.
+    /// RESP3 only types fall back to their closest RESP2 shape.
+    pub fn frame(frame: &Frame, protocol: Protocol) -> Vec<u8> {
+        let mut out = Vec::new();
+        write(&mut out, frame, protocol);
+        out
     }
 
-    pub fn text(s: &str) -> String {
-        format!("+{s}{CRLF}")
+    pub fn bulk(b: &[u8]) -> Vec<u8> {
+        let mut msg = format!("${}{CRLF}", b.len()).into_bytes();
+        msg.extend_from_slice(b);
+        msg.extend_from_slice(CRLF.as_bytes());
+        msg
     }
 
-    pub fn error(e: &str) -> String {
-        format!("-Error {e}{CRLF}")
+    pub fn text(s: &str) -> Vec<u8> {
+        format!("+{s}{CRLF}").into_bytes()
     }
 
-    pub fn null() -> String {
-        format!("$-1{CRLF}")
+    pub fn null(protocol: Protocol) -> Vec<u8> {
+        match protocol {
+            Protocol::Resp2 => format!("$-1{CRLF}").into_bytes(),
+            Protocol::Resp3 => format!("_{CRLF}").into_bytes(),
+        }
+    }
+
+    fn line(out: &mut Vec<u8>, prefix: char, content: impl std::fmt::Display) {
+        out.extend(format!("{prefix}{content}{CRLF}").into_bytes());
+    }
+
+    fn write(out: &mut Vec<u8>, frame: &Frame, protocol: Protocol) {
+        let resp3 = protocol == Protocol::Resp3;
+        match frame {
+            Frame::Simple(s) => out.extend(text(s)),
+            Frame::Error(e) => line(out, '-', e),
+            Frame::Integer(i) => line(out, ':', i),
+            Frame::Bulk(b) => out.extend(bulk(b)),
+            Frame::Null => out.extend(null(protocol)),
+            Frame::NullArray if resp3 => out.extend(null(protocol)),
+            Frame::NullArray => line(out, '*', -1),
+            Frame::Array(items) => {
+                line(out, '*', items.len());
+                items.iter().for_each(|i| write(out, i, protocol));
+            }
+            Frame::Map(pairs) => {
+                if resp3 {
+                    line(out, '%', pairs.len());
+                } else {
+                    line(out, '*', pairs.len() * 2);
+                }
+                for (k, v) in pairs {
+                    write(out, k, protocol);
+                    write(out, v, protocol);
+                }
+            }
+            Frame::Set(items) => {
+                line(out, if resp3 { '~' } else { '*' }, items.len());
+                items.iter().for_each(|i| write(out, i, protocol));
+            }
+            Frame::Double(d) if resp3 => line(out, ',', double(*d)),
+            Frame::Double(d) => out.extend(bulk(double(*d).as_bytes())),
+            Frame::Boolean(b) if resp3 => line(out, '#', if *b { 't' } else { 'f' }),
+            Frame::Boolean(b) => line(out, ':', *b as i64),
+            Frame::BigNumber(n) if resp3 => line(out, '(', n),
+            Frame::BigNumber(n) => out.extend(bulk(n.as_bytes())),
+            Frame::Verbatim(format, content) if resp3 => {
+                line(out, '=', format.len() + 1 + content.len());
+                out.extend_from_slice(format.as_bytes());
+                out.push(b':');
+                out.extend_from_slice(content);
+                out.extend_from_slice(CRLF.as_bytes());
+            }
+            Frame::Verbatim(_, content) => out.extend(bulk(content)),
+            Frame::Attribute(attrs, reply) => {
+                if resp3 {
+                    line(out, '|', attrs.len());
+                    for (k, v) in attrs {
+                        write(out, k, protocol);
+                        write(out, v, protocol);
+                    }
+                }
+                write(out, reply, protocol);
+            }
+            Frame::Push(items) => {
+                line(out, if resp3 { '>' } else { '*' }, items.len());
+                items.iter().for_each(|i| write(out, i, protocol));
+            }
+        }
+    }
+
+    fn double(d: f64) -> String {
+        match d {
+            d if d.is_nan() => "nan".to_string(),
+            d if d.is_infinite() && d > 0.0 => "inf".to_string(),
+            d if d.is_infinite() => "-inf".to_string(),
+            d => d.to_string(),
+        }
     }
 }
 
 pub mod decode {
-    pub fn array(s: &str) -> impl Iterator<Item = &str> {
-        s.lines().skip(1).filter(|s| !s.starts_with('$'))
+    use super::*;
+    use thiserror::Error;
+
+    const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+    const MAX_MULTIBULK_LEN: i64 = 1024 * 1024;
+    const MAX_INLINE_LEN: usize = 64 * 1024;
+    const MAX_NESTING: usize = 64;
+
+    #[derive(Error, PartialEq, Debug)]
+    pub enum ProtoError {
+        #[error("Protocol error: unexpected type byte '{}'", char::from(*.0))]
+        UnknownType(u8),
+        #[error("Protocol error: invalid integer")]
+        InvalidInteger,
+        #[error("Protocol error: invalid double")]
+        InvalidDouble,
+        #[error("Protocol error: invalid boolean")]
+        InvalidBoolean,
+        #[error("Protocol error: invalid verbatim string")]
+        InvalidVerbatim,
+        #[error("Protocol error: invalid bulk length")]
+        InvalidBulkLength,
+        #[error("Protocol error: invalid multibulk length")]
+        InvalidMultibulkLength,
+        #[error("Protocol error: expected CRLF after bulk payload")]
+        MissingCrlf,
+        #[error("Protocol error: too big inline request")]
+        InlineTooBig,
+        #[error("Protocol error: unbalanced quotes in request")]
+        UnbalancedQuotes,
+        #[error("Protocol error: expected '$', got '{}'", char::from(*.0))]
+        ExpectedBulk(u8),
+        #[error("Protocol error: too many nested aggregates")]
+        TooDeep,
+    }
+
+    /// Decodes a client request from the start of `buf`.
+    /// Multibulk requests start with `*`, anything else is an inline command
+    /// made of space separated arguments, as typed from telnet or netcat.
+    pub fn request(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtoError> {
+        match buf.first() {
+            None => Ok(None),
+            Some(b'*') => multibulk(buf),
+            Some(_) => inline(buf),
+        }
+    }
+
+    /// Requests are flat arrays of bulk strings, so unlike `frame` any other
+    /// element type is rejected up front instead of being decoded.
+    fn multibulk(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtoError> {
+        let (line, mut read) = match line(&buf[1..]) {
+            Some((line, read)) => (line, read + 1),
+            None => return Ok(None),
+        };
+        let len = integer(line).map_err(|_| ProtoError::InvalidMultibulkLength)?;
+        if len == -1 {
+            return Ok(Some((Frame::NullArray, read)));
+        }
+        if !(0..=MAX_MULTIBULK_LEN).contains(&len) {
+            return Err(ProtoError::InvalidMultibulkLength);
+        }
+
+        let mut args = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            match buf.get(read) {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(other) => return Err(ProtoError::ExpectedBulk(*other)),
+            }
+            match frame(&buf[read..])? {
+                Some((arg, size)) => {
+                    args.push(arg);
+                    read += size;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((Frame::Array(args), read)))
+    }
+
+    fn inline(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtoError> {
+        let end = match buf.iter().position(|b| *b == b'\n') {
+            Some(end) => end,
+            None if buf.len() > MAX_INLINE_LEN => return Err(ProtoError::InlineTooBig),
+            None => return Ok(None),
+        };
+        if end > MAX_INLINE_LEN {
+            return Err(ProtoError::InlineTooBig);
+        }
+        let line = buf[..end].strip_suffix(b"\r").unwrap_or(&buf[..end]);
+        let args = split_args(line)?
+            .into_iter()
+            .map(|a| Frame::Bulk(a.into()))
+            .collect();
+        Ok(Some((Frame::Array(args), end + 1)))
+    }
+
+    /// Splits an inline request the way redis-cli does: arguments are separated
+    /// by whitespace, and may be "double quoted" with C-like escapes such as
+    /// `\n` or `\x2a`, or 'single quoted' where only `\'` is an escape.
+    pub fn split_args(line: &[u8]) -> Result<Vec<Vec<u8>>, ProtoError> {
+        let mut args = Vec::new();
+        let mut i = 0;
+        loop {
+            while i < line.len() && line[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i == line.len() {
+                return Ok(args);
+            }
+
+            let mut arg = Vec::new();
+            match line[i] {
+                b'"' => {
+                    i += 1;
+                    loop {
+                        match line.get(i..) {
+                            Some([b'\\', b'x', h, l, ..])
+                                if hex(*h).is_some() && hex(*l).is_some() =>
+                            {
+                                arg.push(hex(*h).unwrap() * 16 + hex(*l).unwrap());
+                                i += 4;
+                            }
+                            Some([b'\\', c, ..]) => {
+                                arg.push(match c {
+                                    b'n' => b'\n',
+                                    b'r' => b'\r',
+                                    b't' => b'\t',
+                                    b'b' => 0x08,
+                                    b'a' => 0x07,
+                                    c => *c,
+                                });
+                                i += 2;
+                            }
+                            Some([b'"', ..]) => {
+                                i += 1;
+                                break;
+                            }
+                            Some([c, ..]) => {
+                                arg.push(*c);
+                                i += 1;
+                            }
+                            _ => return Err(ProtoError::UnbalancedQuotes),
+                        }
+                    }
+                }
+                b'\'' => {
+                    i += 1;
+                    loop {
+                        match line.get(i..) {
+                            Some([b'\\', b'\'', ..]) => {
+                                arg.push(b'\'');
+                                i += 2;
+                            }
+                            Some([b'\'', ..]) => {
+                                i += 1;
+                                break;
+                            }
+                            Some([c, ..]) => {
+                                arg.push(*c);
+                                i += 1;
+                            }
+                            _ => return Err(ProtoError::UnbalancedQuotes),
+                        }
+                    }
+                }
+                _ => {
+                    while i < line.len() && !line[i].is_ascii_whitespace() {
+                        arg.push(line[i]);
+                        i += 1;
+                    }
+                }
+            }
+
+            // A closing quote must be followed by a space or the end of the line.
+            if i < line.len() && !line[i].is_ascii_whitespace() {
+                return Err(ProtoError::UnbalancedQuotes);
+            }
+            args.push(arg);
+        }
+    }
+
+    fn hex(c: u8) -> Option<u8> {
+        (c as char).to_digit(16).map(|d| d as u8)
+    }
+
+    /// Decodes a single frame from the start of `buf`.
+    /// Returns `Ok(None)` when `buf` holds only part of a frame, otherwise the
+    /// frame along with the amount of bytes it took.
+    /// Aggregates may nest up to `MAX_NESTING` levels deep.
+    pub fn frame(buf: &[u8]) -> Result<Option<(Frame, usize)>, ProtoError> {
+        nested(buf, 0)
+    }
+
+    fn nested(buf: &[u8], depth: usize) -> Result<Option<(Frame, usize)>, ProtoError> {
+        let kind = match buf.first() {
+            Some(kind) => *kind,
+            None => return Ok(None),
+        };
+        let (line, mut read) = match line(&buf[1..]) {
+            Some((line, read)) => (line, read + 1),
+            None => return Ok(None),
+        };
+
+        let frame = match kind {
+            b'+' => Frame::Simple(String::from_utf8_lossy(line).into_owned()),
+            b'-' => Frame::Error(String::from_utf8_lossy(line).into_owned()),
+            b':' => Frame::Integer(integer(line)?),
+            b'_' => Frame::Null,
+            b',' => Frame::Double(double(line)?),
+            b'#' => match line {
+                b"t" => Frame::Boolean(true),
+                b"f" => Frame::Boolean(false),
+                _ => return Err(ProtoError::InvalidBoolean),
+            },
+            b'(' => Frame::BigNumber(String::from_utf8_lossy(line).into_owned()),
+            b'$' | b'=' | b'!' => {
+                let len = integer(line).map_err(|_| ProtoError::InvalidBulkLength)?;
+                if kind == b'$' && len == -1 {
+                    return Ok(Some((Frame::Null, read)));
+                }
+                if !(0..=MAX_BULK_LEN).contains(&len) {
+                    return Err(ProtoError::InvalidBulkLength);
+                }
+                let len = len as usize;
+                let rest = &buf[read..];
+                if rest.len() < len + 2 {
+                    return Ok(None);
+                }
+                if &rest[len..len + 2] != CRLF.as_bytes() {
+                    return Err(ProtoError::MissingCrlf);
+                }
+                read += len + 2;
+                let payload = &rest[..len];
+                match kind {
+                    b'$' => Frame::Bulk(Bytes::copy_from_slice(payload)),
+                    b'!' => Frame::Error(String::from_utf8_lossy(payload).into_owned()),
+                    _ => verbatim(payload)?,
+                }
+            }
+            b'*' | b'~' | b'>' | b'%' | b'|' => {
+                let len = integer(line).map_err(|_| ProtoError::InvalidMultibulkLength)?;
+                if kind == b'*' && len == -1 {
+                    return Ok(Some((Frame::NullArray, read)));
+                }
+                if !(0..=MAX_MULTIBULK_LEN).contains(&len) {
+                    return Err(ProtoError::InvalidMultibulkLength);
+                }
+                if depth == MAX_NESTING {
+                    return Err(ProtoError::TooDeep);
+                }
+                let count = match kind {
+                    b'%' | b'|' => len as usize * 2,
+                    _ => len as usize,
+                };
+                let items = match aggregate(&buf[read..], count, depth + 1)? {
+                    Some((items, size)) => {
+                        read += size;
+                        items
+                    }
+                    None => return Ok(None),
+                };
+                match kind {
+                    b'*' => Frame::Array(items),
+                    b'~' => Frame::Set(items),
+                    b'>' => Frame::Push(items),
+                    b'%' => Frame::Map(pairs(items)),
+                    _ => {
+                        let attrs = pairs(items);
+                        match nested(&buf[read..], depth + 1)? {
+                            Some((reply, size)) => {
+                                read += size;
+                                Frame::Attribute(attrs, Box::new(reply))
+                            }
+                            None => return Ok(None),
+                        }
+                    }
+                }
+            }
+            other => return Err(ProtoError::UnknownType(other)),
+        };
+
+        Ok(Some((frame, read)))
+    }
+
+    fn aggregate(
+        buf: &[u8],
+        count: usize,
+        depth: usize,
+    ) -> Result<Option<(Vec<Frame>, usize)>, ProtoError> {
+        let mut items = Vec::with_capacity(count);
+        let mut read = 0;
+        for _ in 0..count {
+            match nested(&buf[read..], depth)? {
+                Some((item, size)) => {
+                    items.push(item);
+                    read += size;
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((items, read)))
+    }
+
+    fn pairs(items: Vec<Frame>) -> Vec<(Frame, Frame)> {
+        let mut iter = items.into_iter();
+        let mut pairs = Vec::new();
+        while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+            pairs.push((k, v));
+        }
+        pairs
+    }
+
+    /// Splits off the bytes before the first CRLF, along with the amount of
+    /// bytes consumed including the CRLF itself.
+    fn line(buf: &[u8]) -> Option<(&[u8], usize)> {
+        buf.windows(2)
+            .position(|w| w == CRLF.as_bytes())
+            .map(|end| (&buf[..end], end + 2))
+    }
+
+    fn integer(line: &[u8]) -> Result<i64, ProtoError> {
+        std::str::from_utf8(line)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or(ProtoError::InvalidInteger)
+    }
+
+    fn double(line: &[u8]) -> Result<f64, ProtoError> {
+        match line {
+            b"inf" => Ok(f64::INFINITY),
+            b"-inf" => Ok(f64::NEG_INFINITY),
+            b"nan" => Ok(f64::NAN),
+            line => std::str::from_utf8(line)
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .ok_or(ProtoError::InvalidDouble),
+        }
+    }
+
+    fn verbatim(payload: &[u8]) -> Result<Frame, ProtoError> {
+        if payload.len() < 4 || payload[3] != b':' {
+            return Err(ProtoError::InvalidVerbatim);
+        }
+        let format = String::from_utf8_lossy(&payload[..3]).into_owned();
+        Ok(Frame::Verbatim(
+            format,
+            Bytes::copy_from_slice(&payload[4..]),
+        ))
     }
 }
 
@@ -39,15 +550,246 @@ pub mod decode {
 mod tests {
     use super::*;
 
+    fn bulks<T: AsRef<[u8]>>(items: &[T]) -> Frame {
+        let items = items
+            .iter()
+            .map(|b| Frame::Bulk(Bytes::copy_from_slice(b.as_ref())))
+            .collect();
+        Frame::Array(items)
+    }
+
     #[test]
     fn test_encode_array() {
-        let str = encode::array(&["ECHO", "hey"]);
-        assert_eq!(str, "*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n");
+        let bytes = encode::frame(&bulks(&["ECHO", "hey"]), Protocol::Resp2);
+        assert_eq!(bytes, b"*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n");
+    }
+
+    #[test]
+    fn test_encode_resp3_fallback() {
+        let map = Frame::Map(vec![
+            (Frame::Simple("proto".into()), Frame::Integer(3)),
+            (Frame::Bulk("ok".into()), Frame::Boolean(true)),
+        ]);
+        let resp3 = encode::frame(&map, Protocol::Resp3);
+        assert_eq!(resp3, b"%2\r\n+proto\r\n:3\r\n$2\r\nok\r\n#t\r\n");
+        let resp2 = encode::frame(&map, Protocol::Resp2);
+        assert_eq!(resp2, b"*4\r\n+proto\r\n:3\r\n$2\r\nok\r\n:1\r\n");
+
+        let double = Frame::Double(1.5);
+        assert_eq!(encode::frame(&double, Protocol::Resp3), b",1.5\r\n");
+        assert_eq!(encode::frame(&double, Protocol::Resp2), b"$3\r\n1.5\r\n");
+
+        let verbatim = Frame::Verbatim("txt".into(), "hi".into());
+        assert_eq!(
+            encode::frame(&verbatim, Protocol::Resp3),
+            b"=6\r\ntxt:hi\r\n"
+        );
+        assert_eq!(encode::frame(&verbatim, Protocol::Resp2), b"$2\r\nhi\r\n");
+
+        assert_eq!(encode::frame(&Frame::Null, Protocol::Resp3), b"_\r\n");
+        assert_eq!(encode::frame(&Frame::Null, Protocol::Resp2), b"$-1\r\n");
     }
 
     #[test]
     fn test_decode_array() {
-        let vec = decode::array("*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n").collect::<Vec<_>>();
-        assert_eq!(&vec, &["ECHO", "hey"]);
+        let msg = b"*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n";
+        let (frame, read) = decode::frame(msg).unwrap().unwrap();
+        let expected = Frame::Array(vec![
+            Frame::Bulk(Bytes::from("ECHO")),
+            Frame::Bulk(Bytes::from("hey")),
+        ]);
+        assert_eq!(frame, expected);
+        assert_eq!(read, msg.len());
+    }
+
+    #[test]
+    fn test_decode_binary_bulk() {
+        let msg = b"$6\r\n$a\r\n\xff\x00\r\n";
+        let (frame, _) = decode::frame(msg).unwrap().unwrap();
+        assert_eq!(frame, Frame::Bulk(Bytes::from_static(b"$a\r\n\xff\x00")));
+    }
+
+    #[test]
+    fn test_decode_partial() {
+        let msg = b"*2\r\n$4\r\nECHO\r\n$3\r\nhey\r\n";
+        for end in 0..msg.len() {
+            assert_eq!(decode::frame(&msg[..end]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn test_decode_scalars() {
+        let msg = b"*4\r\n+OK\r\n-ERR boom\r\n:-42\r\n$-1\r\n";
+        let (frame, _) = decode::frame(msg).unwrap().unwrap();
+        let expected = Frame::Array(vec![
+            Frame::Simple("OK".into()),
+            Frame::Error("ERR boom".into()),
+            Frame::Integer(-42),
+            Frame::Null,
+        ]);
+        assert_eq!(frame, expected);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(
+            decode::frame(b"$abc\r\n"),
+            Err(decode::ProtoError::InvalidBulkLength)
+        );
+        assert_eq!(
+            decode::frame(b"$3\r\nheyXX"),
+            Err(decode::ProtoError::MissingCrlf)
+        );
+    }
+
+    /// Small xorshift generator, enough to drive the round-trip properties below.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn text(&mut self) -> String {
+            const ALPHABET: &[char] = &['a', 'Z', '0', ' ', '$', '*', '\r', '\n', 'ñ', '€'];
+            let len = self.below(24);
+            (0..len)
+                .map(|_| ALPHABET[self.below(ALPHABET.len())])
+                .collect()
+        }
+
+        fn bytes(&mut self) -> Vec<u8> {
+            let len = self.below(32);
+            (0..len).map(|_| self.next() as u8).collect()
+        }
+
+        fn frame(&mut self, depth: usize) -> Frame {
+            let kinds = if depth == 0 { 9 } else { 14 };
+            let items = |rng: &mut Rng| (0..rng.below(4)).map(|_| rng.frame(depth - 1)).collect();
+            match self.below(kinds) {
+                0 => Frame::Simple(self.text().replace(['\r', '\n'], "")),
+                1 => Frame::Error(self.text().replace(['\r', '\n'], "")),
+                2 => Frame::Integer(self.next() as i64),
+                3 => Frame::Bulk(self.bytes().into()),
+                4 => Frame::Null,
+                5 => Frame::Double(self.next() as i32 as f64 / 8.0),
+                6 => Frame::Boolean(self.below(2) == 0),
+                7 => Frame::BigNumber(format!("-{}{:020}", self.next(), self.next())),
+                8 => Frame::Verbatim("txt".into(), self.bytes().into()),
+                9 => Frame::Array(items(self)),
+                10 => Frame::Set(items(self)),
+                11 => Frame::Push(items(self)),
+                12 => Frame::Map(
+                    (0..self.below(3))
+                        .map(|_| (self.frame(depth - 1), self.frame(depth - 1)))
+                        .collect(),
+                ),
+                _ => {
+                    let attrs = vec![(self.frame(depth - 1), self.frame(depth - 1))];
+                    Frame::Attribute(attrs, Box::new(self.frame(depth - 1)))
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prop_array_round_trip() {
+        let mut rng = Rng(0x2545F4914F6CDD1D);
+        for _ in 0..500 {
+            let items: Vec<Vec<u8>> = (0..rng.below(8)).map(|_| rng.bytes()).collect();
+            let encoded = encode::frame(&bulks(&items), Protocol::Resp2);
+            let (frame, read) = decode::frame(&encoded).unwrap().unwrap();
+            assert_eq!(frame, bulks(&items));
+            assert_eq!(read, encoded.len());
+        }
+    }
+
+    #[test]
+    fn prop_split_frames_are_incomplete() {
+        let mut rng = Rng(0x9E3779B97F4A7C15);
+        for _ in 0..200 {
+            let items: Vec<Vec<u8>> = (0..1 + rng.below(4)).map(|_| rng.bytes()).collect();
+            let encoded = encode::frame(&bulks(&items), Protocol::Resp2);
+            let cut = rng.below(encoded.len());
+            assert_eq!(decode::frame(&encoded[..cut]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn prop_scalars_round_trip() {
+        let mut rng = Rng(0xD1B54A32D192ED03);
+        for _ in 0..200 {
+            let text: String = rng.text().replace(['\r', '\n'], "");
+            let encoded = encode::text(&text);
+            let decoded = decode::frame(&encoded).unwrap();
+            assert_eq!(decoded, Some((Frame::Simple(text), encoded.len())));
+
+            let bytes = rng.bytes();
+            let encoded = encode::bulk(&bytes);
+            let decoded = decode::frame(&encoded).unwrap();
+            assert_eq!(decoded, Some((Frame::Bulk(bytes.into()), encoded.len())));
+        }
+        let encoded = encode::null(Protocol::Resp2);
+        let decoded = decode::frame(&encoded).unwrap();
+        assert_eq!(decoded, Some((Frame::Null, encoded.len())));
+    }
+
+    #[test]
+    fn prop_resp3_round_trip() {
+        let mut rng = Rng(0xA0761D6478BD642F);
+        for _ in 0..500 {
+            let frame = rng.frame(3);
+            let encoded = encode::frame(&frame, Protocol::Resp3);
+            let (decoded, read) = decode::frame(&encoded).unwrap().unwrap();
+            assert_eq!(decoded, frame);
+            assert_eq!(read, encoded.len());
+        }
+    }
+
+    #[test]
+    fn test_decode_inline() {
+        let (frame, read) = decode::request(b"SET key \"a\\x41\\\"\" 'b c'\r\nPING")
+            .unwrap()
+            .unwrap();
+        assert_eq!(frame, bulks(&["SET", "key", "aA\"", "b c"]));
+        assert_eq!(read, 25);
+        assert_eq!(decode::request(b"PING"), Ok(None));
+        assert_eq!(
+            decode::request(b"GET \"a\"b\r\n"),
+            Err(decode::ProtoError::UnbalancedQuotes)
+        );
+        let huge = vec![b'a'; 70 * 1024];
+        assert_eq!(
+            decode::request(&huge),
+            Err(decode::ProtoError::InlineTooBig)
+        );
+    }
+
+    #[test]
+    fn test_decode_nested() {
+        let deep = b"*1\r\n".repeat(100_000);
+        assert_eq!(
+            decode::request(&deep),
+            Err(decode::ProtoError::ExpectedBulk(b'*'))
+        );
+        assert_eq!(decode::frame(&deep), Err(decode::ProtoError::TooDeep));
+        let attributes = b"|1\r\n+a\r\n+b\r\n".repeat(100);
+        assert_eq!(decode::frame(&attributes), Err(decode::ProtoError::TooDeep));
+        assert_eq!(
+            decode::request(b"*2\r\n$3\r\nGET\r\n:1\r\n"),
+            Err(decode::ProtoError::ExpectedBulk(b':'))
+        );
+        assert_eq!(decode::request(b"*2\r\n$3\r\nGET\r\n"), Ok(None));
+
+        let mut shallow = b"*1\r\n".repeat(10);
+        shallow.extend_from_slice(b":1\r\n");
+        assert!(decode::frame(&shallow).unwrap().is_some());
     }
 }
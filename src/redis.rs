@@ -1,88 +1,1101 @@
+mod aof;
 mod cache;
+mod glob;
+mod introspection;
+mod object;
+mod persistence;
 
-use crate::db::{self, Database};
+use crate::db::{self, Database, Entry, Usage};
 use crate::{
-    command::{Command, ConfigCmd, ConfigKey},
-    config::Config,
+    command::{
+        table, Command, CommandCmd, CommandError, Condition, ConfigCmd, ConfigKey, ExpireOptions,
+        Expiry, Hello, ObjectCmd, SetOptions, Timeout,
+    },
+    config::{self, Config, MaxMemoryPolicy},
+    proto::Protocol,
     response::{Builder, Response},
+    session::Session,
 };
-use anyhow::Result;
-use std::{sync::Mutex, time};
+use anyhow::{Context, Result};
+use aof::Aof;
+use bytes::Bytes;
+use cache::now_ms;
+use object::Object;
+use persistence::Persistence;
+use std::{
+    cmp::Ordering,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering},
+        Mutex, MutexGuard, RwLock, RwLockReadGuard,
+    },
+    thread,
+    time::{self, SystemTime},
+};
+
+type Cache = cache::Cache<Bytes, Object>;
+
+const REDIS_VERSION: &str = "7.2.0";
+/// Keys with a deadline each round of the active expiration cycle looks at.
+const ACTIVE_EXPIRE_KEYS_PER_LOOP: usize = 20;
+/// Percentage of expired keys in a sample which makes the cycle sample
+/// the same database again.
+const ACTIVE_EXPIRE_STALE_PERC: usize = 25;
+/// Time each cron run may spend expiring keys, a quarter of its period.
+const ACTIVE_EXPIRE_BUDGET: time::Duration = time::Duration::from_millis(25);
+/// Best candidates for eviction kept between two samples.
+const EVICTION_POOL_SIZE: usize = 16;
 
-type Cache = cache::Cache<String, String>;
+fn config_pair(key: &str, value: &str) -> (Response, Response) {
+    (
+        Response::bulk(key.as_bytes()),
+        Response::bulk(value.as_bytes()),
+    )
+}
 
 pub struct Redis {
-    cache: Mutex<Cache>,
-    config: Config,
+    /// Every database, by index, behind a single lock so commands touching
+    /// two of them and snapshots see a consistent state.
+    dbs: Mutex<Vec<Cache>>,
+    config: RwLock<Config>,
+    persistence: Persistence,
+    aof: Aof,
+    started_at: time::Instant,
+    /// Running average of the percentage of expired keys the active
+    /// expiration cycle finds, an estimate of those still in memory.
+    expired_stale_perc: Mutex<f64>,
+    /// Database the next active expiration cycle starts at, so one which
+    /// keeps using up the time does not starve the others.
+    expire_db: AtomicUsize,
+    /// Keys deleted to stay within `maxmemory`.
+    evicted_keys: AtomicU64,
+}
+
+/// Unix time in milliseconds.
+fn unix_ms(at: SystemTime) -> u64 {
+    let since = at.duration_since(time::UNIX_EPOCH).unwrap_or_default();
+    since.as_millis() as u64
+}
+
+/// Unix time in milliseconds of a moment on the monotonic clock, like
+/// when a command was received.
+fn unix_ms_of(at: time::Instant) -> u64 {
+    now_ms().saturating_sub(at.elapsed().as_millis() as u64)
+}
+
+/// Every live key of each database, ready to be written to an RDB file.
+/// Under the LRU and LFU policies, keys keep the stats they are evicted by.
+fn entries(dbs: &[Cache], policy: MaxMemoryPolicy) -> Vec<Vec<Entry>> {
+    let entries = |cache: &Cache| {
+        cache
+            .entries()
+            .map(|(key, value, deadline)| {
+                let expires_at =
+                    deadline.map(|ms| time::UNIX_EPOCH + time::Duration::from_millis(ms));
+                let usage = if policy.is_lfu() {
+                    cache.freq(key).map(Usage::Freq)
+                } else if policy.is_lru() {
+                    cache.idle(key).map(|ms| Usage::Idle(ms / 1000))
+                } else {
+                    None
+                };
+                Entry::new(key.clone(), value.to_value(), expires_at).with_usage(usage)
+            })
+            .collect()
+    };
+    dbs.iter().map(entries).collect()
+}
+
+fn empty(databases: usize) -> Vec<Cache> {
+    (0..databases).map(|_| Cache::new()).collect()
 }
 
 impl Redis {
     pub fn new(config: Config) -> Result<Self> {
-        let cache = Mutex::new(Cache::new());
-        Ok(Self { cache, config })
+        let mut redis = Self {
+            dbs: Mutex::new(empty(config.databases)),
+            config: RwLock::new(config.clone()),
+            persistence: Persistence::new(),
+            aof: Aof::disabled(),
+            started_at: time::Instant::now(),
+            expired_stale_perc: Mutex::new(0.0),
+            expire_db: AtomicUsize::new(0),
+            evicted_keys: AtomicU64::new(0),
+        };
+        // With the AOF on, it is the source of truth and the snapshot is ignored.
+        if config.append_only {
+            redis.aof = redis.load_aof(&config)?;
+            // Replayed commands counted as changes, but they come from the AOF.
+            redis.persistence.reset_dirty();
+        } else {
+            // Without a snapshot yet, the server starts empty.
+            let path = config.local_store_path();
+            if path.exists() {
+                let dbs = Self::load(&path, config.rdb_checksum, config.databases)?;
+                redis.dbs = Mutex::new(dbs);
+            }
+        }
+        Ok(redis)
     }
 
-    pub fn handle(&self, cmd: &Command, received_at: time::Instant) -> Option<Response> {
-        match cmd {
-            Command::Ping => Some(Response::pong()),
-            Command::Echo(message) => Some(Response::text(message)),
-            Command::Get(key) => self.handle_get(key),
-            Command::Set(key, value, delta) => {
-                let timeout = delta.map(|d| received_at + d);
-                Some(self.handle_set(key, value, timeout))
+    /// Loads the base and incremental files listed in the AOF manifest, and
+    /// keeps appending to them. Without a manifest, the single file AOF of
+    /// older versions is loaded and becomes the base of a new one.
+    fn load_aof(&self, config: &Config) -> Result<Aof> {
+        let dir = config.aof_dir();
+        let filename = &config.append_filename;
+        let truncated = config.aof_load_truncated;
+        let manifest = match aof::Manifest::read(&dir, filename)? {
+            Some(manifest) => manifest,
+            None => {
+                self.replay(&config.aof_path(), truncated)?;
+                let dbs = self.dbs();
+                let (fsync, options) = (config.append_fsync, config.save_options());
+                let entries = entries(&dbs, config.maxmemory_policy);
+                return Aof::create(&dir, filename, fsync, &entries, options);
+            }
+        };
+
+        let files: Vec<_> = manifest.files().collect();
+        for (i, file) in files.iter().enumerate() {
+            let path = dir.join(&file.name);
+            if file.is_rdb() {
+                *self.dbs() = Self::load(&path, config.rdb_checksum, config.databases)?;
+            } else {
+                // Only the file being appended to when we stopped may be cut short.
+                self.replay(&path, truncated && i + 1 == files.len())?;
             }
-            Command::Config(cmd) => Some(self.handle_config(cmd)),
-            Command::Keys(op) => self.handle_keys(op).ok(),
-            Command::Unknown(cmd, args) => {
-                println!("Skip unknown command: {cmd}, args: {args}");
-                None
+        }
+        Aof::open(&dir, filename, config.append_fsync, manifest)
+    }
+
+    /// Runs every command logged in an AOF file, before it is reopened for
+    /// writes. Like in Redis, `maxmemory` does not apply while loading.
+    fn replay(&self, path: &Path, truncated: bool) -> Result<()> {
+        let mut session = Session::default();
+        for command in aof::load(path, truncated)? {
+            self.execute(&mut session, &command, time::Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Reads an RDB snapshot into `databases` caches, leaving out the keys
+    /// which expired while it was on disk.
+    fn load(path: &Path, verify_checksum: bool, databases: usize) -> Result<Vec<Cache>> {
+        let mut dbs = empty(databases);
+        let db = db::open_at(path, verify_checksum)?;
+        let loaded = db.databases();
+        let loaded = loaded.with_context(|| format!("Unable to load {}", path.display()))?;
+        let now = now_ms();
+        for (index, entries) in loaded {
+            let cache = match dbs.get_mut(index) {
+                Some(cache) => cache,
+                None => anyhow::bail!(
+                    "Unable to load {}, database {index} is out of range",
+                    path.display()
+                ),
+            };
+            Self::load_entries(cache, entries, now);
+        }
+        Ok(dbs)
+    }
+
+    fn load_entries(cache: &mut Cache, entries: Vec<Entry>, now: u64) {
+        for entry in entries {
+            let deadline = entry.expires_at().map(unix_ms);
+            if deadline.map_or(false, |at| at <= now) {
+                continue;
+            }
+            let usage = entry.usage();
+            let (key, value) = entry.into_parts();
+            cache.put(key.clone(), Object::from(value), deadline);
+            match usage {
+                Some(Usage::Idle(idle)) => cache.set_idle(&key, idle * 1000),
+                Some(Usage::Freq(freq)) => cache.set_freq(&key, freq),
+                None => {}
             }
         }
     }
 
-    fn handle_keys(&self, _op: &str) -> Result<Response> {
-        let db = db::open_at(&self.config.local_store_path())?;
-        let keys = db.all_keys();
-        let keys: Vec<_> = keys.iter().map(String::as_str).collect();
-        Ok(Response::array(&keys))
+    fn dbs(&self) -> MutexGuard<'_, Vec<Cache>> {
+        self.dbs.lock().expect("unique access to cache")
     }
 
-    fn handle_config(&self, cmd: &ConfigCmd) -> Response {
+    /// Copies every live key under a single lock, so the snapshot is
+    /// consistent even if it is written while clients keep changing data.
+    /// Also returns the amount of changes the snapshot includes.
+    fn snapshot(&self) -> (Vec<Vec<Entry>>, u64) {
+        let policy = self.config().maxmemory_policy;
+        let dbs = self.dbs();
+        (entries(&dbs, policy), self.persistence.dirty())
+    }
+
+    /// Starts rewriting the AOF in the background. Returns `false` when a
+    /// rewrite is already running.
+    fn rewrite_aof(&self) -> Result<bool> {
+        // Writes keep the cache locked until they are logged, so holding it
+        // splits the log exactly between the snapshot and the new file.
+        let policy = self.config().maxmemory_policy;
+        let dbs = self.dbs();
+        let rewrite = match self.aof.start_rewrite()? {
+            Some(rewrite) => rewrite,
+            None => return Ok(false),
+        };
+        let entries = entries(&dbs, policy);
+        drop(dbs);
+        self.aof
+            .rewrite(rewrite, entries, self.config().save_options());
+        Ok(true)
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().expect("shared access to config")
+    }
+
+    /// Periodic housekeeping, run by the server a few times per second.
+    pub fn cron(&self) {
+        self.active_expire_cycle(ACTIVE_EXPIRE_BUDGET);
+        self.aof.cron();
+        let (percentage, min_size) = {
+            let config = self.config();
+            let min_size = config.auto_aof_rewrite_min_size;
+            (config.auto_aof_rewrite_percentage, min_size)
+        };
+        if self.aof.is_rewrite_due(percentage, min_size) {
+            if let Err(e) = self.rewrite_aof() {
+                eprintln!("Unable to rewrite the append only file: {e}");
+            }
+        }
+        let due = self.persistence.is_due(&self.config().save);
+        if due {
+            let (entries, dirty) = self.snapshot();
+            let config = self.config();
+            let path = config.local_store_path();
+            self.persistence
+                .bgsave(path, entries, dirty, config.save_options());
+        }
+    }
+
+    /// Runs a command from a client, once keys were evicted to make room
+    /// for it. Commands which may need more memory are refused without.
+    pub fn handle(
+        &self,
+        session: &mut Session,
+        cmd: &Command,
+        received_at: time::Instant,
+    ) -> Response {
+        let fits = self.free_memory();
+        if !fits && cmd.is_denyoom() {
+            return CommandError::Oom.into();
+        }
+        self.execute(session, cmd, received_at)
+    }
+
+    fn execute(
+        &self,
+        session: &mut Session,
+        cmd: &Command,
+        received_at: time::Instant,
+    ) -> Response {
         match cmd {
-            ConfigCmd::Get(key) => match key {
-                ConfigKey::Dir => Response::array(&["dir", &self.config.dir.to_str().unwrap()]),
-                ConfigKey::DbFilename => Response::array(&["dbfilename", &self.config.db_filename]),
+            Command::Ping(None) => Response::pong(),
+            Command::Ping(Some(message)) => Response::bulk(message),
+            Command::Echo(message) => Response::bulk(message),
+            Command::Get(key) => self.handle_get(session.db, key),
+            Command::Set(key, value, options) => {
+                self.handle_set(session.db, key, value, options, received_at)
+            }
+            Command::Config(cmd) => self.handle_config(cmd),
+            Command::Keys(pattern) => self.handle_keys(session.db, pattern),
+            Command::Hello(hello) => self.handle_hello(session, hello),
+            Command::Introspect(cmd) => self.handle_command(cmd),
+            Command::Save => self.handle_save(),
+            Command::BgSave => self.handle_bgsave(),
+            Command::LastSave => Response::integer(self.persistence.last_save() as i64),
+            Command::Info(sections) => self.handle_info(sections),
+            Command::BgRewriteAof => self.handle_bgrewriteaof(),
+            Command::Select(index) => match self.db_index(*index) {
+                Ok(db) => {
+                    session.db = db;
+                    Response::ok()
+                }
+                Err(e) => e.into(),
             },
+            Command::SwapDb(first, second) => self.handle_swapdb(session.db, *first, *second),
+            Command::Move(key, index) => self.handle_move(session.db, key, *index),
+            Command::Del(keys) => self.handle_del(session.db, keys),
+            Command::FlushDb(lazy) => self.handle_flush(Some(session.db), *lazy),
+            Command::FlushAll(lazy) => self.handle_flush(None, *lazy),
+            Command::DbSize => Response::integer(self.dbs()[session.db].len() as i64),
+            Command::Type(key) => {
+                let mut dbs = self.dbs();
+                let name = dbs[session.db].peek(key).map(Object::type_name);
+                Response::text(name.unwrap_or("none"))
+            }
+            Command::Object(cmd) => self.handle_object(session.db, cmd),
+            Command::Expire(key, timeout, options) => {
+                self.handle_expire(session.db, key, *timeout, options, received_at)
+            }
+            Command::Ttl(key, millis) => self.handle_ttl(session.db, key, *millis, false),
+            Command::ExpireTime(key, millis) => self.handle_ttl(session.db, key, *millis, true),
+            Command::Persist(key) => self.handle_persist(session.db, key),
         }
     }
 
-    fn handle_get(&self, k: &String) -> Option<Response> {
-        let mut cache = self.cache.lock().expect("unique access to cache");
-        if let Ok(cached) = cache.value(k) {
-            return Some(Response::text(cached));
+    /// Sets the deadline of a key, or deletes it when the deadline already
+    /// passed. Replies whether the key was changed.
+    fn handle_expire(
+        &self,
+        db: usize,
+        key: &Bytes,
+        timeout: Timeout,
+        options: &ExpireOptions,
+        received_at: time::Instant,
+    ) -> Response {
+        let deadline = match timeout {
+            // Deadlines past what fits never arrive anyway.
+            Timeout::In(ms) => (unix_ms_of(received_at) as i64).saturating_add(ms),
+            Timeout::At(ms) => ms,
+        };
+        let mut dbs = self.dbs();
+        let cache = &mut dbs[db];
+        if cache.value(key).is_err() {
+            return Response::integer(0);
         }
+        let current = cache.deadline(key);
+        let allowed = match options.condition {
+            Condition::Always => true,
+            Condition::IfMissing => current.is_none(),
+            Condition::IfExists => current.is_some(),
+        };
+        // Keys without a deadline never expire, so any deadline is sooner.
+        let order = current.map_or(Ordering::Less, |c| deadline.cmp(&(c as i64)));
+        if !allowed || options.compare.map_or(false, |compare| compare != order) {
+            return Response::integer(0);
+        }
+
+        if deadline <= now_ms() as i64 {
+            cache.remove(key);
+        } else {
+            cache.set_deadline(key, Some(deadline as u64));
+        }
+        self.persistence.touch(1);
+        // Replaying a deadline in the past deletes the key just the same.
+        let at = deadline.to_string();
+        self.propagate(db, &[b"PEXPIREAT", key, at.as_bytes()]);
+        Response::integer(1)
+    }
 
-        let db = db::open_at(&self.config.local_store_path()).ok()?;
-        if let Some(val) = db.find(k) {
-            return Some(Response::text(&val));
+    /// Replies how long a key has left, or when it expires if `absolute`:
+    /// -2 when the key is missing and -1 when it never expires.
+    fn handle_ttl(&self, db: usize, key: &Bytes, millis: bool, absolute: bool) -> Response {
+        let mut dbs = self.dbs();
+        if dbs[db].peek(key).is_err() {
+            return Response::integer(-2);
         }
+        let deadline = match dbs[db].deadline(key) {
+            Some(deadline) => deadline,
+            None => return Response::integer(-1),
+        };
+        let ms = match absolute {
+            true => deadline,
+            false => deadline.saturating_sub(now_ms()),
+        };
+        match (millis, absolute) {
+            (true, _) => Response::integer(ms as i64),
+            (false, true) => Response::integer((ms / 1000) as i64),
+            // Rounded, so a key set to expire in 10 seconds reports 10.
+            (false, false) => Response::integer(((ms + 500) / 1000) as i64),
+        }
+    }
 
-        Some(Response::null())
+    fn handle_persist(&self, db: usize, key: &Bytes) -> Response {
+        let mut dbs = self.dbs();
+        if dbs[db].deadline(key).is_none() {
+            return Response::integer(0);
+        }
+        dbs[db].set_deadline(key, None);
+        self.persistence.touch(1);
+        self.propagate(db, &[b"PERSIST", key]);
+        Response::integer(1)
     }
 
-    fn handle_set(&self, key: &String, value: &str, timeout: Option<time::Instant>) -> Response {
-        let previous = {
-            let mut cache = self.cache.lock().unwrap();
-            cache.value(key).cloned()
+    /// Deletes the live keys among `keys`, replying how many there were.
+    fn handle_del(&self, db: usize, keys: &[Bytes]) -> Response {
+        let mut dbs = self.dbs();
+        let mut args: Vec<&[u8]> = vec![b"DEL"];
+        for key in keys {
+            if dbs[db].remove(key).is_some() {
+                args.push(key);
+            }
+        }
+        let removed = args.len() - 1;
+        if removed > 0 {
+            self.persistence.touch(removed as u64);
+            self.propagate(db, &args);
+        }
+        Response::integer(removed as i64)
+    }
+
+    /// Deletes expired keys nobody asks for, like Redis: each database is
+    /// sampled until less than a quarter of the sample had expired, or the
+    /// time runs out. The next cycle picks up at the database after the last
+    /// one sampled.
+    fn active_expire_cycle(&self, budget: time::Duration) {
+        let started = time::Instant::now();
+        let (mut sampled, mut expired) = (0, 0);
+        let mut dbs = self.dbs();
+        let databases = dbs.len();
+        'dbs: for _ in 0..databases {
+            let index = self.expire_db.load(AtomicOrdering::Relaxed) % databases;
+            self.expire_db
+                .store((index + 1) % databases, AtomicOrdering::Relaxed);
+            let cache = &mut dbs[index];
+            loop {
+                let sample = cache.expire_sample(ACTIVE_EXPIRE_KEYS_PER_LOOP);
+                sampled += sample.0;
+                expired += sample.1;
+                if started.elapsed() >= budget {
+                    break 'dbs;
+                }
+                if sample.1 * 100 <= sample.0 * ACTIVE_EXPIRE_STALE_PERC {
+                    break;
+                }
+            }
+        }
+        drop(dbs);
+        if sampled > 0 {
+            let current = expired as f64 / sampled as f64 * 100.0;
+            let mut perc = self
+                .expired_stale_perc
+                .lock()
+                .expect("unique access to stats");
+            *perc = current * 0.05 + *perc * 0.95;
+        }
+    }
+
+    /// Looks at a key without counting it as an access.
+    fn handle_object(&self, db: usize, cmd: &ObjectCmd) -> Response {
+        let mut dbs = self.dbs();
+        let cache = &mut dbs[db];
+        let key = match cmd {
+            ObjectCmd::Encoding(key) | ObjectCmd::IdleTime(key) | ObjectCmd::Freq(key) => key,
         };
-        let mut cache = self.cache.lock().expect("unique access to cache");
+        let object = match cache.peek(key) {
+            Ok(object) => object,
+            Err(_) => return Response::null(),
+        };
+        let lfu = self.config().maxmemory_policy.is_lfu();
+        match cmd {
+            ObjectCmd::Encoding(_) => Response::bulk(object.encoding().as_bytes()),
+            ObjectCmd::IdleTime(_) if lfu => CommandError::Other(
+                "An LFU maxmemory policy is selected, idle time not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".into(),
+            )
+            .into(),
+            ObjectCmd::IdleTime(_) => {
+                let idle = cache.idle(key).unwrap_or_default();
+                Response::integer((idle / 1000) as i64)
+            }
+            ObjectCmd::Freq(_) if !lfu => CommandError::Other(
+                "An LFU maxmemory policy is not selected, access frequency not tracked. Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.".into(),
+            )
+            .into(),
+            ObjectCmd::Freq(_) => Response::integer(cache.freq(key).unwrap_or_default() as i64),
+        }
+    }
 
-        cache.put(key.to_string(), value.to_string(), timeout);
+    /// Evicts keys, the way `maxmemory-policy` says, until the memory used
+    /// fits in `maxmemory`. Returns `false` when it could not make it fit.
+    ///
+    /// Like Redis, it approximates LRU and LFU: a few keys are sampled out
+    /// of each database and the best candidates kept in a small pool, from
+    /// which the most idle, least used or soonest to expire one goes.
+    fn free_memory(&self) -> bool {
+        let (limit, policy, samples) = {
+            let config = self.config();
+            let samples = config.maxmemory_samples;
+            (config.maxmemory, config.maxmemory_policy, samples)
+        };
+        if limit == 0 {
+            return true;
+        }
+        let mut dbs = self.dbs();
+        let volatile = policy.is_volatile();
+        let mut pool: Vec<(u64, usize, Bytes)> = Vec::new();
+        while dbs.iter().map(Cache::used_memory).sum::<usize>() > limit {
+            let candidate = match policy {
+                MaxMemoryPolicy::NoEviction => return false,
+                MaxMemoryPolicy::VolatileRandom | MaxMemoryPolicy::AllKeysRandom => {
+                    // Databases take turns, so none is emptied before the others.
+                    let first = self.evicted_keys.load(AtomicOrdering::Relaxed) as usize;
+                    let databases = dbs.len();
+                    (0..databases)
+                        .map(|i| (first + i) % databases)
+                        .find_map(|i| dbs[i].sample(1, volatile).pop().map(|key| (i, key)))
+                }
+                _ => {
+                    for (i, cache) in dbs.iter_mut().enumerate() {
+                        for key in cache.sample(samples, volatile) {
+                            // The higher the score, the sooner the key goes.
+                            let score = match policy {
+                                MaxMemoryPolicy::VolatileLfu | MaxMemoryPolicy::AllKeysLfu => {
+                                    u8::MAX as u64 - cache.freq(&key).unwrap_or_default() as u64
+                                }
+                                MaxMemoryPolicy::VolatileTtl => {
+                                    u64::MAX - cache.deadline(&key).unwrap_or(u64::MAX)
+                                }
+                                _ => cache.idle(&key).unwrap_or_default(),
+                            };
+                            if !pool.iter().any(|(_, d, k)| *d == i && *k == key) {
+                                pool.push((score, i, key));
+                            }
+                        }
+                    }
+                    pool.sort_by_key(|(score, _, _)| *score);
+                    pool.drain(..pool.len().saturating_sub(EVICTION_POOL_SIZE));
+                    pool.pop().map(|(_, i, key)| (i, key))
+                }
+            };
+            let (i, key) = match candidate {
+                Some(candidate) => candidate,
+                None => return false,
+            };
+            if dbs[i].remove(&key).is_some() {
+                self.evicted_keys.fetch_add(1, AtomicOrdering::Relaxed);
+                self.propagate(i, &[b"DEL", &key]);
+            }
+        }
+        true
+    }
 
-        if let Ok(value) = previous {
-            Response::text(&value)
+    /// Checks a database index given by a client.
+    fn db_index(&self, index: i64) -> Result<usize, CommandError> {
+        match usize::try_from(index) {
+            Ok(db) if db < self.config().databases => Ok(db),
+            _ => Err(CommandError::Other("DB index is out of range".into())),
+        }
+    }
+
+    fn handle_swapdb(&self, db: usize, first: i64, second: i64) -> Response {
+        let (first, second) = match (self.db_index(first), self.db_index(second)) {
+            (Ok(first), Ok(second)) => (first, second),
+            (Err(e), _) | (_, Err(e)) => return e.into(),
+        };
+        let mut dbs = self.dbs();
+        dbs.swap(first, second);
+        self.persistence.touch(1);
+        let (first, second) = (first.to_string(), second.to_string());
+        self.propagate(db, &[b"SWAPDB", first.as_bytes(), second.as_bytes()]);
+        Response::ok()
+    }
+
+    fn handle_move(&self, db: usize, key: &Bytes, index: i64) -> Response {
+        let dst = match self.db_index(index) {
+            Ok(dst) => dst,
+            Err(e) => return e.into(),
+        };
+        if dst == db {
+            let msg = "source and destination objects are the same";
+            return CommandError::Other(msg.into()).into();
+        }
+        let mut dbs = self.dbs();
+        if dbs[dst].value(key).is_ok() {
+            return Response::integer(0);
+        }
+        let (value, deadline) = match dbs[db].remove(key) {
+            Some(item) => item,
+            None => return Response::integer(0),
+        };
+        dbs[dst].put(key.clone(), value, deadline);
+        self.persistence.touch(1);
+        let index = dst.to_string();
+        self.propagate(db, &[b"MOVE", key, index.as_bytes()]);
+        Response::integer(1)
+    }
+
+    /// Empties the database `db`, or all of them. Lazily flushed keys are
+    /// freed on another thread, so large databases do not block clients.
+    fn handle_flush(&self, db: Option<usize>, lazy: bool) -> Response {
+        let mut dbs = self.dbs();
+        let flushed: Vec<_> = match db {
+            Some(db) => vec![dbs[db].take()],
+            None => dbs.iter_mut().map(Cache::take).collect(),
+        };
+        let removed: usize = flushed.iter().map(Cache::len).sum();
+        self.persistence.touch(removed as u64);
+        let mut args: Vec<&[u8]> = match db {
+            Some(_) => vec![b"FLUSHDB"],
+            None => vec![b"FLUSHALL"],
+        };
+        if lazy {
+            args.push(b"ASYNC");
+        }
+        self.propagate(db.unwrap_or_default(), &args);
+        drop(dbs);
+        if lazy {
+            thread::spawn(move || drop(flushed));
+        }
+        Response::ok()
+    }
+
+    /// Fields of each `INFO` section, in the order Redis lists them.
+    fn info_sections(&self) -> Vec<(&'static str, Vec<(String, String)>)> {
+        let status = |ok| if ok { "ok" } else { "err" }.to_string();
+        let (expired_keys, used_memory) = {
+            let dbs = self.dbs();
+            let expired: u64 = dbs.iter().map(Cache::expired).sum();
+            (expired, dbs.iter().map(Cache::used_memory).sum::<usize>())
+        };
+        let (maxmemory, policy) = {
+            let config = self.config();
+            (config.maxmemory, config.maxmemory_policy)
+        };
+        let stale_perc = *self
+            .expired_stale_perc
+            .lock()
+            .expect("shared access to stats");
+        vec![
+            (
+                "Server",
+                vec![
+                    ("redis_version".to_string(), REDIS_VERSION.to_string()),
+                    ("redis_mode".to_string(), "standalone".to_string()),
+                    ("process_id".to_string(), std::process::id().to_string()),
+                    (
+                        "uptime_in_seconds".to_string(),
+                        self.started_at.elapsed().as_secs().to_string(),
+                    ),
+                ],
+            ),
+            (
+                "Memory",
+                vec![
+                    ("used_memory".to_string(), used_memory.to_string()),
+                    ("maxmemory".to_string(), maxmemory.to_string()),
+                    ("maxmemory_policy".to_string(), policy.name().to_string()),
+                ],
+            ),
+            (
+                "Persistence",
+                vec![
+                    ("loading".to_string(), "0".to_string()),
+                    (
+                        "aof_enabled".to_string(),
+                        (self.aof.is_enabled() as u8).to_string(),
+                    ),
+                    (
+                        "aof_rewrite_in_progress".to_string(),
+                        (self.aof.rewrite_in_progress() as u8).to_string(),
+                    ),
+                    (
+                        "aof_last_bgrewrite_status".to_string(),
+                        status(self.aof.last_rewrite_ok()),
+                    ),
+                    (
+                        "rdb_changes_since_last_save".to_string(),
+                        self.persistence.dirty().to_string(),
+                    ),
+                    (
+                        "rdb_bgsave_in_progress".to_string(),
+                        (self.persistence.bgsave_in_progress() as u8).to_string(),
+                    ),
+                    (
+                        "rdb_last_save_time".to_string(),
+                        self.persistence.last_save().to_string(),
+                    ),
+                    (
+                        "rdb_last_bgsave_status".to_string(),
+                        status(self.persistence.last_bgsave_ok()),
+                    ),
+                ],
+            ),
+            (
+                "Stats",
+                vec![
+                    ("expired_keys".to_string(), expired_keys.to_string()),
+                    ("expired_stale_perc".to_string(), format!("{stale_perc:.2}")),
+                    (
+                        "evicted_keys".to_string(),
+                        self.evicted_keys.load(AtomicOrdering::Relaxed).to_string(),
+                    ),
+                ],
+            ),
+            ("Keyspace", self.keyspace()),
+        ]
+    }
+
+    /// Key counts of every database holding keys.
+    fn keyspace(&self) -> Vec<(String, String)> {
+        let dbs = self.dbs();
+        let dbs = dbs.iter().enumerate().filter(|(_, cache)| cache.len() > 0);
+        dbs.map(|(db, cache)| {
+            let keys = format!("keys={},expires={},avg_ttl=0", cache.len(), cache.expires());
+            (format!("db{db}"), keys)
+        })
+        .collect()
+    }
+
+    fn handle_info(&self, sections: &[Bytes]) -> Response {
+        let wanted: Vec<_> = sections
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).to_lowercase())
+            .collect();
+        let everything = wanted.is_empty()
+            || wanted
+                .iter()
+                .any(|s| matches!(s.as_str(), "all" | "default" | "everything"));
+        let info: Vec<_> = self
+            .info_sections()
+            .into_iter()
+            .filter(|(name, _)| everything || wanted.contains(&name.to_lowercase()))
+            .map(|(name, fields)| {
+                let fields: String = fields
+                    .iter()
+                    .map(|(key, value)| format!("{key}:{value}\r\n"))
+                    .collect();
+                format!("# {name}\r\n{fields}")
+            })
+            .collect();
+        Response::verbatim("txt", info.join("\r\n").as_bytes())
+    }
+
+    fn handle_save(&self) -> Response {
+        let (path, options) = {
+            let config = self.config();
+            (config.local_store_path(), config.save_options())
+        };
+        let (entries, dirty) = self.snapshot();
+        match self.persistence.save(path, entries, dirty, options) {
+            Ok(true) => Response::ok(),
+            Ok(false) => CommandError::Other("Background save already in progress".into()).into(),
+            Err(e) => {
+                eprintln!("Error saving DB on disk: {e}");
+                CommandError::Other("Error saving DB on disk".into()).into()
+            }
+        }
+    }
+
+    fn handle_bgrewriteaof(&self) -> Response {
+        match self.rewrite_aof() {
+            Ok(true) => Response::text("Background append only file rewriting started"),
+            Ok(false) => CommandError::Other(
+                "Background append only file rewriting already in progress".into(),
+            )
+            .into(),
+            Err(e) => CommandError::Other(format!("Background AOF rewrite failed: {e}")).into(),
+        }
+    }
+
+    fn handle_bgsave(&self) -> Response {
+        let (path, options) = {
+            let config = self.config();
+            (config.local_store_path(), config.save_options())
+        };
+        let (entries, dirty) = self.snapshot();
+        if self.persistence.bgsave(path, entries, dirty, options) {
+            Response::text("Background saving started")
         } else {
-            Response::ok()
+            CommandError::Other("Background save already in progress".into()).into()
+        }
+    }
+
+    fn handle_keys(&self, db: usize, pattern: &[u8]) -> Response {
+        let dbs = self.dbs();
+        let keys: Vec<_> = dbs[db]
+            .keys()
+            .filter(|k| glob::matches(pattern, k))
+            .collect();
+        Response::array(&keys)
+    }
+
+    fn handle_config(&self, cmd: &ConfigCmd) -> Response {
+        match cmd {
+            ConfigCmd::Get(keys) => {
+                let config = self.config();
+                let pairs = keys.iter().map(|key| match key {
+                    ConfigKey::Dir => config_pair("dir", config.dir.to_str().unwrap()),
+                    ConfigKey::DbFilename => config_pair("dbfilename", &config.db_filename),
+                    ConfigKey::ClientQueryBufferLimit => {
+                        let limit = config.client_query_buffer_limit.to_string();
+                        config_pair("client-query-buffer-limit", &limit)
+                    }
+                    ConfigKey::Save => config_pair("save", &config::save_params(&config.save)),
+                    ConfigKey::RdbCompression => config_pair(
+                        "rdbcompression",
+                        config::yes_no_name(config.rdb_compression),
+                    ),
+                    ConfigKey::RdbChecksum => {
+                        config_pair("rdbchecksum", config::yes_no_name(config.rdb_checksum))
+                    }
+                    ConfigKey::AppendOnly => {
+                        config_pair("appendonly", config::yes_no_name(config.append_only))
+                    }
+                    ConfigKey::AppendFsync => {
+                        config_pair("appendfsync", config.append_fsync.name())
+                    }
+                    ConfigKey::AutoAofRewritePercentage => {
+                        let percentage = config.auto_aof_rewrite_percentage.to_string();
+                        config_pair("auto-aof-rewrite-percentage", &percentage)
+                    }
+                    ConfigKey::AutoAofRewriteMinSize => {
+                        let size = config.auto_aof_rewrite_min_size.to_string();
+                        config_pair("auto-aof-rewrite-min-size", &size)
+                    }
+                    ConfigKey::Databases => config_pair("databases", &config.databases.to_string()),
+                    ConfigKey::MaxMemory => config_pair("maxmemory", &config.maxmemory.to_string()),
+                    ConfigKey::MaxMemoryPolicy => {
+                        config_pair("maxmemory-policy", config.maxmemory_policy.name())
+                    }
+                    ConfigKey::MaxMemorySamples => {
+                        let samples = config.maxmemory_samples.to_string();
+                        config_pair("maxmemory-samples", &samples)
+                    }
+                });
+                Response::map(pairs.collect())
+            }
+            ConfigCmd::Set(pairs) => {
+                // Every parameter is validated before any of them is applied.
+                let mut config = self.config.write().expect("unique access to config");
+                let mut updated = config.clone();
+                for (key, value) in pairs {
+                    let (name, valid) = match key {
+                        ConfigKey::Dir => {
+                            updated.dir = value.into();
+                            ("dir", updated.dir.is_dir())
+                        }
+                        ConfigKey::DbFilename => {
+                            updated.db_filename = value.clone();
+                            ("dbfilename", !value.contains('/'))
+                        }
+                        ConfigKey::ClientQueryBufferLimit => match config::memory(value) {
+                            Some(limit) => {
+                                updated.client_query_buffer_limit = limit;
+                                ("client-query-buffer-limit", true)
+                            }
+                            None => ("client-query-buffer-limit", false),
+                        },
+                        ConfigKey::Save => match config::save_points(value) {
+                            Some(points) => {
+                                updated.save = points;
+                                ("save", true)
+                            }
+                            None => ("save", false),
+                        },
+                        ConfigKey::RdbCompression => match config::yes_no(value) {
+                            Some(on) => {
+                                updated.rdb_compression = on;
+                                ("rdbcompression", true)
+                            }
+                            None => ("rdbcompression", false),
+                        },
+                        ConfigKey::RdbChecksum => match config::yes_no(value) {
+                            Some(on) => {
+                                updated.rdb_checksum = on;
+                                ("rdbchecksum", true)
+                            }
+                            None => ("rdbchecksum", false),
+                        },
+                        // Turning the AOF on or off at runtime is not supported yet.
+                        ConfigKey::AppendOnly => (
+                            "appendonly",
+                            config::yes_no(value) == Some(updated.append_only),
+                        ),
+                        ConfigKey::AppendFsync => {
+                            match config::AppendFsync::try_from(value.as_str()) {
+                                Ok(fsync) => {
+                                    updated.append_fsync = fsync;
+                                    ("appendfsync", true)
+                                }
+                                Err(_) => ("appendfsync", false),
+                            }
+                        }
+                        ConfigKey::AutoAofRewritePercentage => match value.parse() {
+                            Ok(percentage) => {
+                                updated.auto_aof_rewrite_percentage = percentage;
+                                ("auto-aof-rewrite-percentage", true)
+                            }
+                            Err(_) => ("auto-aof-rewrite-percentage", false),
+                        },
+                        ConfigKey::AutoAofRewriteMinSize => match config::memory(value) {
+                            Some(size) => {
+                                updated.auto_aof_rewrite_min_size = size;
+                                ("auto-aof-rewrite-min-size", true)
+                            }
+                            None => ("auto-aof-rewrite-min-size", false),
+                        },
+                        // Only read at startup, like in Redis.
+                        ConfigKey::Databases => ("databases", false),
+                        ConfigKey::MaxMemory => match config::memory(value) {
+                            Some(limit) => {
+                                updated.maxmemory = limit;
+                                ("maxmemory", true)
+                            }
+                            None => ("maxmemory", false),
+                        },
+                        ConfigKey::MaxMemoryPolicy => {
+                            match MaxMemoryPolicy::try_from(value.as_str()) {
+                                Ok(policy) => {
+                                    updated.maxmemory_policy = policy;
+                                    ("maxmemory-policy", true)
+                                }
+                                Err(_) => ("maxmemory-policy", false),
+                            }
+                        }
+                        ConfigKey::MaxMemorySamples => match value.parse() {
+                            Ok(samples) if samples > 0 => {
+                                updated.maxmemory_samples = samples;
+                                ("maxmemory-samples", true)
+                            }
+                            _ => ("maxmemory-samples", false),
+                        },
+                    };
+                    if !valid {
+                        let msg = format!(
+                            "CONFIG SET failed (possibly related to argument '{name}') - Invalid argument '{value}'"
+                        );
+                        return CommandError::Other(msg).into();
+                    }
+                }
+                self.aof.set_fsync(updated.append_fsync);
+                *config = updated;
+                Response::ok()
+            }
+        }
+    }
+
+    fn handle_command(&self, cmd: &CommandCmd) -> Response {
+        match cmd {
+            CommandCmd::List => Response::list(
+                introspection::specs(&[])
+                    .into_iter()
+                    .flatten()
+                    .map(introspection::info)
+                    .collect(),
+            ),
+            CommandCmd::Count => Response::integer(table::all().len() as i64),
+            CommandCmd::Info(names) => {
+                let infos = introspection::specs(names)
+                    .into_iter()
+                    .map(|s| s.map(introspection::info).unwrap_or_else(Response::null))
+                    .collect();
+                Response::list(infos)
+            }
+            CommandCmd::Docs(names) => {
+                let docs = introspection::specs(names)
+                    .into_iter()
+                    .flatten()
+                    .map(|s| (Response::bulk(s.name.as_bytes()), introspection::docs(s)))
+                    .collect();
+                Response::map(docs)
+            }
+            CommandCmd::GetKeys(args) => introspection::getkeys(args),
+        }
+    }
+
+    fn handle_hello(&self, session: &mut Session, hello: &Hello) -> Response {
+        let protocol = match hello.protover.map(Protocol::try_from) {
+            Some(Ok(protocol)) => protocol,
+            Some(Err(_)) => return CommandError::NoProto.into(),
+            None => session.protocol,
+        };
+        if let Some((user, _)) = &hello.auth {
+            if user.as_ref() != b"default" {
+                return CommandError::WrongPass.into();
+            }
+        }
+        if let Some(name) = &hello.setname {
+            session.name = Some(name.clone());
+        }
+        session.protocol = protocol;
+
+        Response::map(vec![
+            (Response::bulk(b"server"), Response::bulk(b"redis")),
+            (
+                Response::bulk(b"version"),
+                Response::bulk(REDIS_VERSION.as_bytes()),
+            ),
+            (
+                Response::bulk(b"proto"),
+                Response::integer(protocol.version()),
+            ),
+            (Response::bulk(b"id"), Response::integer(session.id as i64)),
+            (Response::bulk(b"mode"), Response::bulk(b"standalone")),
+            (Response::bulk(b"role"), Response::bulk(b"master")),
+            (Response::bulk(b"modules"), Response::list(vec![])),
+        ])
+    }
+
+    fn handle_get(&self, db: usize, k: &Bytes) -> Response {
+        let mut dbs = self.dbs();
+        match dbs[db].value(k).map(Object::as_string) {
+            Ok(Ok(value)) => Response::bulk(value),
+            Ok(Err(e)) => e.into(),
+            Err(_) => Response::null(),
+        }
+    }
+
+    /// Logs a change made to the database `db` to the AOF.
+    fn propagate(&self, db: usize, args: &[&[u8]]) {
+        if let Err(e) = self.aof.feed(db, args) {
+            eprintln!("Error writing to the AOF file: {e}");
+        }
+    }
+
+    /// Logs a `SET`. Relative expirations are written as unix deadlines, so
+    /// replaying the log later does not extend them.
+    fn propagate_set(&self, db: usize, key: &[u8], value: &[u8], deadline: Option<u64>) {
+        let at = deadline.map(|ms| ms.to_string());
+        let mut args: Vec<&[u8]> = vec![b"SET", key, value];
+        if let Some(at) = &at {
+            args.extend([b"PXAT".as_slice(), at.as_bytes()]);
+        }
+        self.propagate(db, &args);
+    }
+
+    fn handle_set(
+        &self,
+        db: usize,
+        key: &Bytes,
+        value: &Bytes,
+        options: &SetOptions,
+        received_at: time::Instant,
+    ) -> Response {
+        let mut dbs = self.dbs();
+        let cache = &mut dbs[db];
+        let previous = match cache.value(key) {
+            Ok(object) if options.get => match object.as_string() {
+                Ok(value) => Some(value.clone()),
+                Err(e) => return e.into(),
+            },
+            // Without GET only whether there was a value matters.
+            Ok(_) => Some(Bytes::new()),
+            Err(_) => None,
+        };
+        let write = match options.condition {
+            Condition::Always => true,
+            Condition::IfMissing => previous.is_none(),
+            Condition::IfExists => previous.is_some(),
+        };
+
+        if write {
+            let deadline = match options.expiry {
+                None => None,
+                Some(Expiry::In(ttl)) => {
+                    Some(unix_ms_of(received_at).saturating_add(ttl.as_millis() as u64))
+                }
+                Some(Expiry::At(at)) => Some(unix_ms(at)),
+                Some(Expiry::Keep) => cache.deadline(key),
+            };
+            cache.put(key.clone(), Object::String(value.clone()), deadline);
+            self.persistence.touch(1);
+            self.propagate_set(db, key, value, deadline);
+        }
+
+        match (options.get, previous) {
+            (true, Some(previous)) => Response::bulk(&previous),
+            (true, None) => Response::null(),
+            (false, _) if write => Response::ok(),
+            (false, _) => Response::null(),
         }
     }
 }
@@ -95,17 +1108,797 @@ mod tests {
         time::{Duration, Instant},
     };
 
+    fn expire_in(ttl: Duration) -> SetOptions {
+        SetOptions {
+            expiry: Some(Expiry::In(ttl)),
+            ..Default::default()
+        }
+    }
+
+    fn temp_config(name: &str) -> Config {
+        let dir = std::env::temp_dir().join(format!("redis-{}-{name}", std::process::id()));
+        Config {
+            dir,
+            ..Config::default()
+        }
+    }
+
     #[test]
     fn test_set_get() {
         let dur = Duration::from_millis(100);
-        let set = Command::Set("k".into(), "v".into(), Some(dur));
+        let set = Command::Set("k".into(), "v".into(), expire_in(dur));
         let get = Command::Get("k".into());
-        let cfg = Config::default();
-        let sut = Redis::new(cfg).unwrap();
+        let sut = Redis::new(temp_config("set_get")).unwrap();
+        let mut session = Session::default();
         let now = Instant::now();
-        assert_eq!(sut.handle(&set, now), Some(Response::ok()));
-        assert_eq!(sut.handle(&get, now), Some(Response::text("v")));
+        assert_eq!(sut.handle(&mut session, &set, now), Response::ok());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
         thread::sleep(dur);
-        assert_eq!(sut.handle(&get, Instant::now()), Some(Response::null()));
+        assert_eq!(
+            sut.handle(&mut session, &get, Instant::now()),
+            Response::null()
+        );
+    }
+
+    #[test]
+    fn test_set_options() {
+        let cfg = temp_config("set_options");
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let mut set = |value: &'static str, options| {
+            let set = Command::Set("k".into(), value.into(), options);
+            sut.handle(&mut session, &set, now)
+        };
+        let nx = SetOptions {
+            condition: Condition::IfMissing,
+            ..Default::default()
+        };
+        let xx_get = SetOptions {
+            condition: Condition::IfExists,
+            get: true,
+            ..Default::default()
+        };
+        assert_eq!(set("0", xx_get), Response::null());
+        assert_eq!(set("1", nx), Response::ok());
+        assert_eq!(set("2", nx), Response::null());
+        assert_eq!(set("3", SetOptions::default()), Response::ok());
+        assert_eq!(set("4", xx_get), Response::bulk(b"3"));
+        let get_nx = SetOptions { get: true, ..nx };
+        assert_eq!(set("5", get_nx), Response::bulk(b"4"));
+
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        let at = SetOptions {
+            expiry: Some(Expiry::At(in_an_hour)),
+            ..Default::default()
+        };
+        assert_eq!(set("6", at), Response::ok());
+        let keep = SetOptions {
+            expiry: Some(Expiry::Keep),
+            ..Default::default()
+        };
+        assert_eq!(set("7", keep), Response::ok());
+        assert!(sut.dbs()[0].deadline(&"k".into()).is_some());
+
+        let past = SetOptions {
+            expiry: Some(Expiry::At(time::UNIX_EPOCH + Duration::from_secs(1))),
+            ..Default::default()
+        };
+        assert_eq!(set("8", past), Response::ok());
+        let get = Command::Get("k".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+
+        sut.handle(&mut session, &Command::Set("k".into(), "9".into(), at), now);
+        let list = Object::List(["a".into()].into());
+        sut.dbs()[0].put("l".into(), list, None);
+        let set = Command::Set("l".into(), "v".into(), xx_get);
+        let reply = sut.handle(&mut session, &set, now);
+        assert_eq!(reply, Response::from(CommandError::WrongType));
+
+        // Absolute deadlines survive a restart.
+        sut.handle(&mut session, &Command::Save, now);
+        let sut = Redis::new(cfg).unwrap();
+        let deadline = sut.dbs()[0].deadline(&"k".into()).unwrap();
+        assert_eq!(deadline, unix_ms(in_an_hour));
+    }
+
+    #[test]
+    fn test_expire() {
+        let cfg = temp_config("expire");
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let mut run = |cmd: Command| sut.handle(&mut session, &cmd, now);
+        let expire = |timeout, condition, compare| {
+            let options = ExpireOptions { condition, compare };
+            Command::Expire("k".into(), timeout, options)
+        };
+        let ttl = |millis| Command::Ttl("k".into(), millis);
+        let always = Condition::Always;
+
+        assert_eq!(
+            run(expire(Timeout::In(1000), always, None)),
+            Response::integer(0)
+        );
+        assert_eq!(run(ttl(false)), Response::integer(-2));
+        run(Command::Set("k".into(), "v".into(), SetOptions::default()));
+        assert_eq!(run(ttl(false)), Response::integer(-1));
+        assert_eq!(run(Command::Persist("k".into())), Response::integer(0));
+
+        // Keys without a deadline compare as never expiring.
+        let gt = Some(Ordering::Greater);
+        let lt = Some(Ordering::Less);
+        assert_eq!(
+            run(expire(Timeout::In(10_000), always, gt)),
+            Response::integer(0)
+        );
+        let xx = Condition::IfExists;
+        assert_eq!(
+            run(expire(Timeout::In(10_000), xx, None)),
+            Response::integer(0)
+        );
+        assert_eq!(
+            run(expire(Timeout::In(10_000), always, lt)),
+            Response::integer(1)
+        );
+        assert_eq!(run(ttl(false)), Response::integer(10));
+        let pttl = run(ttl(true));
+        assert!((9_000..=10_000).any(|ms| pttl == Response::integer(ms)));
+        let nx = Condition::IfMissing;
+        assert_eq!(
+            run(expire(Timeout::In(20_000), nx, None)),
+            Response::integer(0)
+        );
+        assert_eq!(
+            run(expire(Timeout::In(5_000), xx, gt)),
+            Response::integer(0)
+        );
+        assert_eq!(
+            run(expire(Timeout::In(20_000), xx, gt)),
+            Response::integer(1)
+        );
+
+        let in_an_hour = now_ms() as i64 + 3_600_000;
+        assert_eq!(
+            run(expire(Timeout::At(in_an_hour), always, None)),
+            Response::integer(1)
+        );
+        let expiretime = |millis| Command::ExpireTime("k".into(), millis);
+        assert_eq!(run(expiretime(true)), Response::integer(in_an_hour));
+        assert_eq!(run(expiretime(false)), Response::integer(in_an_hour / 1000));
+
+        // Deadlines survive a restart as they are, not as a TTL.
+        run(Command::Save);
+        let sut = Redis::new(cfg).unwrap();
+        let mut session = Session::default();
+        let mut run = |cmd: Command| sut.handle(&mut session, &cmd, now);
+        assert_eq!(run(expiretime(true)), Response::integer(in_an_hour));
+        assert_eq!(run(Command::Persist("k".into())), Response::integer(1));
+        assert_eq!(run(expiretime(false)), Response::integer(-1));
+
+        // A deadline in the past deletes the key.
+        assert_eq!(
+            run(expire(Timeout::In(-1), always, None)),
+            Response::integer(1)
+        );
+        assert_eq!(run(Command::Get("k".into())), Response::null());
+        assert_eq!(run(expiretime(false)), Response::integer(-2));
+    }
+
+    #[test]
+    fn test_active_expire() {
+        let sut = Redis::new(temp_config("active_expire")).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        {
+            let mut dbs = sut.dbs();
+            for i in 0..200 {
+                let deadline = if i < 150 { now_ms() } else { now_ms() + 60_000 };
+                dbs[1].put(
+                    format!("k{i}").into(),
+                    Object::String("v".into()),
+                    Some(deadline),
+                );
+            }
+        }
+        sut.active_expire_cycle(Duration::from_secs(1));
+
+        // Stops sampling once most keys in a sample are still live.
+        let dbs = sut.dbs();
+        let expired = dbs[1].expired();
+        assert!(expired >= 100);
+        assert_eq!(dbs[1].len() as u64 + expired, 200);
+        drop(dbs);
+        let info = sut.handle(&mut session, &Command::Info(vec!["stats".into()]), now);
+        let info = String::from_utf8(info.encode(Protocol::Resp2)).unwrap();
+        let field = |name: &str| {
+            let prefix = format!("{name}:");
+            let line = info.lines().find(|l| l.starts_with(&prefix));
+            line.map(|l| l[prefix.len()..].to_string())
+        };
+        assert_eq!(field("expired_keys"), Some(expired.to_string()));
+        assert_ne!(field("expired_stale_perc").as_deref(), Some("0.00"));
+    }
+
+    #[test]
+    fn test_active_expire_every_db() {
+        let sut = Redis::new(temp_config("active_expire_every_db")).unwrap();
+        {
+            let mut dbs = sut.dbs();
+            for i in 0..10_000 {
+                let key = format!("k{i}").into();
+                dbs[0].put(key, Object::String("v".into()), Some(now_ms()));
+            }
+            dbs[2].put("k".into(), Object::String("v".into()), Some(now_ms()));
+        }
+
+        // Database 0 alone would use up every cycle.
+        let databases = sut.dbs().len();
+        for _ in 0..databases {
+            sut.active_expire_cycle(Duration::ZERO);
+        }
+        let dbs = sut.dbs();
+        assert_eq!(dbs[2].expired(), 1);
+        assert_eq!(dbs[2].len(), 0);
+    }
+
+    #[test]
+    fn test_maxmemory() {
+        let cfg = Config {
+            maxmemory: 2000,
+            ..temp_config("maxmemory")
+        };
+        let sut = Redis::new(cfg).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let mut run = |cmd: Command| sut.handle(&mut session, &cmd, now);
+        let value = Bytes::from(vec![b'v'; 100]);
+        let set = |key: String, options| Command::Set(key.into(), value.clone(), options);
+        let volatile = expire_in(Duration::from_secs(60));
+        let policy = |name: &str| {
+            let policy = (ConfigKey::MaxMemoryPolicy, name.to_string());
+            Command::Config(ConfigCmd::Set(vec![policy]))
+        };
+
+        // Writes are refused once full, reads still work.
+        for i in 0..20 {
+            run(set(format!("k{i}"), SetOptions::default()));
+        }
+        assert_eq!(
+            run(set("more".into(), SetOptions::default())),
+            Response::from(CommandError::Oom)
+        );
+        assert_eq!(run(Command::Get("k0".into())), Response::bulk(&value));
+        let freq = run(Command::Object(ObjectCmd::Freq("k0".into())));
+        assert!(matches!(freq.encode(Protocol::Resp2)[0], b'-'));
+        let idle = run(Command::Object(ObjectCmd::IdleTime("k0".into())));
+        assert_eq!(idle, Response::integer(0));
+
+        // Only keys with a deadline may go, and there are none.
+        assert_eq!(run(policy("volatile-lru")), Response::ok());
+        assert_eq!(
+            run(set("more".into(), SetOptions::default())),
+            Response::from(CommandError::Oom)
+        );
+
+        assert_eq!(run(policy("allkeys-lfu")), Response::ok());
+        let freq = run(Command::Object(ObjectCmd::Freq("k0".into())));
+        assert_eq!(freq, Response::integer(6));
+        for i in 20..40 {
+            assert_eq!(run(set(format!("k{i}"), volatile)), Response::ok());
+        }
+        let used: usize = sut.dbs().iter().map(Cache::used_memory).sum();
+        assert!(used <= 2000 + 200, "{used}");
+
+        // Keys without a deadline stay, whatever their idle time.
+        for name in ["volatile-ttl", "volatile-random", "allkeys-random"] {
+            assert_eq!(run(policy(name)), Response::ok());
+            let persistent = |dbs: MutexGuard<'_, Vec<Cache>>| dbs[0].len() - dbs[0].expires();
+            let before = persistent(sut.dbs());
+            for i in 40..60 {
+                run(set(format!("{name}{i}"), volatile));
+            }
+            if name.starts_with("volatile") {
+                assert_eq!(persistent(sut.dbs()), before);
+            }
+        }
+
+        let info = run(Command::Info(vec!["memory".into(), "stats".into()]));
+        let info = String::from_utf8(info.encode(Protocol::Resp2)).unwrap();
+        assert!(info.contains("maxmemory:2000\r\n"), "{info}");
+        assert!(
+            info.contains("maxmemory_policy:allkeys-random\r\n"),
+            "{info}"
+        );
+        assert!(!info.contains("evicted_keys:0\r\n"), "{info}");
+    }
+
+    #[test]
+    fn test_set_get_binary() {
+        let key = Bytes::from_static(b"\xff\x00key");
+        let value = Bytes::from_static(b"\x89PNG\r\n\x1a\n\x00");
+        let set = Command::Set(key.clone(), value.clone(), SetOptions::default());
+        let get = Command::Get(key);
+        let sut = Redis::new(temp_config("set_get_binary")).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        assert_eq!(sut.handle(&mut session, &set, now), Response::ok());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(&value));
+    }
+
+    #[test]
+    fn test_hello() {
+        let sut = Redis::new(temp_config("hello")).unwrap();
+        let mut session = Session::new(7);
+        let now = Instant::now();
+        let hello = Command::Hello(Hello {
+            protover: Some(3),
+            auth: None,
+            setname: Some("cli".into()),
+        });
+        let reply = sut.handle(&mut session, &hello, now);
+        assert_eq!(session.protocol, Protocol::Resp3);
+        assert_eq!(session.name, Some("cli".into()));
+        assert!(reply.encode(session.protocol).starts_with(b"%7\r\n"));
+
+        let hello = Command::Hello(Hello {
+            protover: Some(4),
+            ..Default::default()
+        });
+        let reply = sut.handle(&mut session, &hello, now);
+        assert_eq!(
+            reply.encode(session.protocol),
+            b"-NOPROTO unsupported protocol version\r\n"
+        );
+        assert_eq!(session.protocol, Protocol::Resp3);
+    }
+
+    #[test]
+    fn test_command() {
+        let sut = Redis::new(temp_config("command")).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let count = sut.handle(&mut session, &Command::Introspect(CommandCmd::Count), now);
+        assert_eq!(count, Response::integer(table::all().len() as i64));
+
+        let getkeys = CommandCmd::GetKeys(vec!["set".into(), "k".into(), "v".into()]);
+        let keys = sut.handle(&mut session, &Command::Introspect(getkeys), now);
+        assert_eq!(keys, Response::array(&["k"]));
+
+        let info = CommandCmd::Info(vec!["get".into(), "nope".into()]);
+        let info = sut.handle(&mut session, &Command::Introspect(info), now);
+        let info = info.encode(Protocol::Resp2);
+        assert!(info.starts_with(b"*2\r\n*10\r\n$3\r\nget\r\n:2\r\n"));
+        assert!(info.ends_with(b"$-1\r\n"));
+    }
+
+    #[test]
+    fn test_save() {
+        let cfg = temp_config("save");
+        let mut session = Session::default();
+        let now = Instant::now();
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let set = Command::Set("k".into(), "v".into(), expire_in(Duration::from_secs(60)));
+        sut.handle(&mut session, &set, now);
+        let set = Command::Set("n".into(), "42".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        assert_eq!(
+            sut.handle(&mut session, &Command::Save, now),
+            Response::ok()
+        );
+        let lastsave = sut.handle(&mut session, &Command::LastSave, now);
+        assert_ne!(lastsave, Response::integer(0));
+
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let get = Command::Get("k".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
+        let get = Command::Get("n".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"42"));
+
+        let set = Command::Set("bg".into(), "1".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        let reply = sut.handle(&mut session, &Command::BgSave, now);
+        assert_eq!(reply, Response::text("Background saving started"));
+        while sut.persistence.bgsave_in_progress() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let sut = Redis::new(cfg).unwrap();
+        let get = Command::Get("bg".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"1"));
+    }
+
+    #[test]
+    fn test_save_usage() {
+        let mut session = Session::default();
+        let now = Instant::now();
+        let save = |policy| {
+            let cfg = Config {
+                maxmemory_policy: policy,
+                ..temp_config("save_usage")
+            };
+            let sut = Redis::new(cfg.clone()).unwrap();
+            let set = Command::Set("k".into(), "v".into(), SetOptions::default());
+            sut.handle(&mut Session::default(), &set, now);
+            let mut dbs = sut.dbs();
+            dbs[0].set_idle(&"k".into(), 120_000);
+            dbs[0].set_freq(&"k".into(), 42);
+            drop(dbs);
+            sut.handle(&mut Session::default(), &Command::Save, now);
+            Redis::new(cfg).unwrap()
+        };
+        let idle = Command::Object(ObjectCmd::IdleTime("k".into()));
+        let freq = Command::Object(ObjectCmd::Freq("k".into()));
+
+        // Each policy only saves the stats it evicts by.
+        let sut = save(MaxMemoryPolicy::AllKeysLru);
+        assert_eq!(sut.handle(&mut session, &idle, now), Response::integer(120));
+        assert_ne!(sut.dbs()[0].freq(&"k".into()), Some(42));
+
+        let sut = save(MaxMemoryPolicy::VolatileLfu);
+        assert_eq!(sut.handle(&mut session, &freq, now), Response::integer(42));
+        assert!(sut.dbs()[0].idle(&"k".into()).unwrap() < 60_000);
+
+        let sut = save(MaxMemoryPolicy::NoEviction);
+        assert_ne!(sut.dbs()[0].freq(&"k".into()), Some(42));
+        assert!(sut.dbs()[0].idle(&"k".into()).unwrap() < 60_000);
+    }
+
+    #[test]
+    fn test_save_points() {
+        let sut = Redis::new(temp_config("save_points")).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let save = vec![(ConfigKey::Save, "900 1 300 10".to_string())];
+        let reply = sut.handle(&mut session, &Command::Config(ConfigCmd::Set(save)), now);
+        assert_eq!(reply, Response::ok());
+        let get = Command::Config(ConfigCmd::Get(vec![ConfigKey::Save]));
+        assert_eq!(
+            sut.handle(&mut session, &get, now),
+            Response::map(vec![config_pair("save", "900 1 300 10")])
+        );
+        let save = vec![(ConfigKey::Save, "900".to_string())];
+        let reply = sut.handle(&mut session, &Command::Config(ConfigCmd::Set(save)), now);
+        assert_ne!(reply, Response::ok());
+
+        let set = Command::Set("k".into(), "v".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        sut.handle(&mut session, &set, now);
+        let info = Command::Info(vec!["persistence".into()]);
+        let info = sut.handle(&mut session, &info, now).encode(Protocol::Resp2);
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("# Persistence\r\n"));
+        assert!(!info.contains("# Server\r\n"));
+        assert!(info.contains("rdb_changes_since_last_save:2\r\n"));
+        assert!(info.contains("rdb_last_bgsave_status:ok\r\n"));
+
+        sut.handle(&mut session, &Command::Save, now);
+        assert_eq!(sut.persistence.dirty(), 0);
+    }
+
+    #[test]
+    fn test_aof() {
+        let cfg = Config {
+            append_only: true,
+            append_fsync: config::AppendFsync::Always,
+            ..temp_config("aof")
+        };
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        std::fs::create_dir_all(&cfg.dir).unwrap();
+        // A single file AOF, as older versions wrote it, becomes the first base.
+        std::fs::write(
+            cfg.aof_path(),
+            b"*3\r\n$3\r\nSET\r\n$3\r\nold\r\n$1\r\n0\r\n",
+        )
+        .unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let set = Command::Set("k".into(), "v".into(), expire_in(Duration::from_secs(60)));
+        sut.handle(&mut session, &set, now);
+        let set = Command::Set("n".into(), "1".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        let set = Command::Set("gone".into(), "1".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        let expire = Command::Expire("gone".into(), Timeout::In(-1), ExpireOptions::default());
+        sut.handle(&mut session, &expire, now);
+        drop(sut);
+
+        let incr = cfg.aof_dir().join("appendonly.aof.1.incr.aof");
+        let aof = std::fs::read(incr).unwrap();
+        let select = b"*2\r\n$6\r\nSELECT\r\n$1\r\n0\r\n";
+        assert!(aof.starts_with(select));
+        assert!(aof[select.len()..]
+            .starts_with(b"*5\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n$4\r\nPXAT\r\n"));
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let get = Command::Get("k".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
+        let get = Command::Get("old".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"0"));
+        let get = Command::Get("gone".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+        assert_eq!(sut.persistence.dirty(), 0);
+
+        let reply = sut.handle(&mut session, &Command::BgRewriteAof, now);
+        assert_eq!(
+            reply,
+            Response::text("Background append only file rewriting started")
+        );
+        let set = Command::Set("during".into(), "1".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        while sut.aof.rewrite_in_progress() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(sut);
+        let sut = Redis::new(cfg).unwrap();
+        for key in ["k", "n", "old", "during"] {
+            let get = Command::Get(key.into());
+            assert_ne!(sut.handle(&mut session, &get, now), Response::null());
+        }
+    }
+
+    #[test]
+    fn test_aof_past_expire() {
+        let cfg = Config {
+            append_only: true,
+            append_fsync: config::AppendFsync::Always,
+            ..temp_config("aof_past_expire")
+        };
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        std::fs::create_dir_all(&cfg.dir).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let sut = Redis::new(cfg.clone()).unwrap();
+        for key in ["a", "b"] {
+            let set = Command::Set(key.into(), "1".into(), SetOptions::default());
+            sut.handle(&mut session, &set, now);
+        }
+        // Keys deleted by a past deadline are logged with it, which deletes
+        // them on replay just the same.
+        let expire = |key: &'static str, timeout| {
+            Command::Expire(key.into(), timeout, ExpireOptions::default())
+        };
+        let reply = sut.handle(&mut session, &expire("a", Timeout::In(-1000)), now);
+        assert_eq!(reply, Response::integer(1));
+        let reply = sut.handle(&mut session, &expire("b", Timeout::At(1)), now);
+        assert_eq!(reply, Response::integer(1));
+        drop(sut);
+
+        let incr = cfg.aof_dir().join("appendonly.aof.1.incr.aof");
+        let aof = String::from_utf8(std::fs::read(incr).unwrap()).unwrap();
+        assert!(
+            aof.contains("*3\r\n$9\r\nPEXPIREAT\r\n$1\r\na\r\n"),
+            "{aof}"
+        );
+        assert!(
+            aof.contains("*3\r\n$9\r\nPEXPIREAT\r\n$1\r\nb\r\n$1\r\n1\r\n"),
+            "{aof}"
+        );
+
+        let sut = Redis::new(cfg).unwrap();
+        for key in ["a", "b"] {
+            let get = Command::Get(key.into());
+            assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+        }
+        let dbsize = sut.handle(&mut session, &Command::DbSize, now);
+        assert_eq!(dbsize, Response::integer(0));
+    }
+
+    #[test]
+    fn test_aof_evict() {
+        let cfg = Config {
+            append_only: true,
+            append_fsync: config::AppendFsync::Always,
+            maxmemory: 2000,
+            maxmemory_policy: MaxMemoryPolicy::AllKeysRandom,
+            ..temp_config("aof_evict")
+        };
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        std::fs::create_dir_all(&cfg.dir).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let value = Bytes::from(vec![b'v'; 100]);
+        for i in 0..40 {
+            let set = Command::Set(format!("k{i}").into(), value.clone(), SetOptions::default());
+            assert_eq!(sut.handle(&mut session, &set, now), Response::ok());
+        }
+        let del = Command::Del(vec!["k39".into(), "k39".into(), "missing".into()]);
+        assert_eq!(sut.handle(&mut session, &del, now), Response::integer(1));
+        let dbsize = sut.handle(&mut session, &Command::DbSize, now);
+        drop(sut);
+
+        let incr = cfg.aof_dir().join("appendonly.aof.1.incr.aof");
+        let aof = String::from_utf8(std::fs::read(incr).unwrap()).unwrap();
+        assert!(aof.contains("*2\r\n$3\r\nDEL\r\n$2\r\nk"), "{aof}");
+        assert!(aof.contains("*2\r\n$3\r\nDEL\r\n$3\r\nk39\r\n"), "{aof}");
+        assert!(!aof.contains("PEXPIREAT"), "{aof}");
+
+        let sut = Redis::new(cfg).unwrap();
+        assert_eq!(sut.handle(&mut session, &Command::DbSize, now), dbsize);
+        let get = Command::Get("k39".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+    }
+
+    #[test]
+    fn test_types() {
+        let cfg = temp_config("types");
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        let list = db::Value::List(vec!["a".into(), "b".into()]);
+        let hash = db::Value::Hash(vec![("f".into(), "v".into())]);
+        let entries = vec![
+            Entry::new("list".into(), list.clone(), None),
+            Entry::new("hash".into(), hash, None),
+            Entry::new("set".into(), db::Value::Set(vec!["1".into()]), None),
+            Entry::new("hll".into(), db::Value::String("HYLL".into()), None),
+        ];
+        let path = cfg.local_store_path();
+        db::save_at(&path, &[], &[entries], db::SaveOptions::default()).unwrap();
+
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let mut reply = |cmd| sut.handle(&mut session, &cmd, now);
+        let encoding = |key: &'static str| Command::Object(ObjectCmd::Encoding(key.into()));
+        assert_eq!(reply(Command::Type("list".into())), Response::text("list"));
+        assert_eq!(reply(Command::Type("hll".into())), Response::text("string"));
+        assert_eq!(reply(Command::Type("nope".into())), Response::text("none"));
+        assert_eq!(reply(encoding("list")), Response::bulk(b"listpack"));
+        assert_eq!(reply(encoding("set")), Response::bulk(b"intset"));
+        assert_eq!(reply(encoding("nope")), Response::null());
+        assert_eq!(
+            reply(Command::Get("hash".into())),
+            Response::from(CommandError::WrongType)
+        );
+        assert_eq!(reply(Command::Get("hll".into())), Response::bulk(b"HYLL"));
+        reply(Command::Set(
+            "set".into(),
+            "42".into(),
+            SetOptions::default(),
+        ));
+        assert_eq!(reply(encoding("set")), Response::bulk(b"int"));
+        assert_eq!(reply(Command::Save), Response::ok());
+
+        let sut = Redis::new(cfg).unwrap();
+        let mut dbs = sut.dbs();
+        let list_key = Bytes::from("list");
+        assert_eq!(dbs[0].value(&list_key).unwrap().to_value(), list);
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let cfg = temp_config("load-missing");
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        let reply = sut.handle(&mut session, &Command::DbSize, Instant::now());
+        assert_eq!(reply, Response::integer(0));
+        assert!(!cfg.dir.exists());
+    }
+
+    #[test]
+    fn test_load_snapshot() {
+        let cfg = temp_config("load");
+        std::fs::create_dir_all(&cfg.dir).unwrap();
+        let in_an_hour = SystemTime::now() + Duration::from_secs(3600);
+        let in_an_hour = in_an_hour.duration_since(time::UNIX_EPOCH).unwrap();
+        let mut rdb = b"REDIS0011\xfe\x00\xfb\x03\x01".to_vec();
+        rdb.extend(b"\x00\x03foo\x03bar");
+        rdb.extend(b"\xfc");
+        rdb.extend((in_an_hour.as_millis() as u64).to_le_bytes());
+        rdb.extend(b"\x00\x03baz\x03qux");
+        rdb.extend(b"\xfc\x01\x00\x00\x00\x00\x00\x00\x00\x00\x04gone\x01x");
+        rdb.extend(b"\xff\x00\x00\x00\x00\x00\x00\x00\x00");
+        std::fs::write(cfg.local_store_path(), rdb).unwrap();
+
+        let sut = Redis::new(cfg).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let get = Command::Get("baz".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"qux"));
+        let get = Command::Get("gone".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+
+        let set = Command::Set("fresh".into(), "1".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        let keys = sut.handle(&mut session, &Command::Keys("ba?".into()), now);
+        assert_eq!(keys, Response::array(&["baz"]));
+        let keys = sut.handle(&mut session, &Command::Keys("f*".into()), now);
+        assert!(
+            keys == Response::array(&["foo", "fresh"])
+                || keys == Response::array(&["fresh", "foo"])
+        );
+    }
+
+    #[test]
+    fn test_databases() {
+        let cfg = temp_config("databases");
+        let _ = std::fs::remove_dir_all(&cfg.dir);
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        let now = Instant::now();
+        let out_of_range = Response::from(CommandError::Other("DB index is out of range".into()));
+        assert_eq!(
+            sut.handle(&mut session, &Command::Select(16), now),
+            out_of_range
+        );
+        let set = Command::Set("k".into(), "v".into(), SetOptions::default());
+        sut.handle(&mut session, &set, now);
+        assert_eq!(
+            sut.handle(&mut session, &Command::Select(3), now),
+            Response::ok()
+        );
+        let get = Command::Get("k".into());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+        sut.handle(
+            &mut session,
+            &Command::Set("k".into(), "3".into(), SetOptions::default()),
+            now,
+        );
+
+        let move_k = Command::Move("k".into(), 0);
+        assert_eq!(sut.handle(&mut session, &move_k, now), Response::integer(0));
+        sut.handle(&mut session, &Command::Select(0), now);
+        let move_k = Command::Move("k".into(), 1);
+        assert_eq!(sut.handle(&mut session, &move_k, now), Response::integer(1));
+        assert_eq!(
+            sut.handle(&mut session, &Command::DbSize, now),
+            Response::integer(0)
+        );
+        let swap = Command::SwapDb(0, 1);
+        assert_eq!(sut.handle(&mut session, &swap, now), Response::ok());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
+
+        let info = Command::Info(vec!["keyspace".into()]);
+        let info = sut.handle(&mut session, &info, now).encode(Protocol::Resp2);
+        let info = String::from_utf8(info).unwrap();
+        assert!(info.contains("db0:keys=1,expires=0,avg_ttl=0\r\n"));
+        assert!(info.contains("db3:keys=1,expires=0,avg_ttl=0\r\n"));
+        assert!(!info.contains("db1:"));
+
+        assert_eq!(
+            sut.handle(&mut session, &Command::Save, now),
+            Response::ok()
+        );
+        let sut = Redis::new(cfg.clone()).unwrap();
+        let mut session = Session::default();
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
+        sut.handle(&mut session, &Command::Select(3), now);
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"3"));
+
+        let flush = Command::FlushDb(true);
+        assert_eq!(sut.handle(&mut session, &flush, now), Response::ok());
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+        sut.handle(&mut session, &Command::Select(0), now);
+        assert_eq!(sut.handle(&mut session, &get, now), Response::bulk(b"v"));
+        sut.handle(&mut session, &Command::FlushAll(false), now);
+        assert_eq!(sut.handle(&mut session, &get, now), Response::null());
+
+        // A snapshot with more databases than configured is refused.
+        let few = Config {
+            databases: 2,
+            ..cfg.clone()
+        };
+        let sut = Redis::new(cfg).unwrap();
+        let mut session = Session::default();
+        sut.handle(&mut session, &Command::Select(3), now);
+        sut.handle(&mut session, &set, now);
+        sut.handle(&mut session, &Command::Save, now);
+        assert!(Redis::new(few).is_err());
+    }
+
+    #[test]
+    fn test_load_corrupt() {
+        let mut cfg = temp_config("load-corrupt");
+        std::fs::create_dir_all(&cfg.dir).unwrap();
+        let mut rdb = b"REDIS0011\xfe\x00\x00\x03foo\x03bar\xff".to_vec();
+        rdb.extend(1u64.to_le_bytes());
+        std::fs::write(cfg.local_store_path(), rdb).unwrap();
+
+        let error = Redis::new(cfg.clone()).err().unwrap();
+        assert!(format!("{error:#}").contains("Bad RDB file at offset 21: checksum mismatch"));
+        cfg.rdb_checksum = false;
+        assert!(Redis::new(cfg).is_ok());
     }
 }
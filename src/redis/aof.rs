@@ -0,0 +1,561 @@
+use super::persistence;
+use crate::{
+    config::AppendFsync,
+    db::{self, Entry, SaveOptions},
+    proto::{decode, encode, Frame, Protocol},
+    scanner, Command,
+};
+use anyhow::{Context, Result};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Base,
+    Incr,
+}
+
+/// One of the files making up the AOF, as listed in the manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AofFile {
+    pub name: String,
+    pub seq: u64,
+    pub kind: FileKind,
+}
+
+impl AofFile {
+    fn base(filename: &str, seq: u64) -> Self {
+        Self {
+            name: format!("{filename}.{seq}.base.rdb"),
+            seq,
+            kind: FileKind::Base,
+        }
+    }
+
+    fn incr(filename: &str, seq: u64) -> Self {
+        Self {
+            name: format!("{filename}.{seq}.incr.aof"),
+            seq,
+            kind: FileKind::Incr,
+        }
+    }
+
+    /// Whether the file is an RDB snapshot rather than a list of commands.
+    pub fn is_rdb(&self) -> bool {
+        self.name.ends_with(".rdb")
+    }
+}
+
+/// Tracks the base file and the incremental files written after it, which
+/// together hold the dataset. Loading replays them in this order.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    pub base: Option<AofFile>,
+    pub incrs: Vec<AofFile>,
+}
+
+impl Manifest {
+    fn path(dir: &Path, filename: &str) -> PathBuf {
+        dir.join(format!("{filename}.manifest"))
+    }
+
+    pub fn read(dir: &Path, filename: &str) -> Result<Option<Self>> {
+        let text = match fs::read_to_string(Self::path(dir, filename)) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut manifest = Self::default();
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            let parts: Vec<_> = line.split_whitespace().collect();
+            let (name, seq, kind) = match parts.as_slice() {
+                ["file", name, "seq", seq, "type", kind] => (name, seq, kind),
+                _ => anyhow::bail!("Invalid AOF manifest line: {line}"),
+            };
+            let kind = match *kind {
+                "b" => FileKind::Base,
+                "i" => FileKind::Incr,
+                // History files are leftovers of a rewrite, never loaded.
+                "h" => continue,
+                other => anyhow::bail!("Unknown AOF file type: {other}"),
+            };
+            let file = AofFile {
+                name: name.to_string(),
+                seq: seq.parse().context("Invalid AOF manifest seq")?,
+                kind,
+            };
+            match file.kind {
+                FileKind::Base => manifest.base = Some(file),
+                FileKind::Incr => manifest.incrs.push(file),
+            }
+        }
+        Ok(Some(manifest))
+    }
+
+    /// Replaces the manifest on disk atomically.
+    fn write(&self, dir: &Path, filename: &str) -> Result<()> {
+        let mut text = String::new();
+        for file in self.files() {
+            let kind = match file.kind {
+                FileKind::Base => "b",
+                FileKind::Incr => "i",
+            };
+            text.push_str(&format!(
+                "file {} seq {} type {kind}\n",
+                file.name, file.seq
+            ));
+        }
+        let temp = dir.join(format!("temp-{filename}.manifest"));
+        let mut file = File::create(&temp)?;
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&temp, Self::path(dir, filename))?;
+        Ok(())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &AofFile> {
+        self.base.iter().chain(&self.incrs)
+    }
+
+    fn next_base(&self, filename: &str) -> AofFile {
+        let seq = self.base.as_ref().map(|b| b.seq).unwrap_or_default();
+        AofFile::base(filename, seq + 1)
+    }
+
+    fn next_incr(&self, filename: &str) -> AofFile {
+        let seq = self.incrs.last().map(|i| i.seq).unwrap_or_default();
+        AofFile::incr(filename, seq + 1)
+    }
+}
+
+struct Log {
+    dir: PathBuf,
+    filename: String,
+    manifest: Manifest,
+    file: File,
+    fsync: AppendFsync,
+    last_fsync: Instant,
+    pending: bool,
+    fsync_in_progress: Arc<AtomicBool>,
+    current_size: u64,
+    base_size: u64,
+    rewrite_in_progress: bool,
+    last_rewrite_ok: bool,
+    /// Database the commands in the current file apply to, `None` until
+    /// the file selects one.
+    selected_db: Option<usize>,
+}
+
+impl Log {
+    fn write(&mut self, args: &[&[u8]]) -> Result<()> {
+        let items = args.iter().map(|a| Frame::Bulk(a.to_vec().into()));
+        let frame = encode::frame(&Frame::Array(items.collect()), Protocol::Resp2);
+        self.file.write_all(&frame)?;
+        self.current_size += frame.len() as u64;
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        let files = self.manifest.files();
+        let sizes = files.map(|f| fs::metadata(self.dir.join(&f.name)));
+        sizes.flatten().map(|m| m.len()).sum()
+    }
+
+    /// Swaps the files `rewrite` replaced for its base in the manifest,
+    /// then deletes them.
+    fn finish_rewrite(&mut self, rewrite: &Rewrite) -> Result<()> {
+        let (replaced, kept): (Vec<_>, Vec<_>) =
+            (self.manifest.incrs.iter().cloned()).partition(|incr| incr.seq < rewrite.first_incr);
+        let manifest = Manifest {
+            base: Some(rewrite.base.clone()),
+            incrs: kept,
+        };
+        if let Err(e) = manifest.write(&self.dir, &self.filename) {
+            let _ = fs::remove_file(self.dir.join(&rewrite.base.name));
+            return Err(e);
+        }
+        let replaced = self.manifest.base.iter().chain(&replaced);
+        for file in replaced {
+            let _ = fs::remove_file(self.dir.join(&file.name));
+        }
+        self.manifest = manifest;
+        self.current_size = self.size();
+        self.base_size = self.current_size;
+        Ok(())
+    }
+}
+
+/// A rewrite which already switched new writes to a fresh incremental file,
+/// and still needs to write its base.
+pub struct Rewrite {
+    base: AofFile,
+    first_incr: u64,
+}
+
+/// The append only file, logging every change to the dataset as the
+/// command that would redo it.
+pub struct Aof {
+    log: Arc<Mutex<Option<Log>>>,
+}
+
+impl Aof {
+    pub fn disabled() -> Self {
+        Self {
+            log: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Starts an AOF from scratch, with `databases` as its base.
+    pub fn create(
+        dir: &Path,
+        filename: &str,
+        fsync: AppendFsync,
+        databases: &[Vec<Entry>],
+        options: SaveOptions,
+    ) -> Result<Self> {
+        let base = Manifest::default().next_base(filename);
+        write_base(dir, &base, databases, options)?;
+        let manifest = Manifest {
+            base: Some(base),
+            incrs: vec![],
+        };
+        Self::open(dir, filename, fsync, manifest)
+    }
+
+    /// Keeps appending to the last incremental file of `manifest`.
+    pub fn open(
+        dir: &Path,
+        filename: &str,
+        fsync: AppendFsync,
+        manifest: Manifest,
+    ) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut manifest = manifest;
+        if manifest.incrs.is_empty() {
+            manifest.incrs.push(manifest.next_incr(filename));
+            manifest.write(dir, filename)?;
+        }
+        let incr = manifest.incrs.last().context("incr")?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(&incr.name))?;
+        let mut log = Log {
+            dir: dir.to_path_buf(),
+            filename: filename.to_string(),
+            manifest,
+            file,
+            fsync,
+            last_fsync: Instant::now(),
+            pending: false,
+            fsync_in_progress: Arc::new(AtomicBool::new(false)),
+            current_size: 0,
+            base_size: 0,
+            rewrite_in_progress: false,
+            last_rewrite_ok: true,
+            selected_db: None,
+        };
+        log.current_size = log.size();
+        log.base_size = log.current_size;
+        Ok(Self {
+            log: Arc::new(Mutex::new(Some(log))),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.log.lock().expect("unique access to aof").is_some()
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        let log = self.log.lock().expect("unique access to aof");
+        log.as_ref().map(|l| l.rewrite_in_progress).unwrap_or(false)
+    }
+
+    pub fn last_rewrite_ok(&self) -> bool {
+        let log = self.log.lock().expect("unique access to aof");
+        log.as_ref().map(|l| l.last_rewrite_ok).unwrap_or(true)
+    }
+
+    pub fn set_fsync(&self, fsync: AppendFsync) {
+        let mut log = self.log.lock().expect("unique access to aof");
+        if let Some(log) = log.as_mut() {
+            log.fsync = fsync;
+        }
+    }
+
+    /// Appends a command run against database `db`, preceded by a `SELECT`
+    /// when the log was on another one. With `appendfsync always` it is on
+    /// disk before this returns, so before the client gets its reply.
+    pub fn feed(&self, db: usize, args: &[&[u8]]) -> Result<()> {
+        let mut log = self.log.lock().expect("unique access to aof");
+        let log = match log.as_mut() {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        if log.selected_db != Some(db) {
+            log.write(&[b"SELECT", db.to_string().as_bytes()])?;
+            log.selected_db = Some(db);
+        }
+        log.write(args)?;
+        match log.fsync {
+            AppendFsync::Always => {
+                log.file.sync_data()?;
+                log.last_fsync = Instant::now();
+            }
+            _ => log.pending = true,
+        }
+        Ok(())
+    }
+
+    /// Flushes the file once per second under `appendfsync everysec`,
+    /// on a background thread so clients are not held by the disk.
+    pub fn cron(&self) {
+        let mut log = self.log.lock().expect("unique access to aof");
+        let log = match log.as_mut() {
+            Some(log) => log,
+            None => return,
+        };
+        if log.fsync != AppendFsync::EverySec
+            || !log.pending
+            || log.last_fsync.elapsed() < FSYNC_PERIOD
+            || log.fsync_in_progress.swap(true, Ordering::AcqRel)
+        {
+            return;
+        }
+        let file = match log.file.try_clone() {
+            Ok(file) => file,
+            Err(e) => {
+                log.fsync_in_progress.store(false, Ordering::Release);
+                eprintln!("Unable to fsync the append only file: {e}");
+                return;
+            }
+        };
+        log.pending = false;
+        log.last_fsync = Instant::now();
+        let in_progress = log.fsync_in_progress.clone();
+        thread::spawn(move || {
+            if let Err(e) = file.sync_data() {
+                eprintln!("Unable to fsync the append only file: {e}");
+            }
+            in_progress.store(false, Ordering::Release);
+        });
+    }
+
+    /// Whether the AOF grew past `percentage` of its size after the last
+    /// rewrite, and is at least `min_size` bytes long.
+    pub fn is_rewrite_due(&self, percentage: u64, min_size: usize) -> bool {
+        let log = self.log.lock().expect("unique access to aof");
+        let log = match log.as_ref() {
+            Some(log) => log,
+            None => return false,
+        };
+        if percentage == 0 || log.rewrite_in_progress || log.current_size < min_size as u64 {
+            return false;
+        }
+        let base = log.base_size.max(1);
+        let growth = log.current_size.saturating_sub(base) * 100 / base;
+        growth >= percentage
+    }
+
+    /// Opens a new incremental file for the writes that follow, so the
+    /// rewrite only needs a snapshot of the dataset as of now. Callers must
+    /// keep the dataset still until they took that snapshot. Returns `None`
+    /// when a rewrite is already running.
+    pub fn start_rewrite(&self) -> Result<Option<Rewrite>> {
+        let mut log = self.log.lock().expect("unique access to aof");
+        let log = match log.as_mut() {
+            Some(log) => log,
+            None => anyhow::bail!("the append only file is disabled"),
+        };
+        if log.rewrite_in_progress {
+            return Ok(None);
+        }
+        log.file.sync_data()?;
+        let incr = log.manifest.next_incr(&log.filename);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log.dir.join(&incr.name))?;
+        let mut manifest = log.manifest.clone();
+        manifest.incrs.push(incr.clone());
+        manifest.write(&log.dir, &log.filename)?;
+
+        log.manifest = manifest;
+        log.file = file;
+        log.selected_db = None;
+        log.rewrite_in_progress = true;
+        Ok(Some(Rewrite {
+            base: log.manifest.next_base(&log.filename),
+            first_incr: incr.seq,
+        }))
+    }
+
+    /// Writes the base of `rewrite` from a background thread, swapping it
+    /// for the files it replaces once it is on disk.
+    pub fn rewrite(&self, rewrite: Rewrite, databases: Vec<Vec<Entry>>, options: SaveOptions) {
+        let log = self.log.clone();
+        thread::spawn(move || {
+            let dir = match log.lock().expect("unique access to aof").as_ref() {
+                Some(log) => log.dir.clone(),
+                None => return,
+            };
+            let result = write_base(&dir, &rewrite.base, &databases, options);
+
+            let mut log = log.lock().expect("unique access to aof");
+            if let Some(log) = log.as_mut() {
+                let result = result.and_then(|_| log.finish_rewrite(&rewrite));
+                if let Err(e) = &result {
+                    eprintln!("Background AOF rewrite error: {e}");
+                }
+                log.rewrite_in_progress = false;
+                log.last_rewrite_ok = result.is_ok();
+            }
+        });
+    }
+}
+
+fn write_base(
+    dir: &Path,
+    base: &AofFile,
+    databases: &[Vec<Entry>],
+    options: SaveOptions,
+) -> Result<()> {
+    let path = dir.join(&base.name);
+    db::save_at(&path, &persistence::aux(true), databases, options)
+}
+
+/// Reads back the commands logged at `path`. A command cut short by a crash
+/// is dropped and the file truncated before it, unless `truncated` is off.
+pub fn load(path: &Path, truncated: bool) -> Result<Vec<Command>> {
+    let mut buf = match fs::read(path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut commands = Vec::new();
+    let mut offset = 0;
+    while offset < buf.len() {
+        let decoded = decode::frame(&buf[offset..])
+            .with_context(|| format!("Bad file format reading the append only file at {offset}"))?;
+        match decoded {
+            Some((frame, read)) => {
+                let command = scanner::scan_frame(frame).with_context(|| {
+                    format!("Invalid command in the append only file at {offset}")
+                })?;
+                commands.push(command);
+                offset += read;
+            }
+            None if truncated => {
+                eprintln!(
+                    "!!! Warning: short read while loading the AOF file {}!!!",
+                    path.display()
+                );
+                eprintln!("AOF loaded anyway because aof-load-truncated is enabled");
+                let file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(offset as u64)?;
+                buf.truncate(offset);
+            }
+            None => anyhow::bail!(
+                "Unexpected end of file reading the append only file at {offset}, \
+                 enable aof-load-truncated to load it anyway"
+            ),
+        }
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::SetOptions;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("redis-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_load_truncated() {
+        let dir = temp_dir("aof-load");
+        let aof = Aof::open(
+            &dir,
+            "appendonly.aof",
+            AppendFsync::Always,
+            Manifest::default(),
+        );
+        let aof = aof.unwrap();
+        aof.feed(0, &[b"SET", b"a", b"1"]).unwrap();
+        aof.feed(0, &[b"SET", b"b", b"2"]).unwrap();
+        drop(aof);
+        let path = dir.join("appendonly.aof.1.incr.aof");
+        let complete = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nc").unwrap();
+
+        assert!(load(&path, false).is_err());
+        let commands = load(&path, true).unwrap();
+        assert_eq!(
+            commands,
+            [
+                Command::Select(0),
+                Command::Set("a".into(), "1".into(), SetOptions::default()),
+                Command::Set("b".into(), "2".into(), SetOptions::default()),
+            ]
+        );
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let dir = temp_dir("aof-rewrite");
+        let aof = Aof::create(
+            &dir,
+            "appendonly.aof",
+            AppendFsync::No,
+            &[],
+            SaveOptions::default(),
+        )
+        .unwrap();
+        aof.feed(0, &[b"SET", b"a", b"1"]).unwrap();
+        assert!(aof.is_rewrite_due(10, 0));
+        assert!(!aof.is_rewrite_due(10, 1024 * 1024));
+
+        let rewrite = aof.start_rewrite().unwrap().unwrap();
+        assert!(aof.start_rewrite().unwrap().is_none());
+        // Written while the base is being rewritten, so kept after it.
+        aof.feed(1, &[b"SET", b"b", b"2"]).unwrap();
+        let entries = vec![Entry::new("a".into(), db::Value::String("1".into()), None)];
+        aof.rewrite(rewrite, vec![entries], SaveOptions::default());
+        while aof.rewrite_in_progress() {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(aof.last_rewrite_ok());
+
+        let manifest = Manifest::read(&dir, "appendonly.aof").unwrap().unwrap();
+        let names: Vec<_> = manifest.files().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["appendonly.aof.2.base.rdb", "appendonly.aof.2.incr.aof"]
+        );
+        assert!(!dir.join("appendonly.aof.1.base.rdb").exists());
+        assert!(!dir.join("appendonly.aof.1.incr.aof").exists());
+        let commands = load(&dir.join("appendonly.aof.2.incr.aof"), false).unwrap();
+        // The new file selects its database again.
+        let set = Command::Set("b".into(), "2".into(), SetOptions::default());
+        assert_eq!(commands, [Command::Select(1), set]);
+    }
+}
@@ -1,11 +1,55 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use bytes::Bytes;
+use std::collections::{hash_map::RandomState, HashMap};
+use std::hash::{BuildHasher, Hash, Hasher};
 use std::time;
 use thiserror::Error;
 
+/// What each item takes besides its key and value: the entry of the
+/// table, its deadline and access stats.
+const ITEM_OVERHEAD: usize = 64;
+/// How slowly the access frequency counter grows, as `lfu-log-factor`.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Minutes it takes the counter to decay by one, as `lfu-decay-time`.
+const LFU_DECAY_MINUTES: u64 = 1;
+/// Counter of new items, so they are not evicted before they get a chance.
+const LFU_INIT_VAL: u8 = 5;
+
+/// Milliseconds since the unix epoch, the clock deadlines are kept in so
+/// they mean the same after a restart or on another machine.
+pub fn now_ms() -> u64 {
+    let now = time::SystemTime::now().duration_since(time::UNIX_EPOCH);
+    now.unwrap_or_default().as_millis() as u64
+}
+
+/// Bytes a key or value takes, roughly, for `maxmemory` to compare with.
+pub trait MemoryUsage {
+    fn memory_usage(&self) -> usize;
+}
+
+impl MemoryUsage for Bytes {
+    fn memory_usage(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Sets of keys kept in vectors, so they can be sampled at random.
+#[derive(Clone, Copy)]
+enum Pool {
+    All = 0,
+    Volatile = 1,
+}
+
 struct Item<Value> {
     value: Value,
-    expires_at: Option<time::Instant>,
+    /// Unix time in milliseconds.
+    expires_at: Option<u64>,
+    /// Position of the key in each pool it belongs to.
+    slots: [Option<usize>; 2],
+    /// Unix time in milliseconds of the last access.
+    accessed_at: u64,
+    /// Logarithmic access counter, as of `decayed_at`, in minutes.
+    freq: u8,
+    decayed_at: u64,
 }
 
 #[derive(Error, PartialEq, Debug)]
@@ -18,15 +62,38 @@ pub enum CacheError {
 
 pub struct Cache<K: Sized, V> {
     items: HashMap<K, Item<V>>,
+    /// Every key, and those with a deadline.
+    pools: [Vec<K>; 2],
+    /// State of the generator picking samples.
+    seed: u64,
+    /// Bytes taken by the items.
+    used: usize,
+    /// Keys deleted because they expired, over the life of the cache.
+    expired: u64,
 }
 
 impl<K, V> Cache<K, V>
 where
-    K: Hash + Eq + Sized,
+    K: Hash + Eq + Sized + Clone + MemoryUsage,
+    V: MemoryUsage,
 {
     pub fn new() -> Self {
         Self {
             items: HashMap::new(),
+            pools: [Vec::new(), Vec::new()],
+            seed: RandomState::new().build_hasher().finish() | 1,
+            used: 0,
+            expired: 0,
+        }
+    }
+
+    /// Moves every item into a new cache, keeping the counters here.
+    pub fn take(&mut self) -> Self {
+        Self {
+            items: std::mem::take(&mut self.items),
+            pools: std::mem::take(&mut self.pools),
+            used: std::mem::take(&mut self.used),
+            ..Self::new()
         }
     }
 
@@ -34,29 +101,232 @@ where
         self.items.get(k)
     }
 
+    /// The value of a live item, counting it as an access.
     pub fn value(&mut self, k: &K) -> Result<&V, CacheError> {
+        if self.del_if_expired(k) {
+            return Err(CacheError::Expired);
+        }
+        let chance = self.random() as f64 / u64::MAX as f64;
+        let item = self.items.get_mut(k).ok_or(CacheError::Missing)?;
+        item.touch(chance);
+        Ok(&item.value)
+    }
+
+    /// The value of a live item, leaving its access stats alone.
+    pub fn peek(&mut self, k: &K) -> Result<&V, CacheError> {
         if self.del_if_expired(k) {
             return Err(CacheError::Expired);
         }
         self.fetch(k).map(|i| &i.value).ok_or(CacheError::Missing)
     }
 
-    pub fn put(&mut self, k: K, v: V, t: Option<time::Instant>) {
+    /// Deadline of a live item, if it has one.
+    pub fn deadline(&self, k: &K) -> Option<u64> {
+        let item = self.fetch(k).filter(|i| !i.is_expired());
+        item.and_then(|i| i.expires_at)
+    }
+
+    /// Milliseconds since a live item was last accessed.
+    pub fn idle(&self, k: &K) -> Option<u64> {
+        let item = self.fetch(k).filter(|i| !i.is_expired());
+        item.map(|i| now_ms().saturating_sub(i.accessed_at))
+    }
+
+    /// Access frequency counter of a live item, once decayed.
+    pub fn freq(&self, k: &K) -> Option<u8> {
+        let item = self.fetch(k).filter(|i| !i.is_expired());
+        item.map(|i| i.decayed_freq(now_ms() / 60_000))
+    }
+
+    /// Restores when an item was last accessed, `idle` milliseconds ago.
+    pub fn set_idle(&mut self, k: &K, idle: u64) {
+        if let Some(item) = self.items.get_mut(k) {
+            item.accessed_at = now_ms().saturating_sub(idle);
+        }
+    }
+
+    /// Restores the access frequency counter of an item.
+    pub fn set_freq(&mut self, k: &K, freq: u8) {
+        if let Some(item) = self.items.get_mut(k) {
+            item.freq = freq;
+            item.decayed_at = now_ms() / 60_000;
+        }
+    }
+
+    /// Stores an item. Replacing a live one keeps its access stats, so a key
+    /// written often is not taken for a new one by the eviction.
+    pub fn put(&mut self, k: K, v: V, t: Option<u64>) {
+        let old = self.del(&k).filter(|i| !i.is_expired());
+        let all = self.link(Pool::All, k.clone());
+        let volatile = t.map(|_| self.link(Pool::Volatile, k.clone()));
+        self.used += k.memory_usage() + v.memory_usage() + ITEM_OVERHEAD;
+        let now = now_ms();
+        let (accessed_at, freq, decayed_at) = match old {
+            Some(old) => (old.accessed_at, old.freq, old.decayed_at),
+            None => (now, LFU_INIT_VAL, now / 60_000),
+        };
         let item = Item {
             value: v,
             expires_at: t,
+            slots: [Some(all), volatile],
+            accessed_at,
+            freq,
+            decayed_at,
         };
         self.items.insert(k, item);
     }
 
-    fn del(&mut self, k: &K) {
-        self.items.remove(k);
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.items
+            .iter()
+            .filter(|(_, i)| !i.is_expired())
+            .map(|(k, _)| k)
+    }
+
+    /// Every live item, along with its deadline.
+    pub fn entries(&self) -> impl Iterator<Item = (&K, &V, Option<u64>)> {
+        self.items
+            .iter()
+            .filter(|(_, i)| !i.is_expired())
+            .map(|(k, i)| (k, &i.value, i.expires_at))
+    }
+
+    /// Changes the deadline of a live item. Returns `false` when missing.
+    pub fn set_deadline(&mut self, k: &K, t: Option<u64>) -> bool {
+        if self.del_if_expired(k) {
+            return false;
+        }
+        let slot = match self.items.get(k) {
+            Some(item) => item.slots[Pool::Volatile as usize],
+            None => return false,
+        };
+        let slot = match (slot, t) {
+            (None, Some(_)) => Some(self.link(Pool::Volatile, k.clone())),
+            (Some(slot), None) => {
+                self.unlink(Pool::Volatile, slot);
+                None
+            }
+            (slot, _) => slot,
+        };
+        if let Some(item) = self.items.get_mut(k) {
+            item.expires_at = t;
+            item.slots[Pool::Volatile as usize] = slot;
+        }
+        true
+    }
+
+    /// Takes a live item out, along with its deadline.
+    pub fn remove(&mut self, k: &K) -> Option<(V, Option<u64>)> {
+        if self.del_if_expired(k) {
+            return None;
+        }
+        self.del(k).map(|i| (i.value, i.expires_at))
+    }
+
+    /// Number of items, expired ones not deleted yet included.
+    pub fn len(&self) -> usize {
+        self.pools[Pool::All as usize].len()
+    }
+
+    /// Number of items with a deadline, expired ones not deleted yet included.
+    pub fn expires(&self) -> usize {
+        self.pools[Pool::Volatile as usize].len()
+    }
+
+    /// Bytes taken by the items, expired ones not deleted yet included.
+    pub fn used_memory(&self) -> usize {
+        self.used
+    }
+
+    /// Keys deleted because they expired, whether a client asked for them
+    /// or a sample found them.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Looks at up to `count` random keys with a deadline, deleting the
+    /// expired ones. Returns how many were looked at, and deleted.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let mut sampled = 0;
+        let mut expired = 0;
+        while sampled < count {
+            let k = match self.pick(Pool::Volatile) {
+                Some(k) => k,
+                None => break,
+            };
+            sampled += 1;
+            expired += self.del_if_expired(&k) as usize;
+        }
+        (sampled, expired)
+    }
+
+    /// Up to `count` random live keys, only out of those with a deadline
+    /// when `volatile`. The same key may come more than once, and expired
+    /// ones found on the way are deleted.
+    pub fn sample(&mut self, count: usize, volatile: bool) -> Vec<K> {
+        let pool = if volatile { Pool::Volatile } else { Pool::All };
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.pick(pool) {
+                Some(k) if !self.del_if_expired(&k) => keys.push(k),
+                Some(_) => continue,
+                None => break,
+            }
+        }
+        keys
+    }
+
+    fn pick(&mut self, pool: Pool) -> Option<K> {
+        let len = self.pools[pool as usize].len() as u64;
+        if len == 0 {
+            return None;
+        }
+        let slot = (self.random() % len) as usize;
+        Some(self.pools[pool as usize][slot].clone())
+    }
+
+    /// Next number of a xorshift generator, which is plenty for sampling.
+    fn random(&mut self) -> u64 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+
+    /// Adds a key to a pool, returning its slot.
+    fn link(&mut self, pool: Pool, k: K) -> usize {
+        let keys = &mut self.pools[pool as usize];
+        keys.push(k);
+        keys.len() - 1
+    }
+
+    /// Drops a key from a pool, moving the last one into its slot.
+    fn unlink(&mut self, pool: Pool, slot: usize) {
+        let keys = &mut self.pools[pool as usize];
+        keys.swap_remove(slot);
+        if let Some(moved) = keys.get(slot) {
+            if let Some(item) = self.items.get_mut(moved) {
+                item.slots[pool as usize] = Some(slot);
+            }
+        }
+    }
+
+    fn del(&mut self, k: &K) -> Option<Item<V>> {
+        let item = self.items.remove(k)?;
+        for pool in [Pool::All, Pool::Volatile] {
+            if let Some(slot) = item.slots[pool as usize] {
+                self.unlink(pool, slot);
+            }
+        }
+        self.used -= k.memory_usage() + item.value.memory_usage() + ITEM_OVERHEAD;
+        Some(item)
     }
 
     fn del_if_expired(&mut self, k: &K) -> bool {
         let is_expired = self.items.get(k).map(|i| i.is_expired()).unwrap_or(false);
         if is_expired {
             self.del(k);
+            self.expired += 1;
         }
         is_expired
     }
@@ -64,9 +334,28 @@ where
 
 impl<T> Item<T> {
     fn is_expired(&self) -> bool {
-        self.expires_at
-            .map(|t| t <= time::Instant::now())
-            .unwrap_or(false)
+        self.expires_at.map(|t| t <= now_ms()).unwrap_or(false)
+    }
+
+    /// The counter, less one for every period nobody accessed the item.
+    fn decayed_freq(&self, minutes: u64) -> u8 {
+        let periods = minutes.saturating_sub(self.decayed_at) / LFU_DECAY_MINUTES;
+        self.freq.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+
+    /// Records an access. The counter grows with a chance that shrinks
+    /// as it gets higher, so 255 stands for about a million accesses.
+    fn touch(&mut self, chance: f64) {
+        let now = now_ms();
+        let minutes = now / 60_000;
+        let mut freq = self.decayed_freq(minutes);
+        let base = freq.saturating_sub(LFU_INIT_VAL) as f64;
+        if freq < u8::MAX && chance < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+            freq += 1;
+        }
+        self.freq = freq;
+        self.decayed_at = minutes;
+        self.accessed_at = now;
     }
 }
 
@@ -75,6 +364,18 @@ mod tests {
     use super::*;
     use std::thread;
 
+    impl MemoryUsage for i32 {
+        fn memory_usage(&self) -> usize {
+            4
+        }
+    }
+
+    impl MemoryUsage for &str {
+        fn memory_usage(&self) -> usize {
+            self.len()
+        }
+    }
+
     #[test]
     fn test_value() {
         let mut cache = Cache::new();
@@ -86,8 +387,119 @@ mod tests {
     fn test_value_miss() {
         let mut cache = Cache::new();
         let dur = time::Duration::from_millis(100);
-        cache.put("key", 42, Some(time::Instant::now() + dur));
+        cache.put("key", 42, Some(now_ms() + 100));
         thread::sleep(dur);
         assert_eq!(cache.value(&"key"), Err(CacheError::Expired));
     }
+
+    #[test]
+    fn test_keys() {
+        let mut cache = Cache::new();
+        cache.put("live", 1, None);
+        cache.put("gone", 2, Some(now_ms()));
+        assert_eq!(cache.keys().collect::<Vec<_>>(), [&"live"]);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut cache = Cache::new();
+        cache.put("live", 1, None);
+        cache.put("soon", 2, Some(now_ms() + 60_000));
+        cache.put("gone", 3, Some(now_ms()));
+        assert_eq!((cache.len(), cache.expires()), (3, 2));
+        assert_eq!(cache.remove(&"live"), Some((1, None)));
+        assert_eq!(cache.remove(&"gone"), None);
+        assert_eq!((cache.len(), cache.expires()), (1, 1));
+
+        assert!(cache.set_deadline(&"soon", None));
+        assert!(!cache.set_deadline(&"live", None));
+        assert_eq!(cache.expires(), 0);
+    }
+
+    #[test]
+    fn test_expire_sample() {
+        let mut cache = Cache::new();
+        for i in 0..100 {
+            let deadline = if i % 2 == 0 {
+                now_ms()
+            } else {
+                now_ms() + 60_000
+            };
+            cache.put(i, i, Some(deadline));
+        }
+        cache.put(100, 100, None);
+        cache.set_deadline(&1, None);
+        assert!(cache.remove(&3).is_some());
+        assert_eq!(cache.pools[Pool::Volatile as usize].len(), 98);
+
+        let (sampled, expired) = cache.expire_sample(20);
+        assert_eq!(sampled, 20);
+        assert!(expired <= 20);
+        for _ in 0..1000 {
+            cache.expire_sample(20);
+        }
+        assert_eq!(cache.expired(), 50);
+        for pool in [Pool::All, Pool::Volatile] {
+            for (slot, k) in cache.pools[pool as usize].iter().enumerate() {
+                assert_eq!(cache.items[k].slots[pool as usize], Some(slot));
+            }
+        }
+        assert_eq!(cache.pools[Pool::Volatile as usize].len(), 48);
+        assert_eq!(cache.pools[Pool::All as usize].len(), 50);
+
+        let taken = cache.take();
+        assert_eq!((taken.len(), cache.len(), cache.expired()), (50, 0, 50));
+        assert_eq!(taken.used_memory(), 50 * (8 + ITEM_OVERHEAD));
+        assert_eq!(cache.used_memory(), 0);
+    }
+
+    #[test]
+    fn test_sample() {
+        let mut cache = Cache::new();
+        assert!(cache.sample(5, false).is_empty());
+        cache.put("live", 1, None);
+        cache.put("soon", 2, Some(now_ms() + 60_000));
+        assert_eq!(cache.used_memory(), 16 + 2 * ITEM_OVERHEAD);
+        assert_eq!(cache.sample(5, true), ["soon"; 5]);
+        assert_eq!(cache.sample(5, false).len(), 5);
+        cache.put("soon", 3, None);
+        assert!(cache.sample(5, true).is_empty());
+        assert_eq!(cache.used_memory(), 16 + 2 * ITEM_OVERHEAD);
+    }
+
+    #[test]
+    fn test_access() {
+        let mut cache = Cache::new();
+        cache.put("key", 1, None);
+        assert_eq!(cache.freq(&"key"), Some(LFU_INIT_VAL));
+        assert!(cache.idle(&"key").unwrap() < 1000);
+        for _ in 0..100 {
+            cache.peek(&"key").unwrap();
+        }
+        assert_eq!(cache.freq(&"key"), Some(LFU_INIT_VAL));
+        for _ in 0..100 {
+            cache.value(&"key").unwrap();
+        }
+        let freq = cache.freq(&"key").unwrap();
+        assert!(freq > LFU_INIT_VAL && freq < 20, "{freq}");
+        assert_eq!(cache.freq(&"missing"), None);
+
+        // Every period without accesses takes one off the counter.
+        let item = cache.items.get_mut(&"key").unwrap();
+        item.decayed_at -= 3 * LFU_DECAY_MINUTES;
+        assert_eq!(cache.freq(&"key"), Some(freq - 3));
+
+        cache.set_idle(&"key", 60_000);
+        cache.set_freq(&"key", 200);
+        assert!(cache.idle(&"key").unwrap() >= 60_000);
+        assert_eq!(cache.freq(&"key"), Some(200));
+
+        // Overwriting a key keeps its stats, unless it had expired.
+        cache.put("key", 2, None);
+        assert!(cache.idle(&"key").unwrap() >= 60_000);
+        assert_eq!(cache.freq(&"key"), Some(200));
+        cache.put("key", 3, Some(now_ms()));
+        cache.put("key", 4, None);
+        assert_eq!(cache.freq(&"key"), Some(LFU_INIT_VAL));
+    }
 }
@@ -0,0 +1,110 @@
+/// Matches `s` against a glob-style `pattern`, as KEYS does: `*` and `?`
+/// wildcards, `[a-z]` / `[^abc]` classes and `\` escapes.
+pub fn matches(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // Where the last `*` left off: the pattern after it, and the first byte
+    // it has not swallowed yet. A mismatch retries from there, one byte on,
+    // so earlier stars are never revisited, however many there are.
+    let mut star = None;
+    while i < s.len() {
+        if pattern.get(p) == Some(&b'*') {
+            while pattern.get(p) == Some(&b'*') {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            star = Some((p, i));
+            continue;
+        }
+        match token(&pattern[p..], s[i]) {
+            Some(len) => {
+                p += len;
+                i += 1;
+            }
+            None => match star {
+                Some((after, swallowed)) => {
+                    star = Some((after, swallowed + 1));
+                    p = after;
+                    i = swallowed + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Checks `c` against the first element of `pattern`, other than `*`.
+/// Returns how many bytes of the pattern it took when it matched.
+fn token(pattern: &[u8], c: u8) -> Option<usize> {
+    match pattern {
+        [] => None,
+        [b'?', ..] => Some(1),
+        [b'[', rest @ ..] => match class(rest, c) {
+            Some((true, after)) => Some(pattern.len() - after.len()),
+            _ => None,
+        },
+        [b'\\', escaped, ..] => (*escaped == c).then_some(2),
+        [p, ..] => (*p == c).then_some(1),
+    }
+}
+
+/// Checks `c` against the class at the start of `pattern`, right after its
+/// opening `[`. Returns whether it matched and the pattern following `]`,
+/// or `None` for an unterminated class.
+fn class(pattern: &[u8], c: u8) -> Option<(bool, &[u8])> {
+    let (negate, mut pattern) = match pattern.split_first() {
+        Some((b'^', rest)) => (true, rest),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    loop {
+        match pattern {
+            [] => return None,
+            [b']', rest @ ..] => return Some((matched != negate, rest)),
+            [b'\\', e, rest @ ..] => {
+                matched |= *e == c;
+                pattern = rest;
+            }
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                let (lo, hi) = if lo <= hi { (*lo, *hi) } else { (*hi, *lo) };
+                matched |= (lo..=hi).contains(&c);
+                pattern = rest;
+            }
+            [e, rest @ ..] => {
+                matched |= *e == c;
+                pattern = rest;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches(b"*", b"anything"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(matches(b"h*llo", b"heeeello"));
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"user:\\*", b"user:*"));
+        assert!(!matches(b"user:\\*", b"user:1"));
+        assert!(!matches(b"h[ae", b"ha"));
+        assert!(!matches(b"foo", b"foobar"));
+        assert!(matches(b"a**b*", b"axxb"));
+        assert!(matches(b"*[0-9]", b"key:42"));
+        assert!(!matches(b"*\\?", b"why?!"));
+    }
+
+    #[test]
+    fn test_matches_many_stars() {
+        let key = vec![b'a'; 10_000];
+        assert!(!matches(b"*a*a*a*a*a*a*a*a*a*a*a*a*b", &key));
+        assert!(matches(b"*a*a*a*a*a*a*a*a*a*a*a*a*", &key));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    command::{
+        table::{self, Flag, Spec},
+        CommandError,
+    },
+    response::{Builder, Response},
+};
+use bytes::Bytes;
+
+fn bulk(s: &str) -> Response {
+    Response::bulk(s.as_bytes())
+}
+
+/// Describes how a command finds its keys, in the shape Redis 7 reports.
+fn key_specs(spec: &Spec) -> Response {
+    let keys = spec.keys;
+    if keys.first == 0 {
+        return Response::list(vec![]);
+    }
+    let access = if spec.has_flag(Flag::Write) {
+        ["RW", "UPDATE"]
+    } else {
+        ["RO", "ACCESS"]
+    };
+    let last = if keys.last < 0 {
+        keys.last
+    } else {
+        keys.last - keys.first
+    };
+    Response::list(vec![Response::map(vec![
+        (
+            bulk("flags"),
+            Response::set(access.iter().map(|f| Response::text(f)).collect()),
+        ),
+        (
+            bulk("begin_search"),
+            Response::map(vec![
+                (bulk("type"), bulk("index")),
+                (
+                    bulk("spec"),
+                    Response::map(vec![(bulk("index"), Response::integer(keys.first))]),
+                ),
+            ]),
+        ),
+        (
+            bulk("find_keys"),
+            Response::map(vec![
+                (bulk("type"), bulk("range")),
+                (
+                    bulk("spec"),
+                    Response::map(vec![
+                        (bulk("lastkey"), Response::integer(last)),
+                        (bulk("keystep"), Response::integer(keys.step)),
+                        (bulk("limit"), Response::integer(0)),
+                    ]),
+                ),
+            ]),
+        ),
+    ])])
+}
+
+/// A `COMMAND INFO` entry.
+pub fn info(spec: &Spec) -> Response {
+    let flags = spec
+        .flags
+        .iter()
+        .map(|f| Response::text(&f.to_string()))
+        .collect();
+    let categories = spec.categories.iter().map(|c| Response::text(c)).collect();
+    Response::list(vec![
+        bulk(spec.name),
+        Response::integer(spec.arity),
+        Response::set(flags),
+        Response::integer(spec.keys.first),
+        Response::integer(spec.keys.last),
+        Response::integer(spec.keys.step),
+        Response::set(categories),
+        Response::list(vec![]),
+        key_specs(spec),
+        Response::list(spec.subcommands.iter().map(info).collect()),
+    ])
+}
+
+/// A `COMMAND DOCS` entry, without the command name it is keyed by.
+pub fn docs(spec: &Spec) -> Response {
+    let mut fields = vec![
+        (bulk("summary"), bulk(spec.summary)),
+        (bulk("since"), bulk(spec.since)),
+        (bulk("group"), bulk(spec.group)),
+    ];
+    if spec.is_container() {
+        let subcommands = spec
+            .subcommands
+            .iter()
+            .map(|s| (bulk(s.name), docs(s)))
+            .collect();
+        fields.push((bulk("subcommands"), Response::map(subcommands)));
+    }
+    Response::map(fields)
+}
+
+/// Specs for the given names, or every command when none are given.
+pub fn specs(names: &[Bytes]) -> Vec<Option<&'static Spec>> {
+    if names.is_empty() {
+        return table::all().iter().map(Some).collect();
+    }
+    names
+        .iter()
+        .map(|n| table::lookup(&String::from_utf8_lossy(n)))
+        .collect()
+}
+
+/// Replies to `COMMAND GETKEYS` with the keys of the given command line.
+pub fn getkeys(args: &[Bytes]) -> Response {
+    let spec = match table::resolve(args) {
+        Ok(spec) => spec,
+        Err(CommandError::WrongArity(_)) => {
+            let msg = "Invalid number of arguments specified for command";
+            return CommandError::Other(msg.into()).into();
+        }
+        Err(_) => return CommandError::Other("Invalid command specified".into()).into(),
+    };
+    let keys: Vec<_> = spec
+        .key_positions(args.len())
+        .into_iter()
+        .map(|i| &args[i])
+        .collect();
+    if keys.is_empty() {
+        return CommandError::Other("The command has no key arguments".into()).into();
+    }
+    Response::array(&keys)
+}
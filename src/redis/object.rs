@@ -0,0 +1,200 @@
+//! Values of the keyspace, one variant per data type.
+
+use super::cache::MemoryUsage;
+use crate::command::CommandError;
+use crate::db::{stream::Stream, Value};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Prefix of the strings holding a HyperLogLog.
+const HLL_MAGIC: &[u8] = b"HYLL";
+/// Largest string kept in the same allocation as its object.
+const EMBSTR_MAX_LEN: usize = 44;
+/// Largest collections, and elements, kept in a compact listpack.
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE: usize = 64;
+/// Largest set of integers kept in an intset.
+const INTSET_MAX_ENTRIES: usize = 512;
+/// What each element of a collection takes besides its bytes.
+const ELEMENT_OVERHEAD: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    /// Members ordered by score, then by member.
+    SortedSet(Vec<(Bytes, f64)>),
+    Stream(Stream),
+    /// A string in the HyperLogLog format, which string commands still see.
+    HyperLogLog(Bytes),
+}
+
+fn is_small<'a>(len: usize, mut items: impl Iterator<Item = &'a Bytes>) -> bool {
+    len <= LISTPACK_MAX_ENTRIES && items.all(|i| i.len() <= LISTPACK_MAX_VALUE)
+}
+
+fn is_int(bytes: &[u8]) -> bool {
+    let int = std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok());
+    int.map_or(false, |i| i.to_string().as_bytes() == bytes)
+}
+
+impl Object {
+    /// Name of the type, as `TYPE` reports it.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) | Self::HyperLogLog(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// The encoding Redis would keep this value in, as `OBJECT ENCODING`
+    /// reports it. Small collections use the compact encodings.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::String(s) if is_int(s) => "int",
+            Self::String(s) if s.len() <= EMBSTR_MAX_LEN => "embstr",
+            Self::String(_) | Self::HyperLogLog(_) => "raw",
+            Self::List(items) if is_small(items.len(), items.iter()) => "listpack",
+            Self::List(_) => "quicklist",
+            Self::Hash(fields) => {
+                let items = fields.iter().flat_map(|(k, v)| [k, v]);
+                match is_small(fields.len(), items) {
+                    true => "listpack",
+                    false => "hashtable",
+                }
+            }
+            Self::Set(members)
+                if members.len() <= INTSET_MAX_ENTRIES && members.iter().all(|m| is_int(m)) =>
+            {
+                "intset"
+            }
+            Self::Set(members) if is_small(members.len(), members.iter()) => "listpack",
+            Self::Set(_) => "hashtable",
+            Self::SortedSet(members) if is_small(members.len(), members.iter().map(|m| &m.0)) => {
+                "listpack"
+            }
+            Self::SortedSet(_) => "skiplist",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    /// The value of a string, failing for any other type.
+    pub fn as_string(&self) -> Result<&Bytes, CommandError> {
+        match self {
+            Self::String(s) | Self::HyperLogLog(s) => Ok(s),
+            _ => Err(CommandError::WrongType),
+        }
+    }
+
+    /// The value as snapshots store it.
+    pub fn to_value(&self) -> Value {
+        match self {
+            Self::String(s) | Self::HyperLogLog(s) => Value::String(s.clone()),
+            Self::List(items) => Value::List(items.iter().cloned().collect()),
+            Self::Hash(fields) => {
+                let fields = fields.iter().map(|(k, v)| (k.clone(), v.clone()));
+                Value::Hash(fields.collect())
+            }
+            Self::Set(members) => Value::Set(members.iter().cloned().collect()),
+            Self::SortedSet(members) => Value::SortedSet(members.clone()),
+            Self::Stream(stream) => Value::Stream(stream.clone()),
+        }
+    }
+}
+
+impl MemoryUsage for Object {
+    fn memory_usage(&self) -> usize {
+        let sum = |items: &mut dyn Iterator<Item = usize>| -> usize {
+            items.map(|len| len + ELEMENT_OVERHEAD).sum()
+        };
+        match self {
+            Self::String(s) | Self::HyperLogLog(s) => s.len(),
+            Self::List(items) => sum(&mut items.iter().map(Bytes::len)),
+            Self::Hash(fields) => sum(&mut fields.iter().map(|(k, v)| k.len() + v.len())),
+            Self::Set(members) => sum(&mut members.iter().map(Bytes::len)),
+            Self::SortedSet(members) => sum(&mut members.iter().map(|(m, _)| m.len() + 8)),
+            Self::Stream(stream) => {
+                let entries = stream.entries.iter().map(|entry| {
+                    let fields = entry.fields.iter().map(|(k, v)| k.len() + v.len());
+                    16 + sum(&mut fields.into_iter())
+                });
+                let groups = stream.groups.iter().map(|group| {
+                    let consumers = group.consumers.iter();
+                    let consumers = consumers.map(|c| c.name.len() + c.pending.len() * 16);
+                    group.name.len() + group.pending.len() * 32 + sum(&mut consumers.into_iter())
+                });
+                sum(&mut entries.into_iter()) + sum(&mut groups.into_iter())
+            }
+        }
+    }
+}
+
+impl From<Value> for Object {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::String(s) if s.starts_with(HLL_MAGIC) => Self::HyperLogLog(s),
+            Value::String(s) => Self::String(s),
+            Value::List(items) => Self::List(items.into()),
+            Value::Set(members) => Self::Set(members.into_iter().collect()),
+            Value::SortedSet(mut members) => {
+                members.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+                Self::SortedSet(members)
+            }
+            Value::Hash(fields) => Self::Hash(fields.into_iter().collect()),
+            Value::Stream(stream) => Self::Stream(stream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(items: &[&str]) -> Vec<Bytes> {
+        items.iter().map(|i| Bytes::from(i.to_string())).collect()
+    }
+
+    #[test]
+    fn test_encoding() {
+        let string = |s: &str| Object::String(Bytes::from(s.to_string()));
+        assert_eq!(string("12").encoding(), "int");
+        assert_eq!(string("012").encoding(), "embstr");
+        assert_eq!(string(&"x".repeat(45)).encoding(), "raw");
+
+        let list = Object::from(Value::List(bytes(&["a", "b"])));
+        assert_eq!((list.type_name(), list.encoding()), ("list", "listpack"));
+        let long = Object::from(Value::List(bytes(&[&"x".repeat(65)])));
+        assert_eq!(long.encoding(), "quicklist");
+
+        let set = Object::from(Value::Set(bytes(&["1", "2"])));
+        assert_eq!((set.type_name(), set.encoding()), ("set", "intset"));
+        let set = Object::from(Value::Set(bytes(&["1", "a"])));
+        assert_eq!(set.encoding(), "listpack");
+
+        let hash = Object::from(Value::Hash(vec![("f".into(), "v".into())]));
+        assert_eq!((hash.type_name(), hash.encoding()), ("hash", "listpack"));
+
+        let hll = Object::from(Value::String("HYLL\x01".into()));
+        assert!(matches!(hll, Object::HyperLogLog(_)));
+        assert_eq!((hll.type_name(), hll.encoding()), ("string", "raw"));
+        assert!(hll.as_string().is_ok());
+        assert_eq!(list.as_string(), Err(CommandError::WrongType));
+    }
+
+    #[test]
+    fn test_sorted_set() {
+        let members = vec![("b".into(), 2.0), ("a".into(), 2.0), ("c".into(), -1.0)];
+        let zset = Object::from(Value::SortedSet(members));
+        assert_eq!((zset.type_name(), zset.encoding()), ("zset", "listpack"));
+        let expected = vec![("c".into(), -1.0), ("a".into(), 2.0), ("b".into(), 2.0)];
+        assert_eq!(zset.to_value(), Value::SortedSet(expected));
+    }
+}
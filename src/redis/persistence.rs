@@ -0,0 +1,229 @@
+use crate::config::SavePoint;
+use crate::db::{self, Entry, SaveOptions};
+use anyhow::Result;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long to wait before retrying a failed background save.
+const BGSAVE_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+struct State {
+    last_save: SystemTime,
+    last_bgsave_try: SystemTime,
+    last_bgsave_ok: bool,
+    bgsave_in_progress: bool,
+    save_in_progress: bool,
+    dirty: u64,
+}
+
+/// Bookkeeping of RDB saves, shared with the thread running a `BGSAVE`.
+pub struct Persistence {
+    state: Arc<Mutex<State>>,
+}
+
+impl Persistence {
+    pub fn new() -> Self {
+        let state = State {
+            last_save: SystemTime::now(),
+            last_bgsave_try: UNIX_EPOCH,
+            last_bgsave_ok: true,
+            bgsave_in_progress: false,
+            save_in_progress: false,
+            dirty: 0,
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Unix time, in seconds, of the last successful save.
+    pub fn last_save(&self) -> u64 {
+        let state = self.state.lock().expect("unique access to persistence");
+        let since = state.last_save.duration_since(UNIX_EPOCH);
+        since.map(|d| d.as_secs()).unwrap_or_default()
+    }
+
+    pub fn last_bgsave_ok(&self) -> bool {
+        let state = self.state.lock().expect("unique access to persistence");
+        state.last_bgsave_ok
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        let state = self.state.lock().expect("unique access to persistence");
+        state.bgsave_in_progress
+    }
+
+    /// Changes to the dataset since the last successful save.
+    pub fn dirty(&self) -> u64 {
+        let state = self.state.lock().expect("unique access to persistence");
+        state.dirty
+    }
+
+    /// Forgets the changes counted so far, for those already on disk.
+    pub fn reset_dirty(&self) {
+        let mut state = self.state.lock().expect("unique access to persistence");
+        state.dirty = 0;
+    }
+
+    pub fn touch(&self, changes: u64) {
+        let mut state = self.state.lock().expect("unique access to persistence");
+        state.dirty += changes;
+    }
+
+    /// Whether any save point fired. A failed background save is only
+    /// retried after a delay, so a full disk does not spin the server.
+    pub fn is_due(&self, points: &[SavePoint]) -> bool {
+        let state = self.state.lock().expect("unique access to persistence");
+        let now = SystemTime::now();
+        let elapsed = |since: SystemTime| now.duration_since(since).unwrap_or_default();
+        if state.is_saving()
+            || !(state.last_bgsave_ok || elapsed(state.last_bgsave_try) > BGSAVE_RETRY_DELAY)
+        {
+            return false;
+        }
+        points
+            .iter()
+            .any(|p| state.dirty >= p.changes && elapsed(state.last_save).as_secs() > p.seconds)
+    }
+
+    /// Writes `databases`, taken when the dataset had `dirty` changes.
+    /// Returns `false` without doing anything when another save is running.
+    pub fn save(
+        &self,
+        path: PathBuf,
+        databases: Vec<Vec<Entry>>,
+        dirty: u64,
+        options: SaveOptions,
+    ) -> Result<bool> {
+        {
+            let mut state = self.state.lock().expect("unique access to persistence");
+            if state.is_saving() {
+                return Ok(false);
+            }
+            state.save_in_progress = true;
+        }
+
+        let result = write(&path, &databases, options);
+        let mut state = self.state.lock().expect("unique access to persistence");
+        state.save_in_progress = false;
+        if result.is_ok() {
+            state.saved(dirty);
+        }
+        result.map(|_| true)
+    }
+
+    /// Writes `databases` from a background thread. Returns `false` without
+    /// doing anything when another save is still running.
+    pub fn bgsave(
+        &self,
+        path: PathBuf,
+        databases: Vec<Vec<Entry>>,
+        dirty: u64,
+        options: SaveOptions,
+    ) -> bool {
+        {
+            let mut state = self.state.lock().expect("unique access to persistence");
+            if state.is_saving() {
+                return false;
+            }
+            state.bgsave_in_progress = true;
+            state.last_bgsave_try = SystemTime::now();
+        }
+
+        let state = self.state.clone();
+        thread::spawn(move || {
+            let result = write(&path, &databases, options);
+            if let Err(e) = &result {
+                eprintln!("Background saving error: {e}");
+            }
+            let mut state = state.lock().expect("unique access to persistence");
+            state.bgsave_in_progress = false;
+            state.last_bgsave_ok = result.is_ok();
+            if result.is_ok() {
+                state.saved(dirty);
+            }
+        });
+        true
+    }
+}
+
+impl State {
+    /// Only one snapshot is written at a time, in the foreground or not.
+    fn is_saving(&self) -> bool {
+        self.save_in_progress || self.bgsave_in_progress
+    }
+
+    /// Changes made while the snapshot was being written still count
+    /// towards the next one.
+    fn saved(&mut self, dirty: u64) {
+        self.last_save = SystemTime::now();
+        self.dirty = self.dirty.saturating_sub(dirty);
+    }
+}
+
+/// Aux fields of an RDB file, `aof_base` telling whether it is the
+/// preamble of an AOF.
+pub fn aux(aof_base: bool) -> Vec<(&'static str, String)> {
+    let ctime = SystemTime::now().duration_since(UNIX_EPOCH);
+    let ctime = ctime.map(|d| d.as_secs()).unwrap_or_default();
+    vec![
+        ("redis-ver", super::REDIS_VERSION.to_string()),
+        ("redis-bits", usize::BITS.to_string()),
+        ("ctime", ctime.to_string()),
+        ("aof-base", (aof_base as u8).to_string()),
+    ]
+}
+
+fn write(path: &Path, databases: &[Vec<Entry>], options: SaveOptions) -> Result<()> {
+    db::save_at(path, &aux(false), databases, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_due() {
+        let sut = Persistence::new();
+        let points = [SavePoint {
+            seconds: 5,
+            changes: 2,
+        }];
+        sut.touch(2);
+        assert!(!sut.is_due(&points));
+
+        sut.state.lock().unwrap().last_save -= Duration::from_secs(10);
+        assert!(sut.is_due(&points));
+
+        let mut state = sut.state.lock().unwrap();
+        state.last_bgsave_ok = false;
+        state.last_bgsave_try = SystemTime::now();
+        drop(state);
+        assert!(!sut.is_due(&points));
+    }
+
+    #[test]
+    fn test_exclusive_saves() {
+        let sut = Persistence::new();
+        let path = std::env::temp_dir().join("persistence-exclusive.rdb");
+        let options = SaveOptions::default();
+
+        sut.state.lock().unwrap().bgsave_in_progress = true;
+        let saved = sut.save(path.clone(), vec![], 0, options).unwrap();
+        assert!(!saved);
+
+        let mut state = sut.state.lock().unwrap();
+        state.bgsave_in_progress = false;
+        state.save_in_progress = true;
+        drop(state);
+        assert!(!sut.bgsave(path.clone(), vec![], 0, options));
+
+        sut.state.lock().unwrap().save_in_progress = false;
+        assert!(sut.save(path.clone(), vec![], 0, options).unwrap());
+        let _ = std::fs::remove_file(path);
+    }
+}
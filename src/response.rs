@@ -1,51 +1,99 @@
-use crate::proto::encode;
+use crate::{
+    command::CommandError,
+    proto::{encode, ErrorKind, Frame, Protocol},
+};
 
 #[derive(PartialEq, Debug)]
-pub struct Response(String);
+pub struct Response(Frame);
 
-pub trait Builder {
+pub trait Builder: Sized {
     fn pong() -> Self;
     fn text(inner: &str) -> Self;
-    fn error(msg: &str) -> Self;
+    fn bulk(inner: &[u8]) -> Self;
+    fn error(kind: ErrorKind, msg: &str) -> Self;
     fn ok() -> Self;
     fn null() -> Self;
-    fn array(items: &[&str]) -> Self;
+    fn integer(value: i64) -> Self;
+    fn array<T: AsRef<[u8]>>(items: &[T]) -> Self;
+    fn list(items: Vec<Self>) -> Self;
+    fn map(pairs: Vec<(Self, Self)>) -> Self;
+    fn set(items: Vec<Self>) -> Self;
+    fn verbatim(format: &str, inner: &[u8]) -> Self;
 }
 
-impl From<String> for Response {
-    fn from(value: String) -> Self {
+impl Response {
+    pub fn encode(&self, protocol: Protocol) -> Vec<u8> {
+        encode::frame(&self.0, protocol)
+    }
+
+    fn frames(items: Vec<Self>) -> Vec<Frame> {
+        items.into_iter().map(|r| r.0).collect()
+    }
+
+    fn pairs(pairs: Vec<(Self, Self)>) -> Vec<(Frame, Frame)> {
+        pairs.into_iter().map(|(k, v)| (k.0, v.0)).collect()
+    }
+}
+
+impl From<Frame> for Response {
+    fn from(value: Frame) -> Self {
         Self(value)
     }
 }
 
-impl<'a> Into<&'a [u8]> for &'a Response {
-    fn into(self) -> &'a [u8] {
-        self.0.as_bytes()
+impl From<CommandError> for Response {
+    fn from(value: CommandError) -> Self {
+        Self::error(value.kind(), &value.to_string())
     }
 }
 
 impl Builder for Response {
     fn pong() -> Self {
-        encode::text("PONG").into()
+        Self::text("PONG")
     }
 
     fn text(inner: &str) -> Self {
-        encode::text(inner).into()
+        Frame::Simple(inner.to_string()).into()
     }
 
-    fn error(msg: &str) -> Self {
-        encode::error(msg).into()
+    fn bulk(inner: &[u8]) -> Self {
+        Frame::Bulk(inner.to_vec().into()).into()
+    }
+
+    fn error(kind: ErrorKind, msg: &str) -> Self {
+        Frame::Error(format!("{kind} {msg}")).into()
     }
 
     fn ok() -> Self {
-        encode::text("OK").into()
+        Self::text("OK")
     }
 
     fn null() -> Self {
-        encode::null().into()
+        Frame::Null.into()
+    }
+
+    fn integer(value: i64) -> Self {
+        Frame::Integer(value).into()
+    }
+
+    fn array<T: AsRef<[u8]>>(items: &[T]) -> Self {
+        let items = items.iter().map(|i| Self::bulk(i.as_ref())).collect();
+        Self::list(items)
+    }
+
+    fn list(items: Vec<Self>) -> Self {
+        Frame::Array(Self::frames(items)).into()
+    }
+
+    fn map(pairs: Vec<(Self, Self)>) -> Self {
+        Frame::Map(Self::pairs(pairs)).into()
+    }
+
+    fn set(items: Vec<Self>) -> Self {
+        Frame::Set(Self::frames(items)).into()
     }
 
-    fn array(items: &[&str]) -> Self {
-        encode::array(items).into()
+    fn verbatim(format: &str, inner: &[u8]) -> Self {
+        Frame::Verbatim(format.to_string(), inner.to_vec().into()).into()
     }
 }
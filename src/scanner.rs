@@ -1,54 +1,293 @@
-use crate::{command::ConfigCmd, proto::decode, Command};
-use anyhow::{Context, Result};
-use std::time;
+use crate::{
+    command::{
+        table, CommandCmd, CommandError, Condition, ConfigCmd, ExpireOptions, Expiry, Hello,
+        ObjectCmd, SetOptions, Timeout,
+    },
+    proto::{decode, Frame},
+    Command,
+};
+use bytes::{Buf, Bytes, BytesMut};
+use std::{cmp::Ordering, time};
 
-pub fn scan(buffer: &str) -> Vec<Command> {
+/// Scans every complete request at the front of `buffer`, consuming it.
+/// Trailing bytes from a partial request are kept for the next read.
+pub fn scan(
+    buffer: &mut BytesMut,
+) -> Result<Vec<Result<Command, CommandError>>, decode::ProtoError> {
     let mut cmds = Vec::new();
-    let mut chunks = decode::array(buffer);
-    while let Ok(cmd) = scan_command(&mut chunks) {
-        cmds.push(cmd);
+    while let Some((frame, read)) = decode::request(buffer)? {
+        buffer.advance(read);
+        // Empty requests, like a blank inline line, are skipped silently.
+        if frame == Frame::Array(vec![]) {
+            continue;
+        }
+        cmds.push(scan_frame(frame));
     }
-    cmds
+    Ok(cmds)
 }
 
-fn scan_command<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<Command> {
-    let command = parts.next().context("command")?.to_uppercase();
-    let mut args = parts.filter(|s| !s.starts_with('$'));
+pub fn scan_frame(frame: Frame) -> Result<Command, CommandError> {
+    let items = match frame {
+        Frame::Array(items) => items,
+        _ => return Err(CommandError::Other("Protocol error: expected array".into())),
+    };
+    let parts = items
+        .into_iter()
+        .map(|item| match item {
+            Frame::Bulk(bytes) => Ok(bytes),
+            _ => Err(CommandError::Other(
+                "Protocol error: expected bulk string".into(),
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    scan_command(parts)
+}
 
-    let command = match command.as_str() {
-        "PING" => Command::Ping,
-        "GET" => {
-            let key = args.next().context("get key")?;
-            Command::Get(key.to_string())
-        }
-        "SET" => {
-            let key = args.next().context("set key")?.to_string();
-            let value = args.next().context("set value")?.to_string();
-            let mut timeout = None;
-            if args.next().map(|s| s.to_uppercase()) == Some("PX".to_string()) {
-                let param = args.next().context("PX miliseconds")?.parse::<u64>()?;
-                timeout = Some(time::Duration::from_millis(param));
+/// Lossy view of an argument, for keywords and numbers which are always ASCII.
+fn text(arg: &Bytes) -> String {
+    String::from_utf8_lossy(arg).into_owned()
+}
+
+/// Arguments of a single command, reporting Redis' arity error when a
+/// required one is missing.
+struct Args {
+    name: &'static str,
+    iter: std::vec::IntoIter<Bytes>,
+}
+
+impl Args {
+    fn required(&mut self) -> Result<Bytes, CommandError> {
+        let name = self.name;
+        self.iter
+            .next()
+            .ok_or_else(|| CommandError::WrongArity(name.to_string()))
+    }
+
+    fn optional(&mut self) -> Option<Bytes> {
+        self.iter.next()
+    }
+
+    fn integer(&mut self) -> Result<i64, CommandError> {
+        text(&self.required()?)
+            .parse()
+            .map_err(|_| CommandError::NotInteger)
+    }
+}
+
+/// Builds a command out of its arguments, once the command table has
+/// checked that it exists and got the right amount of them.
+fn scan_command(parts: Vec<Bytes>) -> Result<Command, CommandError> {
+    let spec = table::resolve(&parts)?;
+    let mut iter = parts.into_iter();
+    iter.next();
+    let mut args = Args {
+        name: spec.name,
+        iter,
+    };
+
+    let (command, sub) = spec.name.split_once('|').unwrap_or((spec.name, ""));
+    let command = match command {
+        "ping" => {
+            let message = args.optional();
+            if args.optional().is_some() {
+                return Err(CommandError::WrongArity(spec.name.to_string()));
             }
-            Command::Set(key, value, timeout)
+            Command::Ping(message)
         }
-        "ECHO" => {
-            let all: Vec<_> = args.collect();
-            Command::Echo(all.join(""))
+        "get" => Command::Get(args.required()?),
+        "set" => {
+            let key = args.required()?;
+            let value = args.required()?;
+            let mut options = SetOptions::default();
+            while let Some(opt) = args.optional() {
+                let opt = text(&opt).to_uppercase();
+                match opt.as_str() {
+                    "NX" if options.condition != Condition::IfExists => {
+                        options.condition = Condition::IfMissing
+                    }
+                    "XX" if options.condition != Condition::IfMissing => {
+                        options.condition = Condition::IfExists
+                    }
+                    "GET" => options.get = true,
+                    "KEEPTTL" if options.expiry.is_none() => options.expiry = Some(Expiry::Keep),
+                    "EX" | "PX" | "EXAT" | "PXAT" if options.expiry.is_none() => {
+                        let time = args.optional().ok_or(CommandError::Syntax)?;
+                        let time: i64 =
+                            text(&time).parse().map_err(|_| CommandError::NotInteger)?;
+                        // Also rejects what would overflow as milliseconds.
+                        let scale = if opt.starts_with('E') { 1000 } else { 1 };
+                        let ms = match time.checked_mul(scale) {
+                            Some(ms) if time > 0 => ms as u64,
+                            _ => return Err(CommandError::InvalidExpire("set".into())),
+                        };
+                        let ms = time::Duration::from_millis(ms);
+                        // Absolute deadlines are logged by the AOF, so replays keep them.
+                        options.expiry = Some(match opt.ends_with("AT") {
+                            true => Expiry::At(time::UNIX_EPOCH + ms),
+                            false => Expiry::In(ms),
+                        });
+                    }
+                    _ => return Err(CommandError::Syntax),
+                }
+            }
+            Command::Set(key, value, options)
         }
-        "CONFIG" => {
-            let all: Vec<_> = args.collect();
+        "echo" => Command::Echo(args.required()?),
+        "config" => {
+            let all: Vec<_> = args.iter.map(|a| text(&a)).collect();
+            let all: Vec<_> = all.iter().map(String::as_str).collect();
             let cmd = ConfigCmd::try_from(all.as_slice())?;
             Command::Config(cmd)
         }
-        "KEYS" => {
-            let all: Vec<_> = args.collect();
-            let op = all.first().context("op")?.to_string();
-            Command::Keys(op)
+        "keys" => Command::Keys(args.required()?),
+        "command" => {
+            let sub = args.optional().map(|s| text(&s).to_lowercase());
+            let rest = args.iter.collect();
+            let cmd = match sub.as_deref() {
+                None => CommandCmd::List,
+                Some("count") => CommandCmd::Count,
+                Some("info") => CommandCmd::Info(rest),
+                Some("docs") => CommandCmd::Docs(rest),
+                Some("getkeys") => CommandCmd::GetKeys(rest),
+                Some(other) => {
+                    let sub = other.to_string();
+                    return Err(CommandError::UnknownSubcommand(sub, "COMMAND".into()));
+                }
+            };
+            Command::Introspect(cmd)
+        }
+        "hello" => {
+            let mut hello = Hello::default();
+            if let Some(ver) = args.optional() {
+                let ver = text(&ver).parse().map_err(|_| {
+                    CommandError::Other("Protocol version is not an integer or out of range".into())
+                })?;
+                hello.protover = Some(ver);
+            }
+            while let Some(opt) = args.optional() {
+                let syntax = || {
+                    CommandError::Other(format!("Syntax error in HELLO option '{}'", text(&opt)))
+                };
+                match text(&opt).to_uppercase().as_str() {
+                    "AUTH" => {
+                        let user = args.optional().ok_or_else(syntax)?;
+                        let pass = args.optional().ok_or_else(syntax)?;
+                        hello.auth = Some((user, pass));
+                    }
+                    "SETNAME" => hello.setname = Some(args.optional().ok_or_else(syntax)?),
+                    _ => return Err(syntax()),
+                }
+            }
+            Command::Hello(hello)
+        }
+        "save" => Command::Save,
+        "bgsave" => {
+            // SCHEDULE is accepted for compatibility, saves are never deferred.
+            match args.optional().map(|o| text(&o).to_uppercase()) {
+                None => Command::BgSave,
+                Some(opt) if opt == "SCHEDULE" && args.optional().is_none() => Command::BgSave,
+                Some(_) => return Err(CommandError::Syntax),
+            }
+        }
+        "lastsave" => Command::LastSave,
+        "bgrewriteaof" => Command::BgRewriteAof,
+        "info" => Command::Info(args.iter.collect()),
+        "select" => Command::Select(args.integer()?),
+        "swapdb" => {
+            let index = |arg: Bytes, which: &str| {
+                text(&arg)
+                    .parse()
+                    .map_err(|_| CommandError::Other(format!("invalid {which} DB index")))
+            };
+            let first = index(args.required()?, "first")?;
+            let second = index(args.required()?, "second")?;
+            Command::SwapDb(first, second)
+        }
+        "move" => {
+            let key = args.required()?;
+            Command::Move(key, args.integer()?)
         }
-        _ => {
-            let all: Vec<_> = args.collect();
-            Command::Unknown(command, all.join(" "))
+        "flushdb" | "flushall" => {
+            let lazy = match args.optional().map(|o| text(&o).to_uppercase()) {
+                None => false,
+                Some(opt) if opt == "ASYNC" => true,
+                Some(opt) if opt == "SYNC" => false,
+                Some(_) => return Err(CommandError::Syntax),
+            };
+            if args.optional().is_some() {
+                return Err(CommandError::Syntax);
+            }
+            match command {
+                "flushdb" => Command::FlushDb(lazy),
+                _ => Command::FlushAll(lazy),
+            }
+        }
+        "del" => Command::Del(args.iter.collect()),
+        "dbsize" => Command::DbSize,
+        "type" => Command::Type(args.required()?),
+        "object" => {
+            // The command table already rejected unknown subcommands.
+            args.required()?;
+            let key = args.required()?;
+            Command::Object(match sub {
+                "freq" => ObjectCmd::Freq(key),
+                "idletime" => ObjectCmd::IdleTime(key),
+                _ => ObjectCmd::Encoding(key),
+            })
+        }
+        "expire" | "pexpire" | "expireat" | "pexpireat" => {
+            let key = args.required()?;
+            let time = args.integer()?;
+            // Seconds that would overflow as milliseconds are rejected.
+            let scale = if command.starts_with('p') { 1 } else { 1000 };
+            let ms = time
+                .checked_mul(scale)
+                .ok_or_else(|| CommandError::InvalidExpire(command.into()))?;
+            let timeout = match command.ends_with("at") {
+                true => Timeout::At(ms),
+                false => Timeout::In(ms),
+            };
+            let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+            while let Some(opt) = args.optional() {
+                match text(&opt).to_uppercase().as_str() {
+                    "NX" => nx = true,
+                    "XX" => xx = true,
+                    "GT" => gt = true,
+                    "LT" => lt = true,
+                    _ => {
+                        let msg = format!("Unsupported option {}", text(&opt));
+                        return Err(CommandError::Other(msg));
+                    }
+                }
+            }
+            if nx && (xx || gt || lt) {
+                let msg = "NX and XX, GT or LT options at the same time are not compatible";
+                return Err(CommandError::Other(msg.into()));
+            }
+            if gt && lt {
+                let msg = "GT and LT options at the same time are not compatible";
+                return Err(CommandError::Other(msg.into()));
+            }
+            let options = ExpireOptions {
+                condition: match (nx, xx) {
+                    (true, _) => Condition::IfMissing,
+                    (_, true) => Condition::IfExists,
+                    _ => Condition::Always,
+                },
+                compare: match (gt, lt) {
+                    (true, _) => Some(Ordering::Greater),
+                    (_, true) => Some(Ordering::Less),
+                    _ => None,
+                },
+            };
+            Command::Expire(key, timeout, options)
         }
+        "ttl" | "pttl" => Command::Ttl(args.required()?, command == "pttl"),
+        "expiretime" | "pexpiretime" => {
+            Command::ExpireTime(args.required()?, command == "pexpiretime")
+        }
+        "persist" => Command::Persist(args.required()?),
+        other => return Err(CommandError::unknown_command(other, &[])),
     };
 
     Ok(command)
@@ -58,35 +297,285 @@ fn scan_command<'a>(parts: &mut impl Iterator<Item = &'a str>) -> Result<Command
 mod tests {
     use super::*;
 
+    fn scan_all(bytes: &[u8]) -> Vec<Command> {
+        let mut buffer = BytesMut::from(bytes);
+        let cmds = scan(&mut buffer).unwrap();
+        assert!(buffer.is_empty());
+        cmds.into_iter().map(Result::unwrap).collect()
+    }
+
     #[test]
     fn test_scan() {
         const ECHO: &str = "*2\r\n$4\r\necho\r\n$12\r\ntoma mensaje\r\n";
-        let cmd = scan(ECHO);
-        assert_eq!(*cmd, [Command::Echo("toma mensaje".to_string())]);
+        let cmd = scan_all(ECHO.as_bytes());
+        assert_eq!(*cmd, [Command::Echo("toma mensaje".into())]);
     }
 
     #[test]
     fn test_scan_set() {
         const SET: &str = "*3\r\n$3\r\nSET\r\n$5\r\nmykey\r\n$5\r\nHello\r\n";
-        let cmd = scan(SET);
+        let cmd = scan_all(SET.as_bytes());
+        let options = SetOptions::default();
         assert_eq!(
             *cmd,
-            [Command::Set("mykey".to_string(), "Hello".to_string(), None)]
+            [Command::Set("mykey".into(), "Hello".into(), options)]
         );
     }
 
     #[test]
     fn test_scan_set_timeout() {
         const SET: &str =
-            "*2\r\n$3\r\nSET\r\n$5\r\nmykey\r\n$5\r\nHello\r\n$2\r\nPX\r\n$3\r\n100\r\n";
-        let cmd = scan(SET);
+            "*5\r\n$3\r\nSET\r\n$5\r\nmykey\r\n$5\r\nHello\r\n$2\r\nPX\r\n$3\r\n100\r\n";
+        let cmd = scan_all(SET.as_bytes());
         assert_eq!(
             *cmd,
             [Command::Set(
-                "mykey".to_string(),
-                "Hello".to_string(),
-                Some(time::Duration::from_millis(100))
+                "mykey".into(),
+                "Hello".into(),
+                SetOptions {
+                    expiry: Some(Expiry::In(time::Duration::from_millis(100))),
+                    ..Default::default()
+                }
             )]
         );
     }
+
+    #[test]
+    fn test_scan_set_options() {
+        let cmds = scan_all(b"SET k v nx get EX 2\r\nSET k v XX KEEPTTL\r\nSET k v PXAT 1500\r\n");
+        let options: Vec<_> = cmds
+            .into_iter()
+            .map(|cmd| match cmd {
+                Command::Set(_, _, options) => options,
+                other => panic!("Expected a SET, got {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            options,
+            [
+                SetOptions {
+                    expiry: Some(Expiry::In(time::Duration::from_secs(2))),
+                    condition: Condition::IfMissing,
+                    get: true,
+                },
+                SetOptions {
+                    expiry: Some(Expiry::Keep),
+                    condition: Condition::IfExists,
+                    get: false,
+                },
+                SetOptions {
+                    expiry: Some(Expiry::At(
+                        time::UNIX_EPOCH + time::Duration::from_millis(1500)
+                    )),
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let invalid = CommandError::InvalidExpire("set".into());
+        let mut buffer = BytesMut::from(
+            &b"SET k v NX XX\r\nSET k v EX 1 KEEPTTL\r\nSET k v PX 1 EXAT 2\r\nSET k v EX\r\nSET k v EX 0\r\nSET k v EXAT -1\r\nSET k v EX 9223372036854775807\r\nSET k v EX 1.5\r\nSET k v NX NX\r\n"[..],
+        );
+        assert_eq!(
+            scan(&mut buffer).unwrap(),
+            [
+                Err(CommandError::Syntax),
+                Err(CommandError::Syntax),
+                Err(CommandError::Syntax),
+                Err(CommandError::Syntax),
+                Err(invalid.clone()),
+                Err(invalid.clone()),
+                Err(invalid),
+                Err(CommandError::NotInteger),
+                Ok(Command::Set(
+                    "k".into(),
+                    "v".into(),
+                    SetOptions {
+                        condition: Condition::IfMissing,
+                        ..Default::default()
+                    }
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_expire() {
+        let cmds = scan_all(
+            b"EXPIRE k 10 xx GT\r\nPEXPIREAT k -5\r\nEXPIREAT k 2 NX\r\nPTTL k\r\nEXPIRETIME k\r\nPERSIST k\r\n",
+        );
+        let gt = ExpireOptions {
+            condition: Condition::IfExists,
+            compare: Some(Ordering::Greater),
+        };
+        let nx = ExpireOptions {
+            condition: Condition::IfMissing,
+            compare: None,
+        };
+        assert_eq!(
+            cmds,
+            [
+                Command::Expire("k".into(), Timeout::In(10_000), gt),
+                Command::Expire("k".into(), Timeout::At(-5), ExpireOptions::default()),
+                Command::Expire("k".into(), Timeout::At(2000), nx),
+                Command::Ttl("k".into(), true),
+                Command::ExpireTime("k".into(), false),
+                Command::Persist("k".into()),
+            ]
+        );
+
+        let mut buffer = BytesMut::from(
+            &b"EXPIRE k 1 NX LT\r\nEXPIRE k 1 GT LT\r\nEXPIRE k 1 ASAP\r\nEXPIRE k soon\r\nEXPIRE k 9223372036854775807\r\n"[..],
+        );
+        let other = |msg: &str| Err(CommandError::Other(msg.into()));
+        assert_eq!(
+            scan(&mut buffer).unwrap(),
+            [
+                other("NX and XX, GT or LT options at the same time are not compatible"),
+                other("GT and LT options at the same time are not compatible"),
+                other("Unsupported option ASAP"),
+                Err(CommandError::NotInteger),
+                Err(CommandError::InvalidExpire("expire".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_command_names() {
+        let cmds = scan_all(
+            b"PING\r\nSET k v\r\nGET k\r\nOBJECT FREQ k\r\nEXPIRE k 1\r\nPEXPIREAT k 1\r\nTTL k\r\n",
+        );
+        let names: Vec<_> = cmds.iter().map(Command::name).collect();
+        assert_eq!(
+            names,
+            [
+                "ping",
+                "set",
+                "get",
+                "object",
+                "pexpire",
+                "pexpireat",
+                "ttl"
+            ]
+        );
+        let denyoom: Vec<_> = cmds.iter().map(Command::is_denyoom).collect();
+        assert_eq!(denyoom, [false, true, false, false, false, false, false]);
+    }
+
+    #[test]
+    fn test_scan_binary_safe() {
+        const ECHO: &[u8] = b"*2\r\n$4\r\nECHO\r\n$6\r\n$a\r\nb\n\r\n*1\r\n$4\r\nPING\r\n";
+        let cmd = scan_all(ECHO);
+        assert_eq!(
+            *cmd,
+            [Command::Echo("$a\r\nb\n".into()), Command::Ping(None)]
+        );
+    }
+
+    #[test]
+    fn test_scan_split_frame() {
+        const ECHO: &[u8] = b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n";
+        let mut buffer = BytesMut::from(&ECHO[..10]);
+        assert!(scan(&mut buffer).unwrap().is_empty());
+        assert_eq!(buffer.len(), 10);
+
+        buffer.extend_from_slice(&ECHO[10..]);
+        let cmd = scan(&mut buffer).unwrap();
+        assert_eq!(cmd.len(), 1);
+        assert_eq!(cmd[0].as_ref().unwrap(), &Command::Echo("hello".into()));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_scan_hello() {
+        const HELLO: &[u8] =
+            b"*6\r\n$5\r\nhello\r\n$1\r\n3\r\n$4\r\nAUTH\r\n$7\r\ndefault\r\n$3\r\npwd\r\n$7\r\nSETNAME\r\n";
+        let mut buffer = BytesMut::from(HELLO);
+        let cmd = scan(&mut buffer).unwrap();
+        assert!(cmd[0].is_err());
+
+        let cmd = scan_all(b"*4\r\n$5\r\nHELLO\r\n$1\r\n3\r\n$7\r\nSETNAME\r\n$3\r\ncli\r\n");
+        let hello = Hello {
+            protover: Some(3),
+            auth: None,
+            setname: Some("cli".into()),
+        };
+        assert_eq!(*cmd, [Command::Hello(hello)]);
+    }
+
+    #[test]
+    fn test_scan_inline() {
+        let cmd = scan_all(b"PING\r\n\r\nset k \"a b\\r\\n\"\necho 'it\\'s'\r\nPING hello\r\n");
+        assert_eq!(
+            *cmd,
+            [
+                Command::Ping(None),
+                Command::Set("k".into(), "a b\r\n".into(), SetOptions::default()),
+                Command::Echo("it's".into()),
+                Command::Ping(Some("hello".into())),
+            ]
+        );
+
+        let mut buffer = BytesMut::from(&b"GET \"unterminated\r\n"[..]);
+        assert_eq!(
+            scan(&mut buffer).unwrap_err(),
+            decode::ProtoError::UnbalancedQuotes
+        );
+    }
+
+    #[test]
+    fn test_scan_databases() {
+        let cmds = scan_all(
+            b"*2\r\n$6\r\nSELECT\r\n$1\r\n2\r\n*3\r\n$4\r\nMOVE\r\n$1\r\nk\r\n$1\r\n1\r\n*3\r\n$3\r\nDEL\r\n$1\r\na\r\n$1\r\nb\r\n*2\r\n$7\r\nFLUSHDB\r\n$5\r\nasync\r\n*1\r\n$8\r\nFLUSHALL\r\n",
+        );
+        assert_eq!(
+            cmds,
+            [
+                Command::Select(2),
+                Command::Move("k".into(), 1),
+                Command::Del(vec!["a".into(), "b".into()]),
+                Command::FlushDb(true),
+                Command::FlushAll(false),
+            ]
+        );
+        let cmds =
+            scan_all(b"TYPE k\r\nobject ENCODING k\r\nOBJECT freq k\r\nOBJECT IDLETIME k\r\n");
+        assert_eq!(
+            cmds,
+            [
+                Command::Type("k".into()),
+                Command::Object(ObjectCmd::Encoding("k".into())),
+                Command::Object(ObjectCmd::Freq("k".into())),
+                Command::Object(ObjectCmd::IdleTime("k".into())),
+            ]
+        );
+        let mut buffer = BytesMut::from(&b"SWAPDB a 1\r\nFLUSHDB NOW\r\nSELECT x\r\n"[..]);
+        assert_eq!(
+            scan(&mut buffer).unwrap(),
+            [
+                Err(CommandError::Other("invalid first DB index".into())),
+                Err(CommandError::Syntax),
+                Err(CommandError::NotInteger),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_errors() {
+        let mut buffer = BytesMut::from(
+            &b"GET\r\nGET a b\r\nSET k v EX 10 PX 5\r\nSET k v PX abc\r\nfoo a\r\nPING a b\r\n"[..],
+        );
+        let cmd = scan(&mut buffer).unwrap();
+        assert_eq!(
+            cmd,
+            [
+                Err(CommandError::WrongArity("get".into())),
+                Err(CommandError::WrongArity("get".into())),
+                Err(CommandError::Syntax),
+                Err(CommandError::NotInteger),
+                Err(CommandError::UnknownCommand("foo".into(), "'a' ".into())),
+                Err(CommandError::WrongArity("ping".into())),
+            ]
+        );
+    }
 }
@@ -1,30 +1,53 @@
 use crate::{
+    proto::ErrorKind,
     redis::Redis,
     response::{Builder, Response},
     scanner,
+    session::Session,
 };
 use anyhow::Result;
 use bytes::BytesMut;
-use std::time;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time,
+};
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
 };
 
+const READ_CHUNK: usize = 16 * 1024;
+const CRON_PERIOD: time::Duration = time::Duration::from_millis(100);
+
 pub struct Server {
     redis: Redis,
+    next_client_id: AtomicU64,
 }
 
 impl Server {
     pub fn new(redis: Redis) -> Self {
-        Self { redis }
+        Self {
+            redis,
+            next_client_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Runs the background jobs of the server, like automatic snapshots.
+    pub async fn cron(&self) {
+        let mut interval = tokio::time::interval(CRON_PERIOD);
+        loop {
+            interval.tick().await;
+            self.redis.cron();
+        }
     }
 
     pub async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
         let mut stream = stream;
+        let mut buffer = BytesMut::with_capacity(READ_CHUNK);
+        let mut session = Session::new(self.next_client_id.fetch_add(1, Ordering::Relaxed));
 
         loop {
-            let mut buffer = BytesMut::with_capacity(1024);
+            buffer.reserve(READ_CHUNK);
             let read_size = stream.read_buf(&mut buffer).await?;
             if read_size == 0 {
                 println!("Empty message, shutting down connection.");
@@ -32,18 +55,31 @@ impl Server {
                 return Ok(());
             }
             let now = time::Instant::now();
-            let frame = std::str::from_utf8(&buffer)?;
-            let commands = scanner::scan(frame);
+            let commands = match scanner::scan(&mut buffer) {
+                Ok(commands) => commands,
+                Err(e) => {
+                    let resp = Response::error(ErrorKind::Err, &e.to_string());
+                    stream.write_all(&resp.encode(session.protocol)).await?;
+                    stream.shutdown().await?;
+                    return Err(e.into());
+                }
+            };
 
-            if commands.is_empty() {
-                let resp = &Response::error("No supported command found");
-                stream.write_all(resp.into()).await?;
-                continue;
+            for command in commands {
+                let response = match command {
+                    Ok(command) => self.redis.handle(&mut session, &command, now),
+                    Err(e) => e.into(),
+                };
+                stream.write_all(&response.encode(session.protocol)).await?;
             }
 
-            let responses = commands.iter().filter_map(|c| self.redis.handle(c, now));
-            for response in responses {
-                stream.write_all((&response).into()).await?;
+            let limit = self.redis.config().client_query_buffer_limit;
+            if buffer.len() > limit {
+                stream.shutdown().await?;
+                anyhow::bail!(
+                    "Closing client, query buffer of {} bytes exceeds client-query-buffer-limit of {limit}",
+                    buffer.len()
+                );
             }
         }
     }
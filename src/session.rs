@@ -0,0 +1,21 @@
+use crate::proto::Protocol;
+use bytes::Bytes;
+
+/// State a single client connection carries between commands.
+#[derive(Debug, Default)]
+pub struct Session {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub name: Option<Bytes>,
+    /// Index of the database selected with `SELECT`.
+    pub db: usize,
+}
+
+impl Session {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+}